@@ -0,0 +1,18 @@
+use std::env::var;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BookSettings {
+    // Opening book loaded at startup
+    pub path: Option<String>,
+    // Finished games are stored here and added to the book
+    pub games: Option<String>,
+}
+
+impl Default for BookSettings {
+    fn default() -> Self {
+        Self {
+            path: var("BOOK_PATH").ok(),
+            games: var("BOOK_GAMES").ok(),
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use std::env::var;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BotSettings {
+    // Seconds a public room waits for a second player before a bot is offered
+    pub offer_after: u64,
+}
+
+impl Default for BotSettings {
+    fn default() -> Self {
+        Self {
+            offer_after: var("BOT_OFFER_AFTER")
+                .unwrap_or("60".to_string())
+                .parse().unwrap(),
+        }
+    }
+}
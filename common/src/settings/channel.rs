@@ -0,0 +1,52 @@
+use std::env::var;
+
+// What happens to a message for a session whose queue is full
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowConsumer {
+    // Lobby updates are dropped, the client can list the rooms again, anything else
+    // disconnects it
+    DropLobby,
+    Disconnect,
+}
+
+impl std::str::FromStr for SlowConsumer {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop_lobby" => Ok(Self::DropLobby),
+            "disconnect" => Ok(Self::Disconnect),
+            _ => Err(Self::Err::new(
+                std::io::ErrorKind::NotFound,
+                "slow consumer policy not recognized",
+            )),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ChannelSettings {
+    // Messages waiting to be written to each socket
+    pub session_capacity: usize,
+    // Requests of every session waiting to be handled
+    pub command_capacity: usize,
+    pub slow_consumer: SlowConsumer,
+}
+
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        Self {
+            session_capacity: var("CHANNEL_SESSION_CAPACITY")
+                .unwrap_or("64".to_string())
+                .parse().unwrap(),
+            command_capacity: var("CHANNEL_COMMAND_CAPACITY")
+                .unwrap_or("1024".to_string())
+                .parse().unwrap(),
+            slow_consumer: var("CHANNEL_SLOW_CONSUMER")
+                .unwrap_or("drop_lobby".to_string())
+                .parse().unwrap(),
+        }
+    }
+}
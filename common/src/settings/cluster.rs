@@ -0,0 +1,24 @@
+use std::env::var;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ClusterSettings {
+    // `redis://[:password@]host:port[/db]` shared with the other nodes, which makes this one
+    // node of many. Rooms and the lobby stay in this process when unset
+    pub redis_url: Option<String>,
+    // Name of this node among the others, random if unset
+    pub node: Option<String>,
+    // Of the channels and keys used on the server
+    pub prefix: String,
+}
+
+impl Default for ClusterSettings {
+    fn default() -> Self {
+        Self {
+            redis_url: var("CLUSTER_REDIS_URL").ok(),
+            node: var("CLUSTER_NODE").ok(),
+            prefix: var("CLUSTER_PREFIX")
+                .unwrap_or("tictactoe".to_string()),
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::env::var;
+
+// permessage-deflate (RFC 7692)
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct DeflateSettings {
+    pub enabled: bool,
+    // Compression level, 0 to 9
+    pub level: u32,
+    // Messages smaller than this many bytes are sent uncompressed
+    pub min_size: usize,
+    // Start every message from an empty window instead of reusing the previous ones,
+    // saves memory per connection at the cost of ratio
+    pub server_no_context_takeover: bool,
+    pub client_no_context_takeover: bool,
+}
+
+impl Default for DeflateSettings {
+    fn default() -> Self {
+        Self {
+            enabled: var("DEFLATE_ENABLED")
+                .unwrap_or("true".to_string())
+                .parse().unwrap(),
+            level: var("DEFLATE_LEVEL")
+                .unwrap_or("6".to_string())
+                .parse().unwrap(),
+            min_size: var("DEFLATE_MIN_SIZE")
+                .unwrap_or("64".to_string())
+                .parse().unwrap(),
+            server_no_context_takeover: var("DEFLATE_SERVER_NO_CONTEXT_TAKEOVER")
+                .unwrap_or("false".to_string())
+                .parse().unwrap(),
+            client_no_context_takeover: var("DEFLATE_CLIENT_NO_CONTEXT_TAKEOVER")
+                .unwrap_or("false".to_string())
+                .parse().unwrap(),
+        }
+    }
+}
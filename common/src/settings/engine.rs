@@ -0,0 +1,12 @@
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct EngineSettings {
+    // Name players use to pick this engine
+    pub name: String,
+    pub command: String,
+    pub args: Vec<String>,
+    // Milliseconds the engine is told to think on each move
+    pub move_time: u64,
+    // Milliseconds the engine has to finish its handshake, and on top of `move_time` to answer
+    // each move, before it forfeits its seat
+    pub timeout: u64,
+}
@@ -0,0 +1,39 @@
+use std::env::var;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct HandshakeSettings {
+    // Seconds a connection has to complete the TLS and HTTP handshakes
+    pub timeout: u64,
+    // Bytes of the request line and headers together
+    pub max_header_size: usize,
+    pub max_headers: usize,
+    // Origins browsers may connect from, any if empty
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for HandshakeSettings {
+    fn default() -> Self {
+        Self {
+            timeout: var("HANDSHAKE_TIMEOUT")
+                .unwrap_or("10".to_string())
+                .parse().unwrap(),
+            max_header_size: var("HANDSHAKE_MAX_HEADER_SIZE")
+                .unwrap_or("8192".to_string())
+                .parse().unwrap(),
+            max_headers: var("HANDSHAKE_MAX_HEADERS")
+                .unwrap_or("64".to_string())
+                .parse().unwrap(),
+            allowed_origins: var("HANDSHAKE_ALLOWED_ORIGINS")
+                .map(|origins| {
+                    origins
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|origin| !origin.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
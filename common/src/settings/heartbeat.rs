@@ -0,0 +1,23 @@
+use std::env::var;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct HeartbeatSettings {
+    // Seconds between pings, also how often rooms tell players their latency
+    pub interval: u64,
+    // Seconds without a pong before the connection is closed
+    pub timeout: u64,
+}
+
+impl Default for HeartbeatSettings {
+    fn default() -> Self {
+        Self {
+            interval: var("HEARTBEAT_INTERVAL")
+                .unwrap_or("20".to_string())
+                .parse().unwrap(),
+            timeout: var("HEARTBEAT_TIMEOUT")
+                .unwrap_or("45".to_string())
+                .parse().unwrap(),
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use std::env::var;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct LimitSettings {
+    // WebSocket connections open at once, more are refused with 503
+    pub max_connections: usize,
+    // Of those, from the same source address, more are refused with 429
+    pub max_connections_per_ip: usize,
+}
+
+impl Default for LimitSettings {
+    fn default() -> Self {
+        Self {
+            max_connections: var("LIMIT_MAX_CONNECTIONS")
+                .unwrap_or("10000".to_string())
+                .parse().unwrap(),
+            max_connections_per_ip: var("LIMIT_MAX_CONNECTIONS_PER_IP")
+                .unwrap_or("32".to_string())
+                .parse().unwrap(),
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use std::env::var;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct MessageSettings {
+    // Bytes of an incoming frame, after inflating, over it the connection is closed with 1009
+    pub max_size: usize,
+}
+
+impl Default for MessageSettings {
+    fn default() -> Self {
+        Self {
+            max_size: var("MESSAGE_MAX_SIZE")
+                .unwrap_or("4096".to_string())
+                .parse().unwrap(),
+        }
+    }
+}
@@ -2,11 +2,53 @@ use std::str::FromStr;
 
 use crate::entities::system::Environment;
 
+pub mod book;
+pub mod bot;
+pub mod channel;
+pub mod cluster;
+pub mod deflate;
+pub mod engine;
+pub mod handshake;
+pub mod heartbeat;
+pub mod limit;
+pub mod message;
+pub mod rate;
+pub mod room;
+pub mod shutdown;
+pub mod tls;
 pub mod web;
 
 #[derive(serde::Serialize, serde::Deserialize, Default)]
 pub struct AppSettings {
     pub websocket: web::WebSocketSettings,
+    #[serde(default)]
+    pub engines: Vec<engine::EngineSettings>,
+    #[serde(default)]
+    pub bots: bot::BotSettings,
+    #[serde(default)]
+    pub book: book::BookSettings,
+    #[serde(default)]
+    pub deflate: deflate::DeflateSettings,
+    #[serde(default)]
+    pub rooms: room::RoomSettings,
+    #[serde(default)]
+    pub tls: tls::TlsSettings,
+    #[serde(default)]
+    pub handshake: handshake::HandshakeSettings,
+    #[serde(default)]
+    pub shutdown: shutdown::ShutdownSettings,
+    #[serde(default)]
+    pub channels: channel::ChannelSettings,
+    #[serde(default)]
+    pub limits: limit::LimitSettings,
+    #[serde(default)]
+    pub rates: rate::RateSettings,
+    #[serde(default)]
+    pub messages: message::MessageSettings,
+    #[serde(default)]
+    pub cluster: cluster::ClusterSettings,
+    #[serde(default)]
+    pub heartbeat: heartbeat::HeartbeatSettings,
     #[serde(skip)]
     pub environment: Environment,
 }
@@ -17,6 +59,11 @@ pub trait Protocol {
     fn get_port(&self) -> &u16;
 
     fn socket(&self) -> String {
+        // IPv6 addresses are bracketed before the port
+        if self.get_host().contains(':') && !self.get_host().starts_with('[') {
+            return format!("[{}]:{}", self.get_host(), self.get_port());
+        }
+
         format!("{}:{}", self.get_host(), self.get_port())
     }
 }
@@ -46,6 +93,20 @@ impl AppSettings {
 
         Self {
             websocket: partial.websocket,
+            engines: partial.engines,
+            bots: partial.bots,
+            book: partial.book,
+            deflate: partial.deflate,
+            rooms: partial.rooms,
+            tls: partial.tls,
+            handshake: partial.handshake,
+            shutdown: partial.shutdown,
+            channels: partial.channels,
+            limits: partial.limits,
+            rates: partial.rates,
+            messages: partial.messages,
+            cluster: partial.cluster,
+            heartbeat: partial.heartbeat,
             environment: env
         }
     }
@@ -58,6 +119,20 @@ impl AppSettings {
                 // Read configs from env or use default
                 Self {
                     websocket: Default::default(),
+                    engines: Vec::new(),
+                    bots: Default::default(),
+                    book: Default::default(),
+                    deflate: Default::default(),
+                    rooms: Default::default(),
+                    tls: Default::default(),
+                    handshake: Default::default(),
+                    shutdown: Default::default(),
+                    channels: Default::default(),
+                    limits: Default::default(),
+                    rates: Default::default(),
+                    messages: Default::default(),
+                    cluster: Default::default(),
+                    heartbeat: Default::default(),
                     environment: env,
                 }
             }
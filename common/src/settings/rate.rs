@@ -0,0 +1,33 @@
+use std::env::var;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct RateSettings {
+    // Frames a client may send per second, in bursts of as many
+    pub messages: u32,
+    // Room lists a client may ask for per minute
+    pub list_rooms: u32,
+    // Rooms a client may create per minute
+    pub create_room: u32,
+    // Requests refused in a row before the client is disconnected
+    pub strikes: u32,
+}
+
+impl Default for RateSettings {
+    fn default() -> Self {
+        Self {
+            messages: var("RATE_MESSAGES")
+                .unwrap_or("20".to_string())
+                .parse().unwrap(),
+            list_rooms: var("RATE_LIST_ROOMS")
+                .unwrap_or("30".to_string())
+                .parse().unwrap(),
+            create_room: var("RATE_CREATE_ROOM")
+                .unwrap_or("6".to_string())
+                .parse().unwrap(),
+            strikes: var("RATE_STRIKES")
+                .unwrap_or("10".to_string())
+                .parse().unwrap(),
+        }
+    }
+}
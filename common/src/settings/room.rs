@@ -0,0 +1,17 @@
+use std::env::var;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RoomSettings {
+    // Seconds a player has to move before being disconnected
+    pub turn_timeout: u64,
+}
+
+impl Default for RoomSettings {
+    fn default() -> Self {
+        Self {
+            turn_timeout: var("ROOM_TURN_TIMEOUT")
+                .unwrap_or("30".to_string())
+                .parse().unwrap(),
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use std::env::var;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ShutdownSettings {
+    // Seconds games in progress get to finish once the server is asked to stop
+    pub deadline: u64,
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        Self {
+            deadline: var("SHUTDOWN_DEADLINE")
+                .unwrap_or("60".to_string())
+                .parse().unwrap(),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::env::var;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuth {
+    None,
+    // Clients may present a certificate, which must then be valid
+    Optional,
+    Required,
+}
+
+impl std::str::FromStr for ClientAuth {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "optional" => Ok(Self::Optional),
+            "required" => Ok(Self::Required),
+            _ => Err(Self::Err::new(
+                std::io::ErrorKind::NotFound,
+                "client auth not recognized",
+            )),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct TlsSettings {
+    // PEM certificate chain, connections are served over TLS when set along with the key
+    pub cert: Option<String>,
+    // PEM private key
+    pub key: Option<String>,
+    pub client_auth: ClientAuth,
+    // PEM roots client certificates are verified against, required unless client auth is none
+    pub client_ca: Option<String>,
+    // Seconds between checks of the files for a new certificate
+    pub reload_interval: u64,
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        Self {
+            cert: var("TLS_CERT").ok(),
+            key: var("TLS_KEY").ok(),
+            client_auth: var("TLS_CLIENT_AUTH")
+                .unwrap_or("none".to_string())
+                .parse().unwrap(),
+            client_ca: var("TLS_CLIENT_CA").ok(),
+            reload_interval: var("TLS_RELOAD_INTERVAL")
+                .unwrap_or("60".to_string())
+                .parse().unwrap(),
+        }
+    }
+}
+
+impl TlsSettings {
+    pub fn enabled(&self) -> bool {
+        self.cert.is_some() && self.key.is_some()
+    }
+}
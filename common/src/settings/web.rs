@@ -3,7 +3,13 @@ use std::env::var;
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct WebSocketSettings {
     pub host: String,
-    pub port: u16
+    pub port: u16,
+    // More `host:port` addresses to listen on, like `[::]:9002` for IPv6
+    #[serde(default)]
+    pub listen: Vec<String>,
+    // Unix domain socket to listen on as well, for a reverse proxy on the same machine
+    #[serde(default)]
+    pub unix: Option<String>,
 }
 
 impl Default for WebSocketSettings {
@@ -14,6 +20,14 @@ impl Default for WebSocketSettings {
             port: var("WEBSOCKET_PORT")
                 .unwrap_or("9002".to_string())
                 .parse().unwrap(),
+            listen: var("WEBSOCKET_LISTEN")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|addr| !addr.is_empty())
+                .map(String::from)
+                .collect(),
+            unix: var("WEBSOCKET_UNIX").ok(),
         }
     }
 }
@@ -0,0 +1,122 @@
+// TLS configuration of the server, built from the PEM files in the settings
+
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{danger::ClientCertVerifier, WebPkiClientVerifier};
+use rustls::{RootCertStore, ServerConfig};
+
+use common::settings::tls::{ClientAuth, TlsSettings};
+
+pub struct Certificates {
+    settings: TlsSettings,
+    config: Arc<ServerConfig>,
+    // Modification time of each file the config was built from, a file replaced by an
+    // older one counts as changed too
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl Certificates {
+    pub fn load(settings: &TlsSettings) -> std::io::Result<Self> {
+        let modified = modified(settings);
+
+        Ok(Self {
+            settings: settings.clone(),
+            config: server_config(settings)?,
+            modified,
+        })
+    }
+
+    // Config for the next connection, the ones already accepted keep theirs
+    pub fn config(&self) -> Arc<ServerConfig> {
+        self.config.clone()
+    }
+
+    // Rebuilds the config if any of the files changed, the current one stays in use when
+    // the new files can't be loaded
+    pub fn reload(&mut self) -> std::io::Result<bool> {
+        let modified = modified(&self.settings);
+
+        if modified == self.modified {
+            return Ok(false);
+        }
+
+        self.config = server_config(&self.settings)?;
+        self.modified = modified;
+
+        Ok(true)
+    }
+}
+
+pub fn server_config(settings: &TlsSettings) -> std::io::Result<Arc<ServerConfig>> {
+    let (cert, key) = match (&settings.cert, &settings.key) {
+        (Some(cert), Some(key)) => (cert, key),
+        _ => return Err(Error::new(ErrorKind::NotFound, "certificate and key are required")),
+    };
+
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{cert}: {e}")))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{key}: {e}")))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let mut config = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid)?
+        .with_client_cert_verifier(client_verifier(settings, provider)?)
+        .with_single_cert(certs, key)
+        .map_err(invalid)?;
+
+    // Upgrades only happen over HTTP/1.1
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Arc::new(config))
+}
+
+fn client_verifier(
+    settings: &TlsSettings,
+    provider: Arc<CryptoProvider>,
+) -> std::io::Result<Arc<dyn ClientCertVerifier>> {
+    if settings.client_auth == ClientAuth::None {
+        return Ok(WebPkiClientVerifier::no_client_auth());
+    }
+
+    // Public roots vouch for servers, so any site's certificate would pass as a client's
+    let Some(path) = &settings.client_ca else {
+        return Err(Error::new(ErrorKind::NotFound, "client CA is required for client auth"));
+    };
+
+    let mut roots = RootCertStore::empty();
+
+    for cert in CertificateDer::pem_file_iter(path).map_err(invalid)? {
+        roots.add(cert.map_err(invalid)?).map_err(invalid)?;
+    }
+
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+
+    let verifier = if settings.client_auth == ClientAuth::Optional {
+        verifier.allow_unauthenticated()
+    } else {
+        verifier
+    };
+
+    verifier.build().map_err(invalid)
+}
+
+fn modified(settings: &TlsSettings) -> Vec<Option<SystemTime>> {
+    [&settings.cert, &settings.key, &settings.client_ca]
+        .into_iter()
+        .flatten()
+        .map(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
+
+fn invalid(e: impl std::error::Error + Send + Sync + 'static) -> Error {
+    Error::new(ErrorKind::InvalidData, e)
+}
@@ -0,0 +1,102 @@
+// WebSocket to the server, or Server-Sent Events with posts when a proxy breaks WebSockets.
+// Either way it dispatches `open`, `message` and `error` events and sends the same JSON
+
+import type { LegacyMessage, LegacyRequest } from "./protocol";
+
+export class Connection extends EventTarget {
+    private ws: WebSocket | null = null;
+    private token: string | null = null;
+    private events: EventSource | null = null;
+
+    constructor(private host: string) {
+        super();
+
+        const ws = new WebSocket(`wss://${host}/`);
+        let opened = false;
+
+        ws.addEventListener("open", () => {
+            opened = true;
+            this.ws = ws;
+            this.dispatchEvent(new Event("open"));
+        });
+        ws.addEventListener("message", (event) => this.receive(event.data));
+        // Never got through, try the events instead
+        ws.addEventListener("error", () => {
+            if (!opened) {
+                this.stream();
+            }
+        });
+    }
+
+    private stream() {
+        const events = new EventSource(`https://${this.host}/events`);
+        this.events = events;
+
+        events.addEventListener("session", (event) => {
+            this.token = JSON.parse(event.data).token;
+            this.dispatchEvent(new Event("open"));
+        });
+        events.addEventListener("message", (event) => this.receive(event.data));
+        // Closed by the server, reconnecting would take a new seat
+        events.addEventListener("close", () => events.close());
+    }
+
+    // The server forgot the stream, a new one starts over in the lobby
+    private reconnect() {
+        this.events?.close();
+        this.token = null;
+        this.stream();
+    }
+
+    private async post(data: string) {
+        let response: Response;
+
+        try {
+            response = await fetch(`https://${this.host}/events/${this.token}`, {
+                method: "POST",
+                headers: { "Content-Type": "application/json" },
+                body: data,
+            });
+        } catch {
+            this.fail("Connection lost, reconnecting");
+            this.reconnect();
+            return;
+        }
+
+        if (!response.ok) {
+            const reason = await response.text().catch(() => "");
+            this.fail(reason || `Request failed with status ${response.status}`);
+
+            if (response.status == 404) {
+                this.reconnect();
+            }
+        }
+    }
+
+    private fail(message: string) {
+        this.dispatchEvent(new CustomEvent("error", { detail: message }));
+    }
+
+    private receive(data: string) {
+        const message: LegacyMessage = JSON.parse(data);
+        this.dispatchEvent(new CustomEvent("message", { detail: message }));
+    }
+
+    onMessage(listener: (message: LegacyMessage) => void) {
+        this.addEventListener("message", (event) => listener((event as CustomEvent<LegacyMessage>).detail));
+    }
+
+    onError(listener: (message: string) => void) {
+        this.addEventListener("error", (event) => listener((event as CustomEvent<string>).detail));
+    }
+
+    send(request: LegacyRequest) {
+        const data = JSON.stringify(request);
+
+        if (this.ws) {
+            this.ws.send(data);
+        } else if (this.token) {
+            this.post(data);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+// Generated by `cargo run -p websocket --features schema -- --protocol <dir>`, do not edit
+
+export const TAGGED_PROTOCOL = "tictactoe.json";
+export const MSGPACK_PROTOCOL = "tictactoe.msgpack";
+
+export type Request = { id?: number, } & ({ "type": "mark_position" } & MarkPosition | { "type": "join_room" } & JoinRoom | { "type": "leave_room" } | { "type": "create_room" } & CreateRoom | { "type": "delete_room" } & RoomId | { "type": "list_rooms" } | { "type": "play_again" } | { "type": "add_engine" } & AddEngine | { "type": "add_bot" } & AddBot | { "type": "book_moves" } | { "type": "get_room_state" });
+
+export type ClientMessage = { "type": "mark_position" } & MarkPosition | { "type": "join_room" } & JoinRoom | { "type": "leave_room" } | { "type": "create_room" } & CreateRoom | { "type": "delete_room" } & RoomId | { "type": "list_rooms" } | { "type": "play_again" } | { "type": "add_engine" } & AddEngine | { "type": "add_bot" } & AddBot | { "type": "book_moves" } | { "type": "get_room_state" };
+
+export type ServerMessage = { "type": "mark_position" } & MarkPosition | { "type": "end_room" } & EndRoom | { "type": "opponent_joined" } & Opponent | { "type": "opponent_left" } | { "type": "room_joined" } & RoomId | { "type": "room_left" } & RoomId | { "type": "room_created" } & RoomEntry | { "type": "room_updated" } & RoomEntry | { "type": "room_deleted" } & RoomId | { "type": "room_list" } & RoomList | { "type": "owner_code" } & OwnerCode | { "type": "bot_offer" } & BotOffer | { "type": "book_moves" } & BookMoves | { "type": "room_state" } & RoomState | { "type": "shutting_down" } & ShuttingDown | { "type": "latency" } & Latency | { "type": "ack" } & Ack | { "type": "error" } & ErrorMessage;
+
+export type MarkPosition = { position: number, };
+
+export type JoinRoom = { player_name: string, room_id: number, room_code: string | null, };
+
+export type CreateRoom = { player_name: string, public: boolean, rules?: string, };
+
+export type RoomId = { id: number, };
+
+export type AddEngine = { engine: string, };
+
+export type AddBot = { difficulty: Difficulty, };
+
+export type EndRoom = { status: number, };
+
+export type Opponent = { id: number, name: string, };
+
+export type RoomEntry = { id: number, player_name: string, players_amount: number, public: boolean, bot: boolean, rules?: string, };
+
+export type RoomList = { parties: Array<RoomEntry>, };
+
+export type OwnerCode = { code: string, };
+
+export type BotOffer = { levels: Array<Difficulty>, };
+
+export type BookMoves = { moves: Array<BookMove>, };
+
+export type BookMove = { position: number, weight: number, };
+
+export type RoomState = { id: number, board: Array<string | null>, turn: number, you: number, player1: string | null, player2: string | null, turn_time_left: number | null, moves: Array<number>, seq: number, latency: Latency, };
+
+export type ShuttingDown = { deadline: number, };
+
+export type Latency = { player1: number | null, player2: number | null, };
+
+export type Ack = { id: number, opcode: number, request: string, };
+
+export type ErrorMessage = { code: ErrorCode, message: string, id: number | null, opcode: number | null, request: string | null, };
+
+export type ErrorCode = "invalid_message" | "invalid_name" | "already_in_room" | "not_in_room" | "room_not_found" | "room_full" | "wrong_room_code" | "not_room_owner" | "no_opponent" | "invalid_position" | "not_your_turn" | "position_taken" | "engine_not_found" | "engine_unavailable" | "bot_not_offered" | "rate_limited" | "cluster_unavailable" | "unknown_rules";
+
+export type Difficulty = "easy" | "medium" | "hard";
+
+export const REQUEST_OPCODES = {
+    "mark_position": 10,
+    "join_room": 12,
+    "leave_room": 14,
+    "create_room": 15,
+    "delete_room": 16,
+    "list_rooms": 17,
+    "play_again": 22,
+    "add_engine": 23,
+    "add_bot": 25,
+    "book_moves": 27,
+    "get_room_state": 29,
+} as const satisfies Record<ClientMessage["type"], number>;
+
+export const MESSAGE_OPCODES = {
+    "mark_position": 10,
+    "end_room": 11,
+    "opponent_joined": 13,
+    "room_joined": 13,
+    "opponent_left": 14,
+    "room_list": 17,
+    "room_created": 18,
+    "room_deleted": 19,
+    "room_left": 20,
+    "owner_code": 21,
+    "bot_offer": 24,
+    "room_updated": 26,
+    "book_moves": 27,
+    "ack": 28,
+    "room_state": 29,
+    "shutting_down": 30,
+    "latency": 31,
+    "error": 1007,
+} as const satisfies Record<ServerMessage["type"], number>;
+
+// Fields only the legacy frames carry
+type LegacyNulls = {
+    "room_joined": { name: null, },
+};
+
+// Fields of a message besides its name, `null` when it has none
+type Payload<M> = keyof Omit<M, "type"> extends never ? null : Omit<M, "type">;
+
+export type LegacyRequest = { [K in ClientMessage["type"]]: { opcode: (typeof REQUEST_OPCODES)[K], d: Payload<Extract<ClientMessage, { "type": K }>>, id?: number, } }[ClientMessage["type"]];
+
+export type LegacyMessage = { [K in ServerMessage["type"]]: { opcode: (typeof MESSAGE_OPCODES)[K], d: Payload<Extract<ServerMessage, { "type": K }>> & (K extends keyof LegacyNulls ? LegacyNulls[K] : unknown), seq?: number, } }[ServerMessage["type"]];
@@ -0,0 +1,158 @@
+// Opening book, one position per line:
+//
+//   <hash> <position>:<weight> <position>:<weight> ...
+//
+// The hash reads the board as a base 3 number (empty 0, X 1, O 2) with square 0 as the
+// lowest digit. Lines starting with `#` are comments.
+//
+// Finished games come from the storage, as the positions played in order, X first.
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+
+use rand::Rng;
+
+use common::settings::book::BookSettings;
+
+use crate::json::BookMove;
+
+#[derive(Default, Debug)]
+pub struct Book {
+    positions: HashMap<u32, HashMap<usize, u32>>,
+}
+
+pub fn hash(tray: &[char; 9]) -> u32 {
+    tray.iter().rev().fold(0, |hash, square| {
+        hash * 3
+            + match square {
+                'X' => 1,
+                'O' => 2,
+                _ => 0,
+            }
+    })
+}
+
+impl Book {
+    pub fn load(path: &std::path::Path) -> std::io::Result<Self> {
+        let mut book = Self::default();
+
+        for line in std::fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let hash = parse(fields.next())?;
+
+            for field in fields {
+                let (position, weight) = field.split_once(':').ok_or(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    "book move must be <position>:<weight>",
+                ))?;
+
+                let position = parse(Some(position))?;
+
+                if position > 8 {
+                    return Err(std::io::Error::new(ErrorKind::InvalidData, "book move out of the board"));
+                }
+
+                book.add(hash, position, parse(Some(weight))?);
+            }
+        }
+
+        Ok(book)
+    }
+
+    pub fn add_game(&mut self, moves: &[usize]) {
+        let mut tray = [' '; 9];
+        // Checked to the end before any of it goes in
+        let mut replies = Vec::with_capacity(moves.len());
+
+        for (ply, &position) in moves.iter().enumerate() {
+            if position > 8 || tray[position] != ' ' {
+                log::error!("skipping invalid game {moves:?}");
+                return;
+            }
+
+            replies.push((hash(&tray), position));
+            tray[position] = if ply % 2 == 0 { 'X' } else { 'O' };
+        }
+
+        for (hash, position) in replies {
+            self.add(hash, position, 1);
+        }
+    }
+
+    fn add(&mut self, hash: u32, position: usize, weight: u32) {
+        *self
+            .positions
+            .entry(hash)
+            .or_default()
+            .entry(position)
+            .or_default() += weight;
+    }
+
+    // Known replies for a position, most played first
+    pub fn moves(&self, tray: &[char; 9]) -> Vec<BookMove> {
+        let mut moves: Vec<BookMove> = self
+            .positions
+            .get(&hash(tray))
+            .into_iter()
+            .flatten()
+            .filter(|(position, _)| tray[**position] == ' ')
+            .map(|(&position, &weight)| BookMove { position, weight })
+            .collect();
+
+        moves.sort_by(|a, b| b.weight.cmp(&a.weight).then(a.position.cmp(&b.position)));
+        moves
+    }
+
+    // Weighted random pick among the known replies
+    pub fn pick(&self, tray: &[char; 9]) -> Option<usize> {
+        let moves = self.moves(tray);
+        let total: u32 = moves.iter().map(|book_move| book_move.weight).sum();
+
+        if total == 0 {
+            return None;
+        }
+
+        let mut roll = rand::thread_rng().gen_range(0..total);
+
+        for book_move in moves {
+            if roll < book_move.weight {
+                return Some(book_move.position);
+            }
+
+            roll -= book_move.weight;
+        }
+
+        None
+    }
+}
+
+pub fn from_settings(settings: &BookSettings, storage: &dyn crate::storage::Storage) -> Book {
+    let mut book = match &settings.path {
+        Some(path) => Book::load(std::path::Path::new(path)).unwrap_or_else(|e| {
+            log::error!("failed to load opening book {path} {e}");
+            Book::default()
+        }),
+        None => Book::default(),
+    };
+
+    match storage.load_games() {
+        Ok(games) => games.iter().for_each(|moves| book.add_game(moves)),
+        Err(e) => log::error!("failed to load stored games {e}"),
+    }
+
+    log::info!("Opening book has {} positions", book.positions.len());
+
+    book
+}
+
+fn parse<T: std::str::FromStr>(field: Option<&str>) -> std::io::Result<T> {
+    field
+        .and_then(|field| field.parse().ok())
+        .ok_or(std::io::Error::new(ErrorKind::InvalidData, "invalid book entry"))
+}
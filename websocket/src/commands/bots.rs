@@ -0,0 +1,31 @@
+use crate::{
+    json::{Difficulty, ErrorCode, ErrorMessage, ServerMessage},
+    server::room::{Context, Room},
+};
+
+pub fn add(
+    addr: std::net::SocketAddr,
+    difficulty: Difficulty,
+    room: &mut Room,
+    context: &Context,
+) -> Result<(), ErrorMessage> {
+    // Only rooms that were offered a bot can take one
+    if !room.bot_offered {
+        return Err(ErrorMessage::new(ErrorCode::BotNotOffered, "room was not offered a bot"));
+    }
+
+    if !room.is_available() || room.bot.is_some() {
+        return Err(ErrorMessage::new(ErrorCode::RoomFull, "room is full"));
+    }
+
+    let session = crate::seat::bot::spawn(difficulty, context.book.clone(), &context.mailbox);
+    log::info!("[{addr}] {difficulty:?} bot took a seat");
+
+    room.bot = Some(session.addr);
+    super::users::join_room(session.addr, session, room);
+
+    let entry = room.entry(context.id);
+    context.update(entry.clone(), ServerMessage::RoomUpdated(entry));
+
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use tokio::sync::mpsc::error::SendError;
+
+use crate::{
+    json::{Command, ErrorCode, ErrorMessage, Outgoing, RoomId, ServerMessage},
+    server::{cluster, cluster::Envelope, lobby::Host, lobby::Lobby, lobby::RoomHandle, send_command},
+};
+
+// Request of a session seated in a room of another node
+pub async fn forward(room: usize, node: String, command: Command, lobby: &mut Lobby) {
+    let envelope = match command {
+        Command::Message { session, id, message } => Envelope::Message {
+            room,
+            home: lobby.cluster.node.clone(),
+            addr: session.addr,
+            id,
+            message,
+        },
+        Command::RemoveUser { addr } => {
+            lobby.members.remove(&addr);
+            lobby.away.remove(&addr);
+
+            Envelope::RemoveUser { room, home: lobby.cluster.node.clone(), addr }
+        }
+        Command::Forfeit { addr } => {
+            lobby.members.remove(&addr);
+            lobby.away.remove(&addr);
+
+            Envelope::Forfeit { room, home: lobby.cluster.node.clone(), addr }
+        }
+        _ => return,
+    };
+
+    lobby.cluster.send(&node, envelope).await;
+}
+
+pub async fn handle(envelope: Envelope, lobby: &mut Lobby) {
+    match envelope {
+        Envelope::Hello { node } => {
+            for (id, room, _) in lobby.local_rooms() {
+                let envelope = Envelope::Room {
+                    node: lobby.cluster.node.clone(),
+                    entry: room.entry.clone(),
+                    code: room.code.clone(),
+                    message: None,
+                };
+
+                log::trace!("telling {node} about room {id}");
+                lobby.cluster.send(&node, envelope).await;
+            }
+        }
+        Envelope::Room { node, entry, code, message } => {
+            // Closed on its node
+            if let Some(ServerMessage::RoomDeleted(_)) = message {
+                lobby.rooms.remove(&entry.id);
+            } else {
+                lobby.rooms.insert(entry.id, RoomHandle {
+                    host: Host::Remote(node),
+                    entry,
+                    code,
+                });
+            }
+
+            if let Some(message) = message {
+                super::notify_connections(message, &lobby.queue);
+            }
+        }
+        Envelope::Bye { node } => {
+            log::info!("Node {node} left the cluster");
+
+            let gone: Vec<usize> = lobby
+                .rooms
+                .iter()
+                .filter(|(_, room)| matches!(&room.host, Host::Remote(host) if *host == node))
+                .map(|(id, _)| *id)
+                .collect();
+
+            // Players seated there are back in the lobby, learning the room is gone with it
+            let seated: Vec<_> = lobby
+                .away
+                .keys()
+                .filter(|addr| lobby.members.get(addr).is_some_and(|room| gone.contains(room)))
+                .copied()
+                .collect();
+
+            for addr in seated {
+                let session = lobby.away.remove(&addr).unwrap();
+                session.send_event(ServerMessage::OpponentLeft);
+                super::users::enter(session, lobby).await;
+            }
+
+            for id in gone {
+                lobby.rooms.remove(&id);
+                super::notify_connections(ServerMessage::RoomDeleted(RoomId { id }), &lobby.queue);
+            }
+        }
+        Envelope::Seat { room, home, addr, name, protocol, id, request } => {
+            let session = lobby.cluster.guest(home.clone(), addr, name, protocol, &lobby.settings, &mut lobby.tasks);
+
+            let Some(Host::Local(mailbox)) = lobby.rooms.get(&room).map(|room| &room.host) else {
+                // Gone with its node, or never here
+                super::respond(
+                    &session,
+                    id,
+                    &request,
+                    Err(ErrorMessage::new(ErrorCode::RoomNotFound, "room not found")),
+                );
+                lobby.cluster.send(&home, Envelope::Enter { addr }).await;
+
+                return;
+            };
+
+            lobby.guests.insert((home.clone(), addr), session.clone());
+
+            // Closed since
+            if let Err(SendError(Command::Seat { session, id, request })) =
+                mailbox.send(Command::Seat { session, id, request }).await
+            {
+                lobby.guests.remove(&(home.clone(), addr));
+                super::respond(
+                    &session,
+                    id,
+                    &request,
+                    Err(ErrorMessage::new(ErrorCode::RoomNotFound, "room not found")),
+                );
+                lobby.cluster.send(&home, Envelope::Enter { addr }).await;
+            }
+        }
+        Envelope::Message { room, home, addr, id, message } => {
+            let (Some(session), Some(Host::Local(mailbox))) =
+                (lobby.guests.get(&(home, addr)), lobby.rooms.get(&room).map(|room| &room.host))
+            else {
+                return;
+            };
+
+            send_command(mailbox, Command::Message { session: session.clone(), id, message }).await;
+        }
+        Envelope::RemoveUser { room, home, addr } | Envelope::Forfeit { room, home, addr }
+            if !lobby.guests.contains_key(&(home.clone(), addr)) =>
+        {
+            log::trace!("[{addr}] of {home} not a guest of room {room}");
+        }
+        Envelope::RemoveUser { room, home, addr } => {
+            let session = lobby.guests.remove(&(home, addr)).unwrap();
+
+            if let Some(Host::Local(mailbox)) = lobby.rooms.get(&room).map(|room| &room.host) {
+                send_command(mailbox, Command::RemoveUser { addr: session.addr }).await;
+            }
+        }
+        Envelope::Forfeit { room, home, addr } => {
+            let session = lobby.guests.remove(&(home, addr)).unwrap();
+
+            if let Some(Host::Local(mailbox)) = lobby.rooms.get(&room).map(|room| &room.host) {
+                send_command(mailbox, Command::Forfeit { addr: session.addr }).await;
+            }
+        }
+        Envelope::Outgoing { addr, seq, message } => {
+            let Some(session) = lobby.away.get(&addr) else {
+                return;
+            };
+
+            match seq {
+                Some(seq) => session.send(Outgoing::Event { seq, message }),
+                None => session.send(message),
+            }
+        }
+        Envelope::Close { addr, code, reason } => {
+            if let Some(session) = lobby.away.get(&addr) {
+                session.send(Outgoing::Close {
+                    code: code.into(),
+                    reason: cluster::close_reason(&reason),
+                });
+            }
+        }
+        Envelope::Enter { addr } => {
+            if let Some(session) = lobby.away.remove(&addr) {
+                super::users::enter(session, lobby).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::{
+    json::{ErrorCode, ErrorMessage, RoomId, ServerMessage},
+    server::room::{Context, Room},
+};
+
+pub fn add(
+    addr: std::net::SocketAddr,
+    engine: String,
+    room: &mut Room,
+    context: &Context,
+) -> Result<(), ErrorMessage> {
+    if !room.is_available() {
+        return Err(ErrorMessage::new(ErrorCode::RoomFull, "room is full"));
+    }
+
+    let settings = match context.settings.engines.iter().find(|settings| settings.name == engine) {
+        Some(value) => value,
+        None => return Err(ErrorMessage::new(ErrorCode::EngineNotFound, "engine not found")),
+    };
+
+    let session = match crate::seat::engine::spawn(settings, &context.mailbox) {
+        Ok(value) => value,
+        Err(e) => {
+            log::error!("[{addr}] failed to start engine {engine} {e}");
+
+            return Err(ErrorMessage::new(ErrorCode::EngineUnavailable, "engine unavailable"));
+        }
+    };
+
+    log::info!("[{addr}] engine {engine} took a seat");
+    super::users::join_room(session.addr, session, room);
+
+    context
+        .update(room.entry(context.id), ServerMessage::RoomJoined(RoomId { id: context.id }));
+
+    Ok(())
+}
@@ -1,30 +1,22 @@
 use crate::{
-    json::{EventData, SocketRequest},
-    server::{room::Room, send_message},
+    hooks::GameEvent,
+    json::{BookMoves, EndRoom, ErrorCode, ErrorMessage, MarkPosition, ServerMessage},
+    rules::Outcome,
+    server::room::{Context, Room},
 };
 
 pub fn position(
     addr: std::net::SocketAddr,
     position: usize,
-    rooms: &mut Vec<Room>,
-) {
-    let idx = match rooms.iter_mut().position(|room| room.find_player(addr)) {
-        Some(value) => value,
-        None => return,
-    };
-
-    let room = &mut rooms[idx];
+    room: &mut Room,
+    context: &Context,
+) -> Result<(), ErrorMessage> {
+    if room.is_available() {
+        return Err(ErrorMessage::new(ErrorCode::NoOpponent, "nobody to play against"));
+    }
 
     let is_player1 = crate::server::room::is_player(&room.player1, addr);
-    if let Err(e) = room.mark_position(is_player1, position) {
-        if is_player1 {
-            send_message(&room.player1.as_ref().unwrap().frame, e);
-        } else {
-            send_message(&room.player2.as_ref().unwrap().frame, e);
-        }
-
-        return;
-    }
+    room.mark_position(is_player1, position)?;
 
     let player = if is_player1 {
         room.player2.as_ref().unwrap()
@@ -32,52 +24,109 @@ pub fn position(
         room.player1.as_ref().unwrap()
     };
 
-    send_message(
-        &player.frame,
-        SocketRequest {
-            opcode: 10,
-            d: Some(EventData::MarkPosition { position }),
-        },
-    );
+    player.send_event(ServerMessage::MarkPosition(MarkPosition { position }));
     room.refresh_turn();
     log::trace!("[{addr}] received mark in {position} position");
 
-    let request = if room.is_win() {
-        SocketRequest {
-            opcode: 11,
-            d: Some(EventData::EndRoom {
-                status: if is_player1 { 1 } else { 2 },
-            }),
-        }
-    } else if room.is_full() {
-        SocketRequest {
-            opcode: 11,
-            d: Some(EventData::EndRoom { status: 3 }),
-        }
-    } else {
-        return;
+    let status = match room.outcome(if is_player1 { 1 } else { 2 }) {
+        Some(Outcome::Win(player)) => player,
+        Some(Outcome::Draw) => 3,
+        None => return Ok(()),
     };
 
     log::info!("Room ended with status!");
-    room.reply_event(request);
 
-    let room: Room = rooms.remove(idx);
-    rooms.insert(idx, room.reset());
+    context.book.write().unwrap().add_game(&room.moves);
+
+    if let Err(e) = context.services.storage.store_game(&room.moves) {
+        log::error!("failed to store finished game {e}");
+    }
+
+    context.services.hooks.emit(GameEvent::GameEnded {
+        room: context.id,
+        moves: room.moves.clone(),
+        status,
+    });
+
+    room.reply_event(ServerMessage::EndRoom(EndRoom { status }));
+    room.reset();
+
+    Ok(())
 }
 
-pub fn play_again(
-    addr: std::net::SocketAddr,
-    rooms: &mut Vec<Room>,
-) {
-    let idx = match rooms.iter_mut().position(|room| room.find_player(addr)) {
-        Some(value) => value,
-        None => return,
-    };
+pub fn play_again(room: &mut Room) -> Result<(), ErrorMessage> {
+    if room.is_available() {
+        return Err(ErrorMessage::new(ErrorCode::NoOpponent, "nobody to play against"));
+    }
 
-    let room = &mut rooms[idx];
     room.duration_turn = Some(std::time::Instant::now());
-    room.player1_turn = !room.player1_turn;
+    // Joined tells player 1 to start, keep the room in sync with it
+    room.player1_turn = true;
     room.tray = [' '; 9];
+    room.moves.clear();
 
     super::users::notify_joined(false, room.player2.as_ref().unwrap(), room.player1.as_ref());
-}
\ No newline at end of file
+
+    Ok(())
+}
+
+pub fn forfeit(addr: std::net::SocketAddr, room: &mut Room, context: &Context) {
+    let is_player1 = crate::server::room::is_player(&room.player1, addr);
+    let player = if is_player1 {
+        &room.player2
+    } else {
+        &room.player1
+    };
+
+    let status = if is_player1 { 2 } else { 1 };
+
+    if let Some(player) = player {
+        player.send_event(ServerMessage::EndRoom(EndRoom { status }));
+    }
+
+    context.services.hooks.emit(GameEvent::GameEnded {
+        room: context.id,
+        moves: room.moves.clone(),
+        status,
+    });
+
+    log::info!("[{addr}] forfeited the match");
+    room.reset();
+
+    super::users::remove(addr, room, context);
+}
+
+pub fn book_moves(
+    addr: std::net::SocketAddr,
+    room: &Room,
+    book: &std::sync::Arc<std::sync::RwLock<crate::book::Book>>,
+) -> Result<(), ErrorMessage> {
+    let player = if crate::server::room::is_player(&room.player1, addr) {
+        room.player1.as_ref().unwrap()
+    } else {
+        room.player2.as_ref().unwrap()
+    };
+
+    player.send(
+        ServerMessage::BookMoves(BookMoves {
+            moves: book.read().unwrap().moves(&room.tray),
+        }),
+    );
+
+    Ok(())
+}
+
+pub fn room_state(
+    addr: std::net::SocketAddr,
+    room: &Room,
+    context: &Context,
+) -> Result<(), ErrorMessage> {
+    let player = room.get_player(addr).unwrap();
+    let turn_timeout = std::time::Duration::from_secs(context.settings.rooms.turn_timeout);
+
+    player.send(
+        ServerMessage::RoomState(Box::new(room.state(context.id, player, turn_timeout))),
+    );
+
+    Ok(())
+}
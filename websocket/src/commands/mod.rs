@@ -1,50 +1,242 @@
-use crate::json::{Command, EventData};
+use crate::json::{
+    Ack, AddBot, AddEngine, ClientMessage, Command, CreateRoom, ErrorCode, ErrorMessage, JoinRoom,
+    MarkPosition, Outgoing, RoomId, ServerMessage, ShuttingDown,
+};
+use crate::server::{cluster::Envelope, lobby::Host, lobby::Lobby, room::Room, room::Context, send_command, send_lobby, session::SocketSession};
 
+mod bots;
+mod cluster;
+mod engines;
 mod game;
 pub mod rooms;
 pub mod users;
 
-pub fn handle(
-    command: Command,
-    rooms: &mut Vec<crate::server::room::Room>,
-    queue: &mut Vec<crate::server::session::SocketSession>,
-) {
+pub async fn lobby(command: Command, lobby: &mut Lobby) {
+    // Sent before the client learned it's in a room
+    if let Some(room) = lobby.seated(&command) {
+        match &lobby.rooms[&room].host {
+            Host::Local(mailbox) => send_command(mailbox, command).await,
+            Host::Remote(node) => cluster::forward(room, node.clone(), command, lobby).await,
+        }
+
+        return;
+    }
+
     match command {
-        Command::JoinUser {
-            addr,
-            data:
-                EventData::JoinRoom {
+        Command::Message { session, id, message } => {
+            let addr = session.addr;
+
+            // Disconnected meanwhile
+            if !lobby.queue.contains_key(&addr) {
+                return;
+            }
+
+            let request = message.clone();
+
+            let result = match message {
+                ClientMessage::JoinRoom(JoinRoom {
                     player_name,
                     room_id,
                     room_code,
-                },
-        } => users::join(addr, player_name, room_id, room_code, rooms, queue),
-        Command::RemoveUser { addr } => users::remove(addr, rooms, queue),
-        Command::MarkPosition {
-            addr,
-            data: EventData::MarkPosition { position },
-        } => game::position(addr, position, rooms),
-        Command::CreateRoom {
-            addr,
-            data:
-                EventData::CreateRoom {
+                }) => {
+                    // Answered by the room
+                    match users::join(addr, player_name, room_id, room_code, id, request.clone(), lobby).await {
+                        Ok(()) => return,
+                        Err(error) => Err(error),
+                    }
+                }
+                ClientMessage::CreateRoom(CreateRoom {
                     player_name,
                     public,
-                },
-        } => rooms::create(addr, player_name, public, rooms, queue),
-        Command::DeleteRoom { addr, id } => rooms::delete(addr, id, rooms, queue),
-        Command::ListRooms { addr } => rooms::list(addr, rooms, queue),
-        Command::PlayAgain { addr } => game::play_again(addr, rooms),
-        Command::LeaveRoom { addr } => rooms::leave(addr, rooms, queue),
-        _ => {}
+                    rules,
+                }) => rooms::create(addr, player_name, public, rules, lobby).await,
+                ClientMessage::DeleteRoom(RoomId { id }) => rooms::delete_from_lobby(id, lobby),
+                ClientMessage::ListRooms => rooms::list(&session, lobby),
+                ClientMessage::MarkPosition(_)
+                | ClientMessage::LeaveRoom
+                | ClientMessage::PlayAgain
+                | ClientMessage::AddEngine(_)
+                | ClientMessage::AddBot(_)
+                | ClientMessage::BookMoves
+                | ClientMessage::GetRoomState => {
+                    Err(ErrorMessage::new(ErrorCode::NotInRoom, "you are not in a room"))
+                }
+            };
+
+            respond(&session, id, &request, result);
+        }
+        Command::RemoveUser { addr } | Command::Forfeit { addr } => users::remove_from_lobby(addr, lobby),
+        Command::Enter { session } => users::enter(session, lobby).await,
+        // No room to take it
+        Command::Seat { session, .. } => users::enter(session, lobby).await,
+        Command::Left { addr } => {
+            lobby.members.remove(&addr);
+        }
+        Command::Update { entry, message } => {
+            let room = lobby.rooms.get_mut(&entry.id).unwrap();
+            let code = room.code.clone();
+
+            // Sent once, as the room closes
+            if let ServerMessage::RoomDeleted(_) = message {
+                lobby.rooms.remove(&entry.id);
+            } else {
+                room.entry = entry.clone();
+            }
+
+            lobby
+                .cluster
+                .broadcast(Envelope::Room {
+                    node: lobby.cluster.node.clone(),
+                    entry,
+                    code,
+                    message: Some(message.clone()),
+                })
+                .await;
+
+            notify_connections(message, &lobby.queue);
+        }
+        Command::Shutdown { deadline } => {
+            let message = ServerMessage::ShuttingDown(ShuttingDown { deadline });
+            notify_connections(message.clone(), &lobby.queue);
+            notify_connections(message, &lobby.away);
+
+            for (_, _, mailbox) in lobby.local_rooms() {
+                send_command(mailbox, Command::Shutdown { deadline }).await;
+            }
+        }
+        Command::Drain { expired } => {
+            close(lobby.queue.values());
+
+            // Games of other nodes don't end with this one
+            if expired {
+                close(lobby.away.values());
+            }
+
+            for (_, _, mailbox) in lobby.local_rooms() {
+                send_command(mailbox, Command::Drain { expired }).await;
+            }
+        }
+        Command::Cluster(envelope) => cluster::handle(envelope, lobby).await,
+    }
+}
+
+pub fn room(command: Command, room: &mut Room, context: &Context) {
+    // Sent before the client learned it's back in the lobby
+    if is_stray(&command, room, context) {
+        send_lobby(&context.lobby, command);
+
+        return;
+    }
+
+    match command {
+        Command::Message { session, id, message } => {
+            let addr = session.addr;
+            let request = message.clone();
+
+            let result = match message {
+                ClientMessage::MarkPosition(MarkPosition { position }) => {
+                    game::position(addr, position, room, context)
+                }
+                ClientMessage::LeaveRoom => rooms::leave(addr, room, context),
+                ClientMessage::DeleteRoom(_) => rooms::delete(addr, room, context),
+                ClientMessage::PlayAgain => game::play_again(room),
+                ClientMessage::AddEngine(AddEngine { engine }) => {
+                    engines::add(addr, engine, room, context)
+                }
+                ClientMessage::AddBot(AddBot { difficulty }) => {
+                    bots::add(addr, difficulty, room, context)
+                }
+                ClientMessage::BookMoves => game::book_moves(addr, room, &context.book),
+                ClientMessage::GetRoomState => game::room_state(addr, room, context),
+                ClientMessage::JoinRoom(_) | ClientMessage::CreateRoom(_) | ClientMessage::ListRooms => {
+                    Err(ErrorMessage::new(ErrorCode::AlreadyInRoom, "you are already in a room"))
+                }
+            };
+
+            respond(&session, id, &request, result);
+        }
+        Command::Seat { session, id, request } => users::seat(session, id, request, room, context),
+        Command::RemoveUser { addr } => users::remove(addr, room, context),
+        Command::Forfeit { addr } => game::forfeit(addr, room, context),
+        Command::Shutdown { deadline } => {
+            for session in room.sessions() {
+                session.send(ServerMessage::ShuttingDown(ShuttingDown { deadline }));
+            }
+        }
+        Command::Drain { expired } => {
+            if expired || room.players_amount() < 2 || !room.is_started() {
+                close(room.sessions());
+            }
+        }
+        // Only sent to the lobby
+        Command::Enter { .. } | Command::Left { .. } | Command::Update { .. } | Command::Cluster(_) => {}
+    }
+}
+
+// Reached a room as it closed
+pub fn closed(command: Command, context: &Context) {
+    match command {
+        Command::Seat { session, id, request } => {
+            respond(&session, id, &request, Err(ErrorMessage::new(ErrorCode::RoomNotFound, "room not found")));
+            send_lobby(&context.lobby, Command::Enter { session });
+        }
+        command => send_lobby(&context.lobby, command),
+    }
+}
+
+// Commands of clients no longer in the room, and deletions of other rooms
+fn is_stray(command: &Command, room: &Room, context: &Context) -> bool {
+    match command {
+        Command::Message { session, message, .. } => {
+            !room.find_player(session.addr)
+                || matches!(message, ClientMessage::DeleteRoom(RoomId { id }) if *id != context.id)
+        }
+        Command::RemoveUser { addr } | Command::Forfeit { addr } => !room.find_player(*addr),
+        _ => false,
     }
 }
 
 pub fn notify_connections(
-    event: crate::json::SocketRequest,
-    queue: &mut Vec<crate::server::session::SocketSession>,
+    event: crate::json::ServerMessage,
+    queue: &std::collections::HashMap<std::net::SocketAddr, SocketSession>,
+) {
+    for connection in queue.values() {
+        connection.send(event.clone())
+    }
+}
+
+// Acks and rejections go back to whoever sent the request, in the lobby or in a room
+fn respond(
+    session: &SocketSession,
+    id: Option<u64>,
+    request: &ClientMessage,
+    result: Result<(), ErrorMessage>,
 ) {
-    for connection in queue.iter() {
-        crate::server::send_message(&connection.frame, event.clone())
+    match (result, id) {
+        (Err(error), id) => {
+            log::trace!("[{}] rejected {}: {}", session.addr, request.kind(), error.message);
+            session.send(error.request(id, request));
+        }
+        (Ok(()), Some(id)) => session.send(ServerMessage::Ack(Ack {
+            id,
+            opcode: request.opcode(),
+            request: request.kind().to_string(),
+        })),
+        (Ok(()), None) => {}
+    }
+}
+
+// Asks the sessions to hang up, server is shutting down
+fn close<'a>(sessions: impl Iterator<Item = &'a SocketSession>) {
+    let close = Outgoing::Close {
+        code: web_socket::CloseCode::Away,
+        reason: "server shutting down",
+    };
+
+    for session in sessions {
+        // Already closing
+        if !session.frame.is_closed() {
+            session.send(close.clone());
+        }
     }
 }
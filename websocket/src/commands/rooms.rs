@@ -1,150 +1,157 @@
 use crate::{
-    json::{EventData, SocketRequest},
-    server::{room::Room, send_message, session::SocketSession},
+    hooks::GameEvent,
+    json::{Command, ErrorCode, ErrorMessage, OwnerCode, Outgoing, RoomId, RoomList, ServerMessage},
+    server::{cluster::Envelope, lobby::Lobby, room::Context, room::Room, send_lobby, session::SocketSession},
 };
 
-pub fn create(
+pub async fn create(
     addr: std::net::SocketAddr,
     player_name: String,
     public: bool,
-    rooms: &mut Vec<Room>,
-    queue: &mut Vec<SocketSession>,
-) {
-    log::info!("Creating room for {player_name}");
-    
-    let mut session = super::users::get_session(addr, queue).unwrap();
+    rules: Option<String>,
+    lobby: &mut Lobby,
+) -> Result<(), ErrorMessage> {
+    super::users::check_name(&player_name)?;
 
-    if session.name.is_none() || session.name.as_ref() != Some(&player_name) {
-        session.name = Some(player_name.clone());
+    let rules = match lobby.services.rules.get(rules.as_deref()) {
+        Some(value) => value,
+        None => return Err(ErrorMessage::new(ErrorCode::UnknownRules, "unknown rules")),
     };
 
-    let room_id = rooms.len();
-    let code = generate_room_code(public);
+    if !lobby.queue.contains_key(&addr) {
+        return Err(ErrorMessage::new(ErrorCode::AlreadyInRoom, "you are already in a room"));
+    }
+
+    // Unique across the nodes
+    let room_id = lobby.cluster.next_room_id().await.map_err(|e| {
+        log::error!("failed to get a room id {e}");
+
+        ErrorMessage::new(ErrorCode::ClusterUnavailable, "failed to create the room")
+    })?;
+
+    let mut session = match lobby.queue.remove(&addr) {
+        Some(value) => value,
+        None => return Err(ErrorMessage::new(ErrorCode::AlreadyInRoom, "you are already in a room")),
+    };
 
-    let room = Room::new(None, None, code.clone(), player_name.clone());
-    rooms.push(room);
-    let room = &mut rooms[room_id];
+    log::info!("Creating room for {player_name}");
+
+    session.name = Some(player_name.clone());
+    let player = session.clone();
 
-    super::users::join_room(addr, session, room);
+    let code = generate_room_code(public);
+
+    let mut room = Room::new(None, None, code.clone(), player_name.clone(), rules);
+    super::users::join_room(addr, session, &mut room);
 
     if let Some(code) = code {
-        send_message(
-            &room.player1.as_ref().unwrap().frame,
-            SocketRequest::new(21, Some(EventData::OwnerCode { code })),
-        )
+        player.send(ServerMessage::OwnerCode(OwnerCode { code }))
     }
 
-    super::notify_connections(
-        SocketRequest::new(
-            18,
-            Some(EventData::RoomCreated {
-                id: room_id as u8,
-                player_name,
-                players_amount: 1,
-                public,
-            }),
-        ),
-        queue,
-    )
+    let entry = room.entry(room_id);
+    let mailbox = lobby.spawn(room_id, room);
+    lobby.members.insert(addr, room_id);
+    player.send(Outgoing::Route(Some(mailbox)));
+
+    lobby
+        .cluster
+        .broadcast(Envelope::Room {
+            node: lobby.cluster.node.clone(),
+            entry: entry.clone(),
+            code: lobby.rooms[&room_id].code.clone(),
+            message: Some(ServerMessage::RoomCreated(entry.clone())),
+        })
+        .await;
+
+    lobby.services.hooks.emit(GameEvent::RoomCreated {
+        room: room_id,
+        owner: player_name,
+        public,
+    });
+
+    super::notify_connections(ServerMessage::RoomCreated(entry), &lobby.queue);
+
+    Ok(())
 }
 
 pub fn leave(
     addr: std::net::SocketAddr,
-    rooms: &mut Vec<Room>,
-    queue: &mut Vec<SocketSession>,
-) {
-    if let Some(idx) = rooms.iter_mut().position(|room| room.find_player(addr)) {
-        let room = &mut rooms[idx];
-
-        let player = if crate::server::room::is_player(&room.player1, addr) {
-            queue.push(room.player1.as_ref().unwrap().clone());
-            room.player1 = None;
-            
-            &room.player2
-        } else {
-            queue.push(room.player2.as_ref().unwrap().clone());
-            room.player2 = None;
-
-            &room.player1
-        };
-
-        if let Some(player) = player {
-            // Left event
-            send_message(
-                &player.frame,
-                SocketRequest {
-                    opcode: 14,
-                    d: None,
-                },
-            );
-        };
-
-        super::notify_connections(
-            SocketRequest::new(20, Some(EventData::Left { id: idx as u8 })),
-            queue,
-        )
-    }
-}
+    room: &mut Room,
+    context: &Context,
+) -> Result<(), ErrorMessage> {
+    let session = if crate::server::room::is_player(&room.player1, addr) {
+        room.player1.take()
+    } else {
+        room.player2.take()
+    };
 
-pub fn delete(
-    addr: std::net::SocketAddr,
-    id: usize,
-    rooms: &mut Vec<Room>,
-    queue: &mut Vec<SocketSession>,
-) {
-    let idx = match rooms
-        .iter()
-        .position(|room| room.player1.as_ref().unwrap().addr == addr)
-    {
+    let session = match session {
         Some(value) => value,
-        None => return,
+        None => return Err(ErrorMessage::new(ErrorCode::NotInRoom, "you are not in a room")),
     };
 
-    if idx != id as usize {
-        return;
-    }
+    if let Some(player) = room.player1.as_ref().or(room.player2.as_ref()) {
+        // Left event
+        player.send_event(ServerMessage::OpponentLeft);
+    };
 
-    let room = rooms.remove(idx);
+    context.services.hooks.emit(GameEvent::PlayerLeft {
+        room: context.id,
+        name: session.name.clone().unwrap_or_default(),
+    });
+    send_lobby(&context.lobby, Command::Enter { session });
+    context
+        .update(room.entry(context.id), ServerMessage::RoomLeft(RoomId { id: context.id }));
 
-    if let Some(player1) = room.player1 {
-        queue.push(player1);
-    }
+    Ok(())
+}
 
-    if let Some(player2) = room.player2 {
-        queue.push(player2);
+pub fn delete(
+    addr: std::net::SocketAddr,
+    room: &mut Room,
+    context: &Context,
+) -> Result<(), ErrorMessage> {
+    if !crate::server::room::is_player(&room.player1, addr) {
+        return Err(ErrorMessage::new(ErrorCode::NotRoomOwner, "only the owner can delete the room"));
     }
 
-    super::notify_connections(
-        SocketRequest::new(19, Some(EventData::RoomDeleted { id })),
-        queue,
-    )
-}
+    // The room closes once empty
+    room.bot = None;
 
-pub fn list(addr: std::net::SocketAddr, rooms: &mut Vec<Room>, queue: &mut Vec<SocketSession>) {
-    let session = match queue.iter().find(|session| session.addr == addr) {
-        Some(value) => value,
-        None => return,
-    };
+    if let Some(player1) = room.player1.take() {
+        send_lobby(&context.lobby, Command::Enter { session: player1 });
+    }
 
-    let mut parties = Vec::new();
+    if let Some(player2) = room.player2.take() {
+        player2.send_event(ServerMessage::OpponentLeft);
 
-    for (idx, room) in rooms.iter().enumerate() {
-        let players_amount = room.player1.is_some() as u8 + room.player2.is_some() as u8;
+        // Bots and engines stop with their session instead
+        if !crate::seat::is_seat(player2.addr) {
+            send_lobby(&context.lobby, Command::Enter { session: player2 });
+        }
+    }
 
-        let party = EventData::RoomCreated {
-            id: idx as u8,
-            player_name: room.name.clone(),
-            players_amount,
-            public: room.code.is_none(),
-        };
+    Ok(())
+}
 
-        parties.push(party);
+// Owners delete their room from inside of it
+pub fn delete_from_lobby(id: usize, lobby: &Lobby) -> Result<(), ErrorMessage> {
+    match lobby.rooms.get(&id) {
+        Some(_) => Err(ErrorMessage::new(ErrorCode::NotRoomOwner, "only the owner can delete the room")),
+        None => Err(ErrorMessage::new(ErrorCode::RoomNotFound, "room not found")),
     }
+}
+
+pub fn list(session: &SocketSession, lobby: &Lobby) -> Result<(), ErrorMessage> {
+    let parties = lobby
+        .rooms
+        .values()
+        .map(|room| room.entry.clone())
+        .collect();
+
+    session.send(ServerMessage::RoomList(RoomList { parties }));
 
-    send_message(
-        &session.frame,
-        SocketRequest::new(17, Some(EventData::ListRooms { parties: Some(parties)})),
-    )
+    Ok(())
 }
 
 fn generate_room_code(public: bool) -> Option<String> {
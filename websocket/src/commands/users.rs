@@ -1,89 +1,199 @@
 use crate::{
-    json::{EventData, SocketRequest},
-    server::{room::Room, send_message, session::SocketSession},
+    hooks::GameEvent,
+    json::{ClientMessage, Command, ErrorCode, ErrorMessage, Opponent, Outgoing, RoomId, ServerMessage},
+    server::{cluster::Envelope, lobby::Host, lobby::Lobby, room::Context, room::Room, send_lobby, session::SocketSession},
 };
 
-pub fn join(
+// Hands the session over to the room it asked for, which answers the request
+pub async fn join(
     addr: std::net::SocketAddr,
     player_name: String,
-    room_id: u8,
+    room_id: usize,
     room_code: Option<String>,
-    rooms: &mut Vec<Room>,
-    queue: &mut Vec<SocketSession>,
-) {
-    if !player_name.chars().all(char::is_alphanumeric) || player_name.len() > 10 {
-        return;
+    id: Option<u64>,
+    request: ClientMessage,
+    lobby: &mut Lobby,
+) -> Result<(), ErrorMessage> {
+    check_name(&player_name)?;
+
+    let room = match lobby.rooms.get(&room_id) {
+        Some(value) => value,
+        None => return Err(ErrorMessage::new(ErrorCode::RoomNotFound, "room not found")),
     };
 
-    let mut session = get_session(addr, queue).unwrap();
+    if room_code != room.code {
+        return Err(ErrorMessage::new(ErrorCode::WrongRoomCode, "wrong room code"));
+    }
 
-    if session.name.is_none() || session.name.as_ref() != Some(&player_name) {
-        session.name = Some(player_name);
+    let mut session = match lobby.queue.remove(&addr) {
+        Some(value) => value,
+        None => return Err(ErrorMessage::new(ErrorCode::AlreadyInRoom, "you are already in a room")),
     };
 
-    let room = &mut rooms[room_id as usize];
+    session.name = Some(player_name.clone());
+    lobby.members.insert(addr, room_id);
 
-    if room_code != room.code {
-        return;
+    match &room.host {
+        Host::Local(mailbox) => {
+            // Seat first, the requests the client sends the room directly come after it
+            let route = Outgoing::Route(Some(mailbox.clone()));
+
+            // Closed since, the lobby drops it with the next update
+            if mailbox.send(Command::Seat { session: session.clone(), id, request }).await.is_err() {
+                lobby.members.remove(&addr);
+                lobby.queue.insert(addr, session);
+
+                return Err(ErrorMessage::new(ErrorCode::RoomNotFound, "room not found"));
+            }
+
+            session.send(route);
+        }
+        // Requests keep going through the lobby, which forwards them
+        Host::Remote(node) => {
+            let envelope = Envelope::Seat {
+                room: room_id,
+                home: lobby.cluster.node.clone(),
+                addr,
+                name: player_name,
+                protocol: session.protocol,
+                id,
+                request,
+            };
+
+            lobby.cluster.send(node, envelope).await;
+            lobby.away.insert(addr, session);
+        }
     }
 
-    join_room(addr, session, room);
+    Ok(())
+}
 
-    super::notify_connections(
-        SocketRequest::new(13, Some(EventData::Joined { id: room_id, name: None })),
-        queue,
-    )
+pub fn check_name(player_name: &str) -> Result<(), ErrorMessage> {
+    if !player_name.chars().all(char::is_alphanumeric) || player_name.len() > 10 {
+        return Err(ErrorMessage::new(
+            ErrorCode::InvalidName,
+            "name must have up to 10 letters or digits",
+        ));
+    };
+
+    Ok(())
 }
 
-pub fn remove(addr: std::net::SocketAddr, rooms: &mut Vec<Room>, queue: &mut Vec<SocketSession>) {
-    if let Some(idx) = rooms.iter().position(|room| room.find_player(addr)) {
-        let room = &mut rooms[idx];
-        let player = if crate::server::room::is_player(&room.player1, addr) {
-            room.player1 = None;
+// Seats a session the lobby handed over, or sends it back when the room is full
+pub fn seat(
+    session: SocketSession,
+    id: Option<u64>,
+    request: ClientMessage,
+    room: &mut Room,
+    context: &Context,
+) {
+    let addr = session.addr;
+    // Humans take the seat back from a bot until the first move
+    let bot = room.bot.filter(|_| !room.is_available() && !room.is_started());
+
+    if !room.is_available() && bot.is_none() {
+        super::respond(
+            &session,
+            id,
+            &request,
+            Err(ErrorMessage::new(ErrorCode::RoomFull, "room is full")),
+        );
+        send_lobby(&context.lobby, Command::Enter { session });
 
-            &room.player2
+        return;
+    }
+
+    let (entry, message) = if let Some(bot) = bot {
+        log::trace!("[{bot}] bot left for {addr}");
+
+        if crate::server::room::is_player(&room.player1, bot) {
+            room.player1 = None;
         } else {
             room.player2 = None;
+        }
+
+        room.bot = None;
+        join_room(addr, session.clone(), room);
 
-            &room.player1
-        };
-
-        if let Some(player) = player {
-            // Left event
-            send_message(
-                &player.frame,
-                SocketRequest {
-                    opcode: 14,
-                    d: None,
-                },
-            );
-        };
-
-        super::notify_connections(
-            SocketRequest::new(20, Some(EventData::Left { id: idx as u8 })),
-            queue,
-        )
-    } else if let Some(idx) = queue.iter().position(|session| session.addr == addr) {
-        queue.remove(idx);
+        let entry = room.entry(context.id);
+        (entry.clone(), ServerMessage::RoomUpdated(entry))
+    } else {
+        join_room(addr, session.clone(), room);
+
+        (room.entry(context.id), ServerMessage::RoomJoined(RoomId { id: context.id }))
+    };
+
+    context.update(entry, message);
+    context.services.hooks.emit(GameEvent::PlayerJoined {
+        room: context.id,
+        name: session.name.clone().unwrap_or_default(),
+    });
+    super::respond(&session, id, &request, Ok(()));
+}
+
+// Session back in the lobby, new or out of a room
+pub async fn enter(session: SocketSession, lobby: &mut Lobby) {
+    // Player of another node, back in the lobby there
+    let guest = lobby.guests.iter().find(|(_, guest)| guest.addr == session.addr).map(|(key, _)| key.clone());
+    if let Some((home, addr)) = guest {
+        lobby.guests.remove(&(home.clone(), addr));
+        lobby.cluster.send(&home, Envelope::Enter { addr }).await;
+
+        return;
     }
 
-    log::trace!("{addr} disconnected");
+    if lobby.members.remove(&session.addr).is_some() {
+        session.send(Outgoing::Route(None));
+    }
+
+    lobby.queue.insert(session.addr, session);
 }
 
-pub fn get_session(
-    addr: std::net::SocketAddr,
-    queue: &mut Vec<SocketSession>,
-) -> Option<SocketSession> {
-    if let Some(idx) = queue.iter().position(|session| session.addr == addr) {
-        Some(queue.remove(idx))
-    } else {
-        None
+pub fn remove(addr: std::net::SocketAddr, room: &mut Room, context: &Context) {
+    if room.bot == Some(addr) {
+        room.bot = None;
+    } else if let Some(name) = room.get_player(addr).and_then(|session| session.name.clone()) {
+        context.services.hooks.emit(GameEvent::PlayerLeft { room: context.id, name });
     }
+
+    let player = if crate::server::room::is_player(&room.player1, addr) {
+        room.player1 = None;
+
+        &room.player2
+    } else {
+        room.player2 = None;
+
+        &room.player1
+    };
+
+    if let Some(player) = player {
+        // Left event
+        player.send_event(ServerMessage::OpponentLeft);
+    };
+
+    send_lobby(&context.lobby, Command::Left { addr });
+    context
+        .update(room.entry(context.id), ServerMessage::RoomLeft(RoomId { id: context.id }));
+
+    log::trace!("{addr} disconnected");
+}
+
+pub fn remove_from_lobby(addr: std::net::SocketAddr, lobby: &mut Lobby) {
+    lobby.queue.remove(&addr);
+
+    log::trace!("{addr} disconnected");
 }
 
 pub fn join_room(addr: std::net::SocketAddr, session: SocketSession, room: &mut Room) {
     let name = session.name.clone().unwrap();
     log::trace!("[{addr}] {} joined in match", &name);
+    session.reset_seq();
+
+    // Whoever takes a seat starts a new match, player 1 first
+    room.tray = [' '; 9];
+    room.moves.clear();
+    room.player1_turn = true;
+    room.duration_turn = None;
 
     match (room.player1.is_none(), room.player2.is_none()) {
         (true, false) | (true, true) => {
@@ -96,7 +206,7 @@ pub fn join_room(addr: std::net::SocketAddr, session: SocketSession, room: &mut
 
             notify_joined(false, room.player2.as_ref().unwrap(), room.player1.as_ref());
         }
-        _ => return,
+        _ => {}
     };
 }
 
@@ -110,29 +220,23 @@ pub fn notify_joined(
         None => return,
     };
 
-    crate::server::send_message(
-        &other_player.frame,
-        SocketRequest::new(
-            13,
-            Some(EventData::Joined {
-                id: is_player1 as u8,
-                name: joined_player
-                    .name
-                    .clone(),
-            }),
-        ),
+    other_player.send_event(
+        ServerMessage::OpponentJoined(Opponent {
+            id: is_player1 as u8,
+            name: joined_player
+                .name
+                .clone()
+                .unwrap_or_default(),
+        }),
     );
 
-    crate::server::send_message(
-        &joined_player.frame,
-        SocketRequest::new(
-            13,
-            Some(EventData::Joined {
-                id: !is_player1 as u8,
-                name: other_player
-                    .name
-                    .clone(),
-            }),
-        ),
+    joined_player.send_event(
+        ServerMessage::OpponentJoined(Opponent {
+            id: !is_player1 as u8,
+            name: other_player
+                .name
+                .clone()
+                .unwrap_or_default(),
+        }),
     );
 }
@@ -1,46 +1,71 @@
-use crate::json::{Command, EventData};
-use crate::server::send_message;
+use crate::json::{Command, ErrorCode, ErrorMessage, Outgoing};
+use crate::server::rate::Limiter;
+use crate::server::send_command;
 use crate::server::session::SocketSession;
 
 pub async fn handle(
     session: &mut SocketSession,
     data: Box<[u8]>,
-    cmd_tx: &tokio::sync::mpsc::UnboundedSender<Command>,
+    cmd_tx: &tokio::sync::mpsc::Sender<Command>,
+    limiter: &mut Limiter,
 ) -> Result<(), ()> {
-    let event = match serde_json::from_slice::<crate::json::SocketRequest>(&data) {
-        Ok(event) => event,
-        Err(_) => return Ok(()),
-    };
+    if !limiter.frame() {
+        let id = session.protocol.request_id(&data);
+        limited(session, limiter, ErrorMessage { id, ..rate_limited() });
+
+        return Ok(());
+    }
 
-    match (event.opcode, event.d.clone()) {
-        (10, Some(EventData::MarkPosition { .. })) => {
-            send_message(
-                &cmd_tx,
-                Command::MarkPosition {
-                    addr: session.addr,
-                    data: event.d.unwrap(),
+    let request = match session.protocol.decode(&data) {
+        Ok(request) => request,
+        Err(e) => {
+            log::trace!("[{}] sent an invalid message {e}", session.addr);
+            session.send(
+                ErrorMessage {
+                    id: session.protocol.request_id(&data),
+                    ..ErrorMessage::new(ErrorCode::InvalidMessage, &e.to_string())
                 },
             );
+
+            return Ok(());
         }
-        (12, Some(EventData::JoinRoom { .. })) => send_message(
-            &cmd_tx,
-            Command::JoinUser {
-                addr: session.addr,
-                data: event.d.unwrap(),
-            },
-        ),
-        (14, None) => send_message(&cmd_tx, Command::LeaveRoom { addr: session.addr }),
-        (15, Some(EventData::CreateRoom { .. })) => send_message(
-            &cmd_tx,
-            Command::CreateRoom {
-                addr: session.addr,
-                data: event.d.unwrap(),
-            },
-        ),
-        (17, None) => send_message(&cmd_tx, Command::ListRooms { addr: session.addr }),
-        (22, None) => send_message(&cmd_tx, Command::PlayAgain { addr: session.addr }),
-        _ => {}
+    };
+
+    if !limiter.request(&request.message) {
+        limited(session, limiter, rate_limited().request(request.id, &request.message));
+
+        return Ok(());
     }
 
+    send_command(
+        cmd_tx,
+        Command::Message {
+            session: session.clone(),
+            id: request.id,
+            message: request.message,
+        },
+    ).await;
+
     Ok(())
 }
+
+fn rate_limited() -> ErrorMessage {
+    ErrorMessage::new(ErrorCode::RateLimited, "too many requests, slow down")
+}
+
+// Refuses the request, and hangs up on clients that keep going
+fn limited(session: &SocketSession, limiter: &mut Limiter, error: ErrorMessage) {
+    if limiter.strike() {
+        log::warn!("[{}] kept going over the rate limit, disconnecting", session.addr);
+
+        session.send(Outgoing::Close {
+            code: web_socket::CloseCode::PolicyViolation,
+            reason: "rate limited",
+        });
+
+        return;
+    }
+
+    log::trace!("[{}] went over the rate limit", session.addr);
+    session.send(error);
+}
@@ -0,0 +1,44 @@
+// Callbacks told what happens in the rooms of this node, for services embedding the server
+
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub enum GameEvent {
+    RoomCreated {
+        room: usize,
+        owner: String,
+        public: bool,
+    },
+    PlayerJoined {
+        room: usize,
+        name: String,
+    },
+    PlayerLeft {
+        room: usize,
+        name: String,
+    },
+    // Status as in `EndRoom`: 1 or 2 for the winner, 3 for a draw
+    GameEnded {
+        room: usize,
+        moves: Vec<usize>,
+        status: u8,
+    },
+}
+
+// Run in the lobby and room tasks, so they should return quickly
+pub type Hook = Arc<dyn Fn(&GameEvent) + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct Hooks(Vec<Hook>);
+
+impl Hooks {
+    pub fn add(&mut self, hook: impl Fn(&GameEvent) + Send + Sync + 'static) {
+        self.0.push(Arc::new(hook));
+    }
+
+    pub fn emit(&self, event: GameEvent) {
+        for hook in &self.0 {
+            hook(&event);
+        }
+    }
+}
@@ -1,106 +1,619 @@
+use serde::de::Error;
+
+// Subprotocols clients ask for to speak tagged messages
+pub const TAGGED_PROTOCOL: &str = "tictactoe.json";
+pub const MSGPACK_PROTOCOL: &str = "tictactoe.msgpack";
+
+pub type DecodeError = Box<dyn std::error::Error + Send + Sync>;
+
+// Wire format spoken by a session
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    // `{"opcode": 10, "d": {...}}`, used by clients that don't ask for a subprotocol
+    Legacy,
+    // `{"type": "mark_position", ...}`
+    Tagged,
+    // Tagged messages as MessagePack maps, sent in binary frames
+    MessagePack,
+}
+
+// Frame of the legacy wire format
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
 pub struct SocketRequest {
     pub opcode: u32,
-    pub d: Option<EventData>,
+    #[cfg_attr(feature = "schema", ts(type = "unknown"))]
+    pub d: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", ts(type = "number"))]
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", ts(type = "number"))]
+    pub seq: Option<u64>,
+}
+
+// Opcodes of the legacy wire format, by the name of the message in the tagged one
+pub const REQUEST_OPCODES: &[(&str, u32)] = &[
+    ("mark_position", 10),
+    ("join_room", 12),
+    ("leave_room", 14),
+    ("create_room", 15),
+    ("delete_room", 16),
+    ("list_rooms", 17),
+    ("play_again", 22),
+    ("add_engine", 23),
+    ("add_bot", 25),
+    ("book_moves", 27),
+    ("get_room_state", 29),
+];
+
+pub const MESSAGE_OPCODES: &[(&str, u32)] = &[
+    ("mark_position", 10),
+    ("end_room", 11),
+    ("opponent_joined", 13),
+    ("room_joined", 13),
+    ("opponent_left", 14),
+    ("room_list", 17),
+    ("room_created", 18),
+    ("room_deleted", 19),
+    ("room_left", 20),
+    ("owner_code", 21),
+    ("bot_offer", 24),
+    ("room_updated", 26),
+    ("book_moves", 27),
+    ("ack", 28),
+    ("room_state", 29),
+    ("shutting_down", 30),
+    ("latency", 31),
+    ("error", 1007),
+];
+
+// Fields legacy frames carry as `null` on top of the message's, as every frame of opcode 13
+// had a name
+pub const LEGACY_NULLS: &[(&str, &str)] = &[("room_joined", "name")];
+
+fn opcode(opcodes: &[(&str, u32)], kind: &str) -> u32 {
+    opcodes
+        .iter()
+        .find(|(name, _)| *name == kind)
+        .map(|(_, opcode)| *opcode)
+        .expect("every message has an opcode")
 }
 
+// Client message with the id to echo in its ack or error
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
-#[serde(untagged)]
-pub enum EventData {
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct Request {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", ts(type = "number"))]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub message: ClientMessage,
+}
+
+// Server message with its number among the events of the room
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct ServerFrame {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", ts(type = "number"))]
+    pub seq: Option<u64>,
+    #[serde(flatten)]
+    pub message: ServerMessage,
+}
+
+// Only the id of a request that couldn't be decoded
+#[derive(serde::Deserialize)]
+struct RequestId {
+    id: Option<u64>,
+}
+
+// Messages sent by clients
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
     // opcode: 10
-    MarkPosition {
-        position: usize
-    },
-    // opcode: 11
-    // 1 -> won of player 1; 2 -> won of player 2; 3 -> draw
-    EndRoom {
-        status: u8,
-    },
+    MarkPosition(MarkPosition),
     // opcode: 12
-    JoinRoom {
-        player_name: String,
-        room_id: u8,
-        room_code: Option<String>,
-    },
-    // opcode: 13
-    Joined {
-        id: u8,
-        name: Option<String>,
-    },
-    // Left -> opcode: 14
+    JoinRoom(JoinRoom),
+    // opcode: 14
+    LeaveRoom,
     // opcode: 15
-    CreateRoom {
-        player_name: String,
-        public: bool,
-    },
+    CreateRoom(CreateRoom),
     // opcode: 16
-    DeleteRoom {
-        id: u8,
-    },
+    DeleteRoom(RoomId),
     // opcode: 17
-    ListRooms {
-        parties: Option<Vec<EventData>>,
-    },
-    // opcode: 18
-    RoomCreated {
-        id: u8,
-        player_name: String,
-        players_amount: u8,
-        public: bool
-    },
-    // opcode: 19
-    RoomDeleted {
-        id: usize,
-    },
-    // opcode: 20
-    Left {
-        id: u8
+    ListRooms,
+    // opcode: 22
+    PlayAgain,
+    // opcode: 23
+    AddEngine(AddEngine),
+    // opcode: 25
+    AddBot(AddBot),
+    // opcode: 27
+    BookMoves,
+    // opcode: 29
+    GetRoomState,
+}
+
+// Messages sent by the server
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    // Opponent's move
+    MarkPosition(MarkPosition),
+    EndRoom(EndRoom),
+    OpponentJoined(Opponent),
+    OpponentLeft,
+    // A player joined a room of the lobby
+    RoomJoined(RoomId),
+    // A player left a room of the lobby
+    RoomLeft(RoomId),
+    RoomCreated(RoomEntry),
+    RoomUpdated(RoomEntry),
+    RoomDeleted(RoomId),
+    RoomList(RoomList),
+    OwnerCode(OwnerCode),
+    BotOffer(BotOffer),
+    BookMoves(BookMoves),
+    RoomState(Box<RoomState>),
+    // Server stops, games in progress get until the deadline to finish
+    ShuttingDown(ShuttingDown),
+    // Round trips of both players, sent to the room every heartbeat interval
+    Latency(Latency),
+    // A request with an id was handled
+    Ack(Ack),
+    Error(ErrorMessage),
+}
+
+// What a session is asked to do with its socket
+#[derive(Clone, Debug)]
+pub enum Outgoing {
+    Message(ServerMessage),
+    // Room event, numbered per player from when they joined the room
+    Event {
+        seq: u64,
+        message: ServerMessage,
     },
-    // opcode: 21
-    OwnerCode {
-        code: String,
+    Ping,
+    // Close frame to send before hanging up
+    Close {
+        code: web_socket::CloseCode,
+        reason: &'static str,
     },
-    // PlayAgain -> opcode: 22
-    Message(String),
+    // Mailbox for the next requests, the room the session is in or the lobby
+    Route(Option<tokio::sync::mpsc::Sender<Command>>),
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct MarkPosition {
+    pub position: usize,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct JoinRoom {
+    pub player_name: String,
+    pub room_id: usize,
+    pub room_code: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct CreateRoom {
+    pub player_name: String,
+    pub public: bool,
+    // Classic rules unless named
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", ts(optional))]
+    pub rules: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct RoomId {
+    pub id: usize,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct AddEngine {
+    pub engine: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct AddBot {
+    pub difficulty: Difficulty,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct EndRoom {
+    // 1 -> won of player 1; 2 -> won of player 2; 3 -> draw
+    pub status: u8,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct Opponent {
+    // 1 when the opponent is player 1 and plays first
+    pub id: u8,
+    pub name: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct RoomEntry {
+    pub id: usize,
+    pub player_name: String,
+    pub players_amount: u8,
+    pub public: bool,
+    pub bot: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", ts(optional))]
+    pub rules: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct RoomList {
+    pub parties: Vec<RoomEntry>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct OwnerCode {
+    pub code: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct BotOffer {
+    pub levels: Vec<Difficulty>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct BookMoves {
+    pub moves: Vec<BookMove>,
 }
 
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct BookMove {
+    pub position: usize,
+    pub weight: u32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct RoomState {
+    pub id: usize,
+    // Squares 0 to 8, player 1 plays `X` and player 2 `O`
+    pub board: Vec<Option<char>>,
+    // 1 or 2, the player whose turn it is and the one asking
+    pub turn: u8,
+    pub you: u8,
+    pub player1: Option<String>,
+    pub player2: Option<String>,
+    // Milliseconds until the player in turn is disconnected, once the match started
+    #[cfg_attr(feature = "schema", ts(type = "number | null"))]
+    pub turn_time_left: Option<u64>,
+    pub moves: Vec<usize>,
+    // Last room event sent to the one asking
+    #[cfg_attr(feature = "schema", ts(type = "number"))]
+    pub seq: u64,
+    pub latency: Latency,
+}
+
+// Milliseconds between a ping and its pong, `None` until a player answered one
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct Latency {
+    #[cfg_attr(feature = "schema", ts(type = "number | null"))]
+    pub player1: Option<u64>,
+    #[cfg_attr(feature = "schema", ts(type = "number | null"))]
+    pub player2: Option<u64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct ShuttingDown {
+    // Seconds left before every connection is closed
+    #[cfg_attr(feature = "schema", ts(type = "number"))]
+    pub deadline: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct Ack {
+    #[cfg_attr(feature = "schema", ts(type = "number"))]
+    pub id: u64,
+    pub opcode: u32,
+    pub request: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+pub struct ErrorMessage {
+    pub code: ErrorCode,
+    pub message: String,
+    // Id, opcode and type of the rejected request, when they could be read
+    #[cfg_attr(feature = "schema", ts(type = "number | null"))]
+    pub id: Option<u64>,
+    pub opcode: Option<u32>,
+    pub request: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidMessage,
+    InvalidName,
+    AlreadyInRoom,
+    NotInRoom,
+    RoomNotFound,
+    RoomFull,
+    WrongRoomCode,
+    NotRoomOwner,
+    NoOpponent,
+    InvalidPosition,
+    NotYourTurn,
+    PositionTaken,
+    EngineNotFound,
+    EngineUnavailable,
+    BotNotOffered,
+    RateLimited,
+    // Nodes couldn't agree on a room id
+    ClusterUnavailable,
+    UnknownRules,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema, ts_rs::TS))]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+// Mailbox messages of the lobby and room tasks
 #[derive(Debug)]
 pub enum Command {
-    JoinUser {
-        addr: std::net::SocketAddr,
-        data: EventData,
+    // Request of a client or seat, answered through `session`
+    Message {
+        session: crate::server::session::SocketSession,
+        id: Option<u64>,
+        message: ClientMessage,
     },
     RemoveUser {
         addr: std::net::SocketAddr,
     },
-    MarkPosition {
+    Forfeit {
         addr: std::net::SocketAddr,
-        data: EventData,
     },
-    CreateRoom {
-        addr: std::net::SocketAddr,
-        data: EventData,
+    // Lobby to room, session asking for a seat with its join request
+    Seat {
+        session: crate::server::session::SocketSession,
+        id: Option<u64>,
+        request: ClientMessage,
     },
-    DeleteRoom {
-        addr: std::net::SocketAddr,
-        id: usize,
+    // Room to lobby, session back in the lobby
+    Enter {
+        session: crate::server::session::SocketSession,
     },
-    ListRooms {
+    // Room to lobby, player disconnected from the room
+    Left {
         addr: std::net::SocketAddr,
     },
-    StartGame {
-        addr: std::net::SocketAddr,
+    // Room to lobby, new lobby entry of the room and what to tell the lobby about it
+    Update {
+        entry: RoomEntry,
+        message: ServerMessage,
     },
-    PlayAgain {
-        addr: std::net::SocketAddr,
+    // Server stopping, games have `deadline` seconds to finish
+    Shutdown {
+        deadline: u64,
     },
-    LeaveRoom {
-        addr: std::net::SocketAddr,
+    // Close idle sessions, all of them once `expired`
+    Drain {
+        expired: bool,
+    },
+    // From another node, to the lobby
+    Cluster(crate::server::cluster::Envelope),
+}
+
+impl ClientMessage {
+    pub fn opcode(&self) -> u32 {
+        opcode(REQUEST_OPCODES, self.kind())
+    }
+
+    // Name of the message in the tagged wire format
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::MarkPosition(_) => "mark_position",
+            Self::JoinRoom(_) => "join_room",
+            Self::LeaveRoom => "leave_room",
+            Self::CreateRoom(_) => "create_room",
+            Self::DeleteRoom(_) => "delete_room",
+            Self::ListRooms => "list_rooms",
+            Self::PlayAgain => "play_again",
+            Self::AddEngine(_) => "add_engine",
+            Self::AddBot(_) => "add_bot",
+            Self::BookMoves => "book_moves",
+            Self::GetRoomState => "get_room_state",
+        }
+    }
+
+    fn from_legacy(request: SocketRequest) -> serde_json::Result<Request> {
+        let Some((kind, _)) = REQUEST_OPCODES.iter().find(|(_, opcode)| *opcode == request.opcode) else {
+            return Err(serde_json::Error::custom(format!("unknown opcode {}", request.opcode)));
+        };
+
+        // Fields of the payload next to the name, as in the tagged format
+        let mut fields = match request.d {
+            Some(serde_json::Value::Object(fields)) => fields,
+            _ => serde_json::Map::new(),
+        };
+        fields.insert("type".to_string(), serde_json::Value::from(*kind));
+        let message = serde_json::from_value(serde_json::Value::Object(fields))?;
+
+        Ok(Request {
+            id: request.id,
+            message,
+        })
+    }
+}
+
+impl ServerMessage {
+    pub fn opcode(&self) -> u32 {
+        opcode(MESSAGE_OPCODES, self.kind())
+    }
+
+    // Name of the message in the tagged wire format
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::MarkPosition(_) => "mark_position",
+            Self::EndRoom(_) => "end_room",
+            Self::OpponentJoined(_) => "opponent_joined",
+            Self::OpponentLeft => "opponent_left",
+            Self::RoomJoined(_) => "room_joined",
+            Self::RoomLeft(_) => "room_left",
+            Self::RoomCreated(_) => "room_created",
+            Self::RoomUpdated(_) => "room_updated",
+            Self::RoomDeleted(_) => "room_deleted",
+            Self::RoomList(_) => "room_list",
+            Self::OwnerCode(_) => "owner_code",
+            Self::BotOffer(_) => "bot_offer",
+            Self::BookMoves(_) => "book_moves",
+            Self::RoomState(_) => "room_state",
+            Self::ShuttingDown(_) => "shutting_down",
+            Self::Latency(_) => "latency",
+            Self::Ack(_) => "ack",
+            Self::Error(_) => "error",
+        }
+    }
+
+    // Broadcasts about the rooms of the lobby, which a client can catch up on by listing
+    // the rooms again
+    pub fn is_lobby_update(&self) -> bool {
+        matches!(
+            self,
+            Self::RoomJoined(_) | Self::RoomLeft(_) | Self::RoomCreated(_) | Self::RoomUpdated(_) | Self::RoomDeleted(_)
+        )
+    }
+
+    fn to_legacy(&self, seq: Option<u64>) -> SocketRequest {
+        let mut fields = match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(fields)) => fields,
+            _ => unreachable!("tagged messages serialize to objects"),
+        };
+        fields.remove("type");
+
+        for (_, field) in LEGACY_NULLS.iter().filter(|(kind, _)| *kind == self.kind()) {
+            fields.insert(field.to_string(), serde_json::Value::Null);
+        }
+
+        SocketRequest {
+            seq,
+            ..SocketRequest::new(
+                self.opcode(),
+                (!fields.is_empty()).then_some(serde_json::Value::Object(fields)),
+            )
+        }
+    }
+}
+
+impl ErrorMessage {
+    pub fn new(code: ErrorCode, message: &str) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            id: None,
+            opcode: None,
+            request: None,
+        }
+    }
+
+    pub fn request(self, id: Option<u64>, request: &ClientMessage) -> Self {
+        Self {
+            id,
+            opcode: Some(request.opcode()),
+            request: Some(request.kind().to_string()),
+            ..self
+        }
+    }
+}
+
+impl From<ErrorMessage> for ServerMessage {
+    fn from(error: ErrorMessage) -> Self {
+        Self::Error(error)
+    }
+}
+
+impl From<ErrorMessage> for Outgoing {
+    fn from(error: ErrorMessage) -> Self {
+        Self::Message(error.into())
+    }
+}
+
+impl Protocol {
+    pub fn from_subprotocol(name: &str) -> Option<Self> {
+        match name {
+            TAGGED_PROTOCOL => Some(Self::Tagged),
+            MSGPACK_PROTOCOL => Some(Self::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Self::MessagePack)
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Result<Request, DecodeError> {
+        Ok(match self {
+            Self::Legacy => ClientMessage::from_legacy(serde_json::from_slice(data)?)?,
+            Self::Tagged => serde_json::from_slice(data)?,
+            Self::MessagePack => rmp_serde::from_slice(data)?,
+        })
+    }
+
+    // Id of a request that failed to decode, every format keeps it at the top level
+    pub fn request_id(&self, data: &[u8]) -> Option<u64> {
+        match self {
+            Self::Legacy | Self::Tagged => serde_json::from_slice::<RequestId>(data).ok()?.id,
+            Self::MessagePack => rmp_serde::from_slice::<RequestId>(data).ok()?.id,
+        }
+    }
+
+    pub fn encode(&self, message: ServerMessage, seq: Option<u64>) -> Vec<u8> {
+        match self {
+            Self::Legacy => serde_json::to_vec(&message.to_legacy(seq)).unwrap(),
+            Self::Tagged => serde_json::to_vec(&ServerFrame { seq, message }).unwrap(),
+            Self::MessagePack => rmp_serde::to_vec_named(&ServerFrame { seq, message }).unwrap(),
+        }
+    }
+}
+
+impl From<ServerMessage> for Outgoing {
+    fn from(message: ServerMessage) -> Self {
+        Self::Message(message)
     }
 }
 
 impl SocketRequest {
-    pub fn new(opcode: u32, d: Option<EventData>) -> Self {
-        Self { opcode, d }
+    pub fn new(opcode: u32, d: Option<serde_json::Value>) -> Self {
+        Self {
+            opcode,
+            d,
+            id: None,
+            seq: None,
+        }
     }
 }
@@ -0,0 +1,13 @@
+// Tic-tac-toe server, run by the `websocket` binary or embedded through `server::App::builder`
+
+pub mod server;
+pub mod json;
+pub mod book;
+pub mod hooks;
+pub mod rules;
+pub mod storage;
+mod events;
+mod commands;
+mod seat;
+#[cfg(feature = "schema")]
+pub mod schema;
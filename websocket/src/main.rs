@@ -1,12 +1,15 @@
-pub mod server;
-pub mod json;
-mod events;
-mod commands;
-
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
 
-    server::App::new().run()
+    // Only writes the protocol definitions
+    #[cfg(feature = "schema")]
+    if let Some(dir) = std::env::args().skip_while(|arg| arg != "--protocol").nth(1) {
+        return websocket::schema::write(std::path::Path::new(&dir));
+    }
+
+    websocket::server::App::new().run()
+        .await?
+        .wait()
         .await
 }
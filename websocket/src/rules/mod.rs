@@ -0,0 +1,79 @@
+// Rule sets rooms are played by, picked by name when the room is created. Bots and engines
+// play the classic game whatever the rules of their room
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub const CLASSIC: &str = "classic";
+
+// How a match ended, player 1 plays `X` and player 2 `O`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    // Player 1 or 2
+    Win(u8),
+    Draw,
+}
+
+pub trait Rules: Send + Sync {
+    // Checked after each move of `mover`, 1 or 2, `None` while the match goes on
+    fn outcome(&self, tray: &[char; 9], mover: u8) -> Option<Outcome>;
+}
+
+// Three in a line wins
+pub struct Classic;
+
+impl Rules for Classic {
+    fn outcome(&self, tray: &[char; 9], mover: u8) -> Option<Outcome> {
+        if crate::server::room::has_line(tray) {
+            Some(Outcome::Win(mover))
+        } else if tray.iter().all(|square| *square != ' ') {
+            Some(Outcome::Draw)
+        } else {
+            None
+        }
+    }
+}
+
+// Rules of a room, `name` is `None` for the default ones
+#[derive(Clone)]
+pub struct RuleSet {
+    pub name: Option<String>,
+    pub rules: Arc<dyn Rules>,
+}
+
+impl std::fmt::Debug for RuleSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name.as_deref().unwrap_or(CLASSIC))
+    }
+}
+
+#[derive(Clone)]
+pub struct Registry {
+    sets: HashMap<String, Arc<dyn Rules>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self { sets: HashMap::new() };
+        registry.register(CLASSIC, Classic);
+
+        registry
+    }
+}
+
+impl Registry {
+    // Replaces the rules registered under the name, the classic ones included
+    pub fn register(&mut self, name: &str, rules: impl Rules + 'static) {
+        self.sets.insert(name.to_string(), Arc::new(rules));
+    }
+
+    // Classic rules unless a room asks for others
+    pub fn get(&self, name: Option<&str>) -> Option<RuleSet> {
+        let rules = self.sets.get(name.unwrap_or(CLASSIC))?.clone();
+
+        Some(RuleSet {
+            name: name.map(String::from),
+            rules,
+        })
+    }
+}
@@ -0,0 +1,125 @@
+// Protocol definitions for clients, written by `websocket --protocol <dir>` when built
+// with the `schema` feature:
+//
+//   protocol.ts           TypeScript types of every message, legacy frames by opcode
+//   protocol.schema.json  JSON Schema of every message, under `$defs`
+
+use ts_rs::TS;
+
+use crate::json::*;
+
+const HEADER: &str = "// Generated by `cargo run -p websocket --features schema -- --protocol <dir>`, do not edit\n";
+
+pub fn write(dir: &std::path::Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join("protocol.ts"), typescript())?;
+    std::fs::write(
+        dir.join("protocol.schema.json"),
+        serde_json::to_string_pretty(&json_schema())? + "\n",
+    )?;
+
+    log::info!("Protocol definitions written to {}", dir.display());
+
+    Ok(())
+}
+
+fn typescript() -> String {
+    let declarations = [
+        // Envelopes
+        Request::decl(),
+        ClientMessage::decl(),
+        ServerMessage::decl(),
+        // Payloads
+        MarkPosition::decl(),
+        JoinRoom::decl(),
+        CreateRoom::decl(),
+        RoomId::decl(),
+        AddEngine::decl(),
+        AddBot::decl(),
+        EndRoom::decl(),
+        Opponent::decl(),
+        RoomEntry::decl(),
+        RoomList::decl(),
+        OwnerCode::decl(),
+        BotOffer::decl(),
+        BookMoves::decl(),
+        BookMove::decl(),
+        RoomState::decl(),
+        ShuttingDown::decl(),
+        Latency::decl(),
+        Ack::decl(),
+        ErrorMessage::decl(),
+        ErrorCode::decl(),
+        Difficulty::decl(),
+    ];
+
+    let mut output = HEADER.to_string();
+    output.push_str(&format!("\nexport const TAGGED_PROTOCOL = \"{TAGGED_PROTOCOL}\";\n"));
+    output.push_str(&format!("export const MSGPACK_PROTOCOL = \"{MSGPACK_PROTOCOL}\";\n"));
+
+    for declaration in declarations {
+        output.push_str(&format!("\nexport {declaration}\n"));
+    }
+
+    output.push_str(&legacy());
+
+    output
+}
+
+// Legacy frames as one type per opcode, built from the tagged messages so that a message
+// without an opcode or with other fields breaks the client's build
+fn legacy() -> String {
+    let mut output = String::new();
+
+    for (name, opcodes, messages) in [
+        ("REQUEST_OPCODES", REQUEST_OPCODES, "ClientMessage"),
+        ("MESSAGE_OPCODES", MESSAGE_OPCODES, "ServerMessage"),
+    ] {
+        output.push_str(&format!("\nexport const {name} = {{\n"));
+        for (kind, opcode) in opcodes {
+            output.push_str(&format!("    \"{kind}\": {opcode},\n"));
+        }
+        output.push_str(&format!("}} as const satisfies Record<{messages}[\"type\"], number>;\n"));
+    }
+
+    // Grouped by message
+    output.push_str("\n// Fields only the legacy frames carry\ntype LegacyNulls = {\n");
+    let mut kinds: Vec<&str> = LEGACY_NULLS.iter().map(|(kind, _)| *kind).collect();
+    kinds.dedup();
+    for kind in kinds {
+        output.push_str(&format!("    \"{kind}\": {{ "));
+        for (_, field) in LEGACY_NULLS.iter().filter(|(other, _)| *other == kind) {
+            output.push_str(&format!("{field}: null, "));
+        }
+        output.push_str("},\n");
+    }
+    output.push_str("};\n");
+
+    output.push_str(
+        r#"
+// Fields of a message besides its name, `null` when it has none
+type Payload<M> = keyof Omit<M, "type"> extends never ? null : Omit<M, "type">;
+
+export type LegacyRequest = { [K in ClientMessage["type"]]: { opcode: (typeof REQUEST_OPCODES)[K], d: Payload<Extract<ClientMessage, { "type": K }>>, id?: number, } }[ClientMessage["type"]];
+
+export type LegacyMessage = { [K in ServerMessage["type"]]: { opcode: (typeof MESSAGE_OPCODES)[K], d: Payload<Extract<ServerMessage, { "type": K }>> & (K extends keyof LegacyNulls ? LegacyNulls[K] : unknown), seq?: number, } }[ServerMessage["type"]];
+"#,
+    );
+
+    output
+}
+
+fn json_schema() -> serde_json::Value {
+    let mut generator = schemars::SchemaGenerator::default();
+
+    // Everything else is reachable from the envelopes
+    generator.subschema_for::<Request>();
+    generator.subschema_for::<SocketRequest>();
+    generator.subschema_for::<ServerMessage>();
+
+    serde_json::json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Tic-tac-toe protocol",
+        "$defs": generator.take_definitions(true),
+    })
+}
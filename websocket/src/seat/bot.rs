@@ -0,0 +1,117 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::json::{Command, Difficulty};
+use crate::server::session::SocketSession;
+
+pub struct Bot {
+    difficulty: Difficulty,
+    book: std::sync::Arc<std::sync::RwLock<crate::book::Book>>,
+}
+
+impl Bot {
+    // Chance of playing a random square instead of the best one
+    fn blunder_rate(&self) -> f64 {
+        match self.difficulty {
+            Difficulty::Easy => 1.0,
+            Difficulty::Medium => 0.4,
+            Difficulty::Hard => 0.0,
+        }
+    }
+}
+
+impl super::Player for Bot {
+    async fn start(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    async fn new_game(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    async fn best_move(&mut self, tray: &[char; 9], mark: char) -> std::io::Result<usize> {
+        let free: Vec<usize> = (0..9).filter(|&i| tray[i] == ' ').collect();
+        let mut rng = rand::thread_rng();
+
+        if rng.gen_bool(self.blunder_rate()) {
+            return free.choose(&mut rng).copied().ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "board is full",
+            ));
+        }
+
+        // Games people played, good or bad, so the hard bot only trusts its search
+        if !matches!(self.difficulty, Difficulty::Hard) {
+            if let Some(position) = self.book.read().unwrap().pick(tray) {
+                return Ok(position);
+            }
+        }
+
+        let mut tray = *tray;
+        let mut best = None;
+
+        for position in free {
+            tray[position] = mark;
+            let score = -minimax(&mut tray, opponent(mark));
+            tray[position] = ' ';
+
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((position, score));
+            }
+        }
+
+        best.map(|(position, _)| position).ok_or(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "board is full",
+        ))
+    }
+
+    async fn exited(&mut self) -> String {
+        std::future::pending().await
+    }
+
+    async fn quit(&mut self) {}
+}
+
+fn opponent(mark: char) -> char {
+    if mark == 'X' {
+        'O'
+    } else {
+        'X'
+    }
+}
+
+// Score of the board for the player about to move, sooner wins score higher
+fn minimax(tray: &mut [char; 9], mark: char) -> i32 {
+    if crate::server::room::has_line(tray) {
+        // The previous move won
+        return -10 - tray.iter().filter(|square| square == &&' ').count() as i32;
+    }
+
+    let mut best = None;
+
+    for position in 0..9 {
+        if tray[position] != ' ' {
+            continue;
+        }
+
+        tray[position] = mark;
+        let score = -minimax(tray, opponent(mark));
+        tray[position] = ' ';
+
+        best = Some(best.map_or(score, |best: i32| best.max(score)));
+    }
+
+    // Draw when nobody can move
+    best.unwrap_or(0)
+}
+
+pub fn spawn(
+    difficulty: Difficulty,
+    book: std::sync::Arc<std::sync::RwLock<crate::book::Book>>,
+    cmd_tx: &tokio::sync::mpsc::Sender<Command>,
+) -> SocketSession {
+    let name = format!("Bot{difficulty:?}");
+
+    super::spawn(Bot { difficulty, book }, name, cmd_tx)
+}
@@ -0,0 +1,139 @@
+// External engines are child processes that play a room seat over stdin/stdout.
+// Every command is a single line of text:
+//
+// server -> engine
+//   tictactoe                   start of the session, answered with `tictactoeok`
+//   newgame                     a new match starts, forget any previous state
+//   position <board> <mark>     board as 9 squares of `X`, `O` or `-` and the mark to play
+//   go movetime <ms>            search the last position, answered with `bestmove <0-8>`
+//   quit                        the seat was released, the process should exit
+//
+// engine -> server
+//   id name <name>              optional, sent before `tictactoeok`
+//   tictactoeok
+//   bestmove <position>
+//
+// A crash, an illegal move, a line longer than `MAX_LINE` or not answering in time forfeits the
+// engine seat. The handshake and each move get one deadline, however many lines come before.
+
+use std::io::ErrorKind;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::time::Instant;
+
+use common::settings::engine::EngineSettings;
+
+use crate::json::Command;
+use crate::server::session::SocketSession;
+
+// Bytes of a line, newline included
+pub const MAX_LINE: u64 = 256;
+
+pub struct Engine {
+    child: tokio::process::Child,
+    stdin: tokio::process::ChildStdin,
+    stdout: tokio::io::BufReader<tokio::process::ChildStdout>,
+    move_time: u64,
+    timeout: std::time::Duration,
+}
+
+impl Engine {
+    async fn write(&mut self, line: &str) -> std::io::Result<()> {
+        self.stdin.write_all(format!("{line}\n").as_bytes()).await?;
+        self.stdin.flush().await
+    }
+
+    async fn read(&mut self, deadline: Instant) -> std::io::Result<String> {
+        let mut line = Vec::new();
+        let read = tokio::time::timeout_at(deadline, (&mut self.stdout).take(MAX_LINE).read_until(b'\n', &mut line))
+            .await
+            .map_err(|_| std::io::Error::new(ErrorKind::TimedOut, "engine did not answer"))??;
+
+        if read == 0 {
+            return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "engine closed its output"));
+        }
+        if read as u64 == MAX_LINE && !line.ends_with(b"\n") {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, "engine sent a line too long"));
+        }
+
+        String::from_utf8(line).map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "engine sent invalid UTF-8"))
+    }
+}
+
+impl super::Player for Engine {
+    async fn start(&mut self) -> std::io::Result<()> {
+        let deadline = Instant::now() + self.timeout;
+        self.write("tictactoe").await?;
+
+        loop {
+            let line = self.read(deadline).await?;
+
+            match line.trim() {
+                "tictactoeok" => return Ok(()),
+                line if line.starts_with("id ") => log::trace!("engine identified as '{}'", &line[3..]),
+                _ => {}
+            }
+        }
+    }
+
+    async fn new_game(&mut self) -> std::io::Result<()> {
+        self.write("newgame").await
+    }
+
+    async fn best_move(&mut self, tray: &[char; 9], mark: char) -> std::io::Result<usize> {
+        let board: String = tray
+            .iter()
+            .map(|square| if square == &' ' { '-' } else { *square })
+            .collect();
+
+        // Thinking time on top of the timeout
+        let deadline = Instant::now() + std::time::Duration::from_millis(self.move_time) + self.timeout;
+        self.write(&format!("position {board} {mark}")).await?;
+        self.write(&format!("go movetime {}", self.move_time)).await?;
+
+        loop {
+            let line = self.read(deadline).await?;
+
+            if let Some(position) = line.trim().strip_prefix("bestmove ") {
+                return position.trim().parse().map_err(|_| {
+                    std::io::Error::new(ErrorKind::InvalidData, "engine sent an invalid move")
+                });
+            }
+        }
+    }
+
+    async fn exited(&mut self) -> String {
+        format!("{:?}", self.child.wait().await)
+    }
+
+    async fn quit(&mut self) {
+        let _ = self.write("quit").await;
+
+        // Killed unless it exits in time
+        if tokio::time::timeout(self.timeout, self.child.wait()).await.is_err() {
+            let _ = self.child.kill().await;
+        }
+    }
+}
+
+pub fn spawn(
+    settings: &EngineSettings,
+    cmd_tx: &tokio::sync::mpsc::Sender<Command>,
+) -> std::io::Result<SocketSession> {
+    let mut child = tokio::process::Command::new(&settings.command)
+        .args(&settings.args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let engine = Engine {
+        stdin: child.stdin.take().unwrap(),
+        stdout: tokio::io::BufReader::new(child.stdout.take().unwrap()),
+        child,
+        move_time: settings.move_time,
+        timeout: std::time::Duration::from_millis(settings.timeout),
+    };
+
+    Ok(super::spawn(engine, settings.name.clone(), cmd_tx))
+}
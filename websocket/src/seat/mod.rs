@@ -0,0 +1,211 @@
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use common::settings::channel::SlowConsumer;
+
+use crate::json::{ClientMessage, Command, MarkPosition, Opponent, Outgoing, Protocol, ServerMessage};
+use crate::server::send_command;
+use crate::server::session::SocketSession;
+
+pub mod bot;
+pub mod engine;
+
+// Seats have no socket, so each gets an address of its own in the discard-only prefix
+// (RFC 6666), apart from the ones of Unix socket peers and cluster guests
+const PREFIX: u128 = (0x0100 << 112) | (2 << 64);
+// A seat only ever gets the events of its room
+const CAPACITY: usize = 16;
+
+// Something that plays a room seat without a client behind it
+pub trait Player {
+    fn start(&mut self) -> impl std::future::Future<Output = std::io::Result<()>> + Send;
+
+    fn new_game(&mut self) -> impl std::future::Future<Output = std::io::Result<()>> + Send;
+
+    fn best_move(
+        &mut self,
+        tray: &[char; 9],
+        mark: char,
+    ) -> impl std::future::Future<Output = std::io::Result<usize>> + Send;
+
+    /// Resolves when the player stops on its own
+    fn exited(&mut self) -> impl std::future::Future<Output = String> + Send;
+
+    fn quit(&mut self) -> impl std::future::Future<Output = ()> + Send;
+}
+
+// Bots and engines, as opposed to clients
+pub fn is_seat(addr: SocketAddr) -> bool {
+    matches!(addr.ip(), IpAddr::V6(ip) if u128::from(ip) >> 64 == PREFIX >> 64)
+}
+
+fn seat_addr() -> SocketAddr {
+    static SEATS: AtomicU64 = AtomicU64::new(0);
+    let id = SEATS.fetch_add(1, Ordering::Relaxed);
+
+    SocketAddr::new(IpAddr::V6(Ipv6Addr::from(PREFIX | id as u128)), 0)
+}
+
+// The seat task's side of its session. The room holds the session, so the task stops once the
+// room lets go of it
+struct Seat {
+    addr: SocketAddr,
+    name: String,
+    frame: tokio::sync::mpsc::WeakSender<Outgoing>,
+    kick: std::sync::Arc<tokio::sync::Notify>,
+}
+
+impl Seat {
+    // To send a move with, answered like the ones of clients
+    fn session(&self) -> Option<SocketSession> {
+        let mut session =
+            SocketSession::new(self.addr, self.frame.upgrade()?, Protocol::Tagged, SlowConsumer::Disconnect);
+        session.name = Some(self.name.clone());
+
+        Some(session)
+    }
+}
+
+pub fn spawn<P: Player + Send + 'static>(
+    player: P,
+    name: String,
+    cmd_tx: &tokio::sync::mpsc::Sender<Command>,
+) -> SocketSession {
+    let addr = seat_addr();
+    let (tx, rx) = tokio::sync::mpsc::channel::<Outgoing>(CAPACITY);
+    let mut session = SocketSession::new(addr, tx, Protocol::Tagged, SlowConsumer::Disconnect);
+    session.name = Some(name.clone());
+
+    let seat = Seat {
+        addr,
+        name,
+        frame: session.frame.downgrade(),
+        kick: session.kick.clone(),
+    };
+    tokio::spawn(run(player, rx, seat, cmd_tx.clone()));
+
+    session
+}
+
+async fn run<P: Player>(
+    mut player: P,
+    mut rx: tokio::sync::mpsc::Receiver<Outgoing>,
+    seat: Seat,
+    cmd_tx: tokio::sync::mpsc::Sender<Command>,
+) {
+    let addr = seat.addr;
+
+    if let Err(e) = player.start().await {
+        log::error!("[{addr}] seat failed to start {e}");
+        send_command(&cmd_tx, Command::Forfeit { addr }).await;
+
+        return;
+    }
+
+    let mut tray = [' '; 9];
+    let mut mark = 'X';
+    let mut my_turn = false;
+
+    loop {
+        tokio::select! {
+            event = rx.recv() => {
+                let message = match event {
+                    Some(Outgoing::Message(message) | Outgoing::Event { message, .. }) => message,
+                    Some(Outgoing::Ping | Outgoing::Route(_)) => continue,
+                    // Seat timed out
+                    Some(Outgoing::Close { .. }) => {
+                        send_command(&cmd_tx, Command::RemoveUser { addr }).await;
+
+                        break;
+                    }
+                    // Seat released by the room
+                    None => break,
+                };
+
+                match message {
+                    // Nothing to play on
+                    ServerMessage::Latency(_) => continue,
+                    // The opponent (re)started a match
+                    ServerMessage::OpponentJoined(Opponent { id, .. }) => {
+                        tray = [' '; 9];
+                        mark = if id == 1 { 'O' } else { 'X' };
+                        my_turn = id != 1;
+
+                        if let Err(e) = player.new_game().await {
+                            log::error!("[{addr}] seat failed to start game {e}");
+                            send_command(&cmd_tx, Command::Forfeit { addr }).await;
+
+                            break;
+                        }
+                    }
+                    ServerMessage::MarkPosition(MarkPosition { position }) => {
+                        tray[position] = if mark == 'X' { 'O' } else { 'X' };
+                        my_turn = true;
+                    }
+                    ServerMessage::EndRoom(_) => my_turn = false,
+                    ServerMessage::OpponentLeft => {
+                        send_command(&cmd_tx, Command::RemoveUser { addr }).await;
+
+                        break;
+                    }
+                    ServerMessage::Error(error) => {
+                        log::error!("[{addr}] seat played an illegal move {}", error.message);
+                        send_command(&cmd_tx, Command::Forfeit { addr }).await;
+
+                        break;
+                    }
+                    _ => {}
+                }
+
+                if my_turn {
+                    let position = match player.best_move(&tray, mark).await {
+                        Ok(value) if value <= 8 => value,
+                        Ok(value) => {
+                            log::error!("[{addr}] seat played out of the board {value}");
+                            send_command(&cmd_tx, Command::Forfeit { addr }).await;
+
+                            break;
+                        }
+                        Err(e) => {
+                            log::error!("[{addr}] seat failed to move {e}");
+                            send_command(&cmd_tx, Command::Forfeit { addr }).await;
+
+                            break;
+                        }
+                    };
+
+                    tray[position] = mark;
+                    my_turn = false;
+
+                    // Released meanwhile
+                    let Some(session) = seat.session() else {
+                        break;
+                    };
+
+                    send_command(
+                        &cmd_tx,
+                        Command::Message {
+                            session,
+                            id: None,
+                            message: ClientMessage::MarkPosition(MarkPosition { position }),
+                        },
+                    ).await;
+                }
+            }
+            // Fell behind its room
+            _ = seat.kick.notified() => {
+                send_command(&cmd_tx, Command::RemoveUser { addr }).await;
+
+                break;
+            }
+            reason = player.exited() => {
+                log::error!("[{addr}] seat exited {reason}");
+                send_command(&cmd_tx, Command::Forfeit { addr }).await;
+
+                return;
+            }
+        }
+    }
+
+    player.quit().await;
+}
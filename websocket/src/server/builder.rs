@@ -0,0 +1,83 @@
+// Server assembled by a service hosting games in its own process
+
+use std::sync::{Arc, RwLock};
+
+use common::settings::AppSettings;
+
+use crate::hooks::{GameEvent, Hooks};
+use crate::rules::{Registry, Rules};
+use crate::server::{cluster::Backend, listener::Listener, App, Services};
+use crate::storage::{Files, Storage};
+
+#[derive(Default)]
+pub struct Builder {
+    settings: Option<AppSettings>,
+    listeners: Vec<Listener>,
+    rules: Registry,
+    storage: Option<Arc<dyn Storage>>,
+    hooks: Hooks,
+    backend: Option<Arc<dyn Backend>>,
+    signals: bool,
+}
+
+impl Builder {
+    // Defaults and the environment otherwise, `application.toml` isn't read
+    pub fn settings(mut self, settings: AppSettings) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+
+    // Already bound, the host, port and sockets of the settings are then ignored
+    pub fn listener(mut self, listener: impl Into<Listener>) -> Self {
+        self.listeners.push(listener.into());
+        self
+    }
+
+    // Rooms ask for them by name
+    pub fn rules(mut self, name: &str, rules: impl Rules + 'static) -> Self {
+        self.rules.register(name, rules);
+        self
+    }
+
+    // Files of the book settings otherwise
+    pub fn storage(mut self, storage: impl Storage + 'static) -> Self {
+        self.storage = Some(Arc::new(storage));
+        self
+    }
+
+    pub fn hook(mut self, hook: impl Fn(&GameEvent) + Send + Sync + 'static) -> Self {
+        self.hooks.add(hook);
+        self
+    }
+
+    // Shared with the other nodes of the process, such as `cluster::memory::Memory`
+    pub fn backend(mut self, backend: Arc<dyn Backend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    // Whether Ctrl-C and SIGTERM stop the server, off unless asked for
+    pub fn signals(mut self, signals: bool) -> Self {
+        self.signals = signals;
+        self
+    }
+
+    pub fn build(self) -> App {
+        let settings = self.settings.unwrap_or_default();
+        let storage = self.storage.unwrap_or_else(|| Arc::new(Files::new(&settings.book)));
+        let book = crate::book::from_settings(&settings.book, storage.as_ref());
+
+        App {
+            settings: Arc::new(settings),
+            book: Arc::new(RwLock::new(book)),
+            services: Arc::new(Services {
+                rules: self.rules,
+                storage,
+                hooks: self.hooks,
+            }),
+            listeners: self.listeners,
+            backend: self.backend,
+            signals: self.signals,
+        }
+    }
+}
@@ -0,0 +1,55 @@
+// Backend of a single process, shared by the nodes running in it
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use tokio::sync::mpsc;
+
+use super::{Backend, BoxFuture};
+
+// Payloads a subscriber may fall behind by
+const CAPACITY: usize = 1024;
+
+#[derive(Default)]
+pub struct Memory {
+    subscribers: Mutex<HashMap<String, Vec<mpsc::Sender<Vec<u8>>>>>,
+    counters: Mutex<HashMap<String, u64>>,
+}
+
+impl Backend for Memory {
+    fn publish<'a>(&'a self, channel: &'a str, payload: Vec<u8>) -> BoxFuture<'a, std::io::Result<()>> {
+        Box::pin(async move {
+            let subscribers = self.subscribers.lock().unwrap().get(channel).cloned().unwrap_or_default();
+
+            for subscriber in subscribers {
+                // Gone subscribers are dropped on the next subscription
+                let _ = subscriber.send(payload.clone()).await;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn subscribe<'a>(&'a self, channel: &'a str) -> BoxFuture<'a, std::io::Result<mpsc::Receiver<Vec<u8>>>> {
+        Box::pin(async move {
+            let (tx, rx) = mpsc::channel(CAPACITY);
+
+            let mut subscribers = self.subscribers.lock().unwrap();
+            let subscribers = subscribers.entry(channel.to_string()).or_default();
+            subscribers.retain(|subscriber| !subscriber.is_closed());
+            subscribers.push(tx);
+
+            Ok(rx)
+        })
+    }
+
+    fn next_id<'a>(&'a self, key: &'a str) -> BoxFuture<'a, std::io::Result<u64>> {
+        Box::pin(async move {
+            let mut counters = self.counters.lock().unwrap();
+            let counter = counters.entry(key.to_string()).or_default();
+            *counter += 1;
+
+            Ok(*counter - 1)
+        })
+    }
+}
@@ -0,0 +1,292 @@
+// Nodes sharing one lobby through a pub/sub backend. Each room is hosted by the node it was
+// created on, players of other nodes sit in it as guests, sessions whose messages go through
+// the backend to the node they are connected to
+
+use std::future::Future;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+use common::settings::{cluster::ClusterSettings, AppSettings};
+
+use crate::json::{ClientMessage, Command, Outgoing, Protocol, RoomEntry, ServerMessage};
+use crate::server::send_lobby;
+use crate::server::session::SocketSession;
+
+pub mod memory;
+pub mod redis;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// Carries payloads between the nodes
+pub trait Backend: Send + Sync {
+    // To every subscriber of the channel, on any node
+    fn publish<'a>(&'a self, channel: &'a str, payload: Vec<u8>) -> BoxFuture<'a, std::io::Result<()>>;
+
+    // Payloads published to the channel from then on
+    fn subscribe<'a>(&'a self, channel: &'a str) -> BoxFuture<'a, std::io::Result<mpsc::Receiver<Vec<u8>>>>;
+
+    // Counter shared by every node, starting from 0
+    fn next_id<'a>(&'a self, key: &'a str) -> BoxFuture<'a, std::io::Result<u64>>;
+
+    // Stops what runs in the background, called by the server that created the backend as it stops
+    fn close(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+}
+
+// Backend of the settings, none for a node on its own
+pub async fn backend(settings: &ClusterSettings) -> std::io::Result<Option<Arc<dyn Backend>>> {
+    match &settings.redis_url {
+        Some(url) => Ok(Some(Arc::new(redis::Redis::connect(url).await?))),
+        None => Ok(None),
+    }
+}
+
+// What nodes tell each other
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Envelope {
+    // Node started, the others tell it about their rooms
+    Hello {
+        node: String,
+    },
+    // Lobby entry of a room, and what to tell the lobby about it
+    Room {
+        node: String,
+        entry: RoomEntry,
+        code: Option<String>,
+        message: Option<ServerMessage>,
+    },
+    // Node stopped, its rooms are gone
+    Bye {
+        node: String,
+    },
+    // Home node of a player to the node hosting its room
+    Seat {
+        room: usize,
+        home: String,
+        addr: SocketAddr,
+        name: String,
+        protocol: Protocol,
+        id: Option<u64>,
+        request: ClientMessage,
+    },
+    Message {
+        room: usize,
+        home: String,
+        addr: SocketAddr,
+        id: Option<u64>,
+        message: ClientMessage,
+    },
+    RemoveUser {
+        room: usize,
+        home: String,
+        addr: SocketAddr,
+    },
+    Forfeit {
+        room: usize,
+        home: String,
+        addr: SocketAddr,
+    },
+    // Host of a room to the home node of a guest
+    Outgoing {
+        addr: SocketAddr,
+        seq: Option<u64>,
+        message: ServerMessage,
+    },
+    Close {
+        addr: SocketAddr,
+        code: u16,
+        reason: String,
+    },
+    // Guest back in the lobby of its node
+    Enter {
+        addr: SocketAddr,
+    },
+}
+
+// This node's side of the backend, cheap to clone
+#[derive(Clone)]
+pub struct Cluster {
+    pub node: String,
+    prefix: String,
+    backend: Arc<dyn Backend>,
+    // Published in order by a single task, so the lobby doesn't wait on the backend. `None` on
+    // a node on its own, which has nobody to tell
+    outbound: Option<mpsc::Sender<(String, Envelope)>>,
+}
+
+impl Cluster {
+    // Listens on the lobby channel and the one of this node, handing what comes in to the lobby,
+    // in `tasks`. Without a backend the node is on its own, and only counts its rooms in memory
+    pub async fn join(
+        settings: &AppSettings,
+        backend: Option<Arc<dyn Backend>>,
+        lobby: mpsc::UnboundedSender<Command>,
+        tasks: &mut JoinSet<()>,
+    ) -> std::io::Result<Self> {
+        let node = settings
+            .cluster
+            .node
+            .clone()
+            .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+
+        let Some(backend) = backend else {
+            return Ok(Self {
+                node,
+                prefix: settings.cluster.prefix.clone(),
+                backend: Arc::new(memory::Memory::default()),
+                outbound: None,
+            });
+        };
+
+        let (outbound, rx) = mpsc::channel(settings.channels.command_capacity);
+
+        let cluster = Self {
+            node,
+            prefix: settings.cluster.prefix.clone(),
+            backend,
+            outbound: Some(outbound),
+        };
+
+        let lobby_rx = cluster.backend.subscribe(&cluster.lobby_channel()).await?;
+        let node_rx = cluster.backend.subscribe(&cluster.node_channel(&cluster.node)).await?;
+
+        tasks.spawn(publish(cluster.backend.clone(), rx));
+        tasks.spawn(ingress(cluster.node.clone(), lobby_rx, lobby.clone()));
+        tasks.spawn(ingress(cluster.node.clone(), node_rx, lobby));
+
+        log::info!("Joined the cluster as node {}", cluster.node);
+        cluster.broadcast(Envelope::Hello { node: cluster.node.clone() }).await;
+
+        Ok(cluster)
+    }
+
+    fn lobby_channel(&self) -> String {
+        format!("{}:lobby", self.prefix)
+    }
+
+    fn node_channel(&self, node: &str) -> String {
+        format!("{}:node:{node}", self.prefix)
+    }
+
+    // To every other node
+    pub async fn broadcast(&self, envelope: Envelope) {
+        if let Some(outbound) = &self.outbound {
+            let _ = outbound.send((self.lobby_channel(), envelope)).await;
+        }
+    }
+
+    pub async fn send(&self, node: &str, envelope: Envelope) {
+        if let Some(outbound) = &self.outbound {
+            let _ = outbound.send((self.node_channel(node), envelope)).await;
+        }
+    }
+
+    // Room ids are unique across the nodes
+    pub async fn next_room_id(&self) -> std::io::Result<usize> {
+        let key = format!("{}:rooms", self.prefix);
+
+        Ok(self.backend.next_id(&key).await? as usize)
+    }
+
+    // Stand-in for a player of another node, what the room sends it goes to that node from a task
+    // in `tasks`. Seated under an address of this node, players of different nodes may share theirs
+    pub fn guest(
+        &self,
+        home: String,
+        addr: SocketAddr,
+        name: String,
+        protocol: Protocol,
+        settings: &AppSettings,
+        tasks: &mut JoinSet<()>,
+    ) -> SocketSession {
+        let (tx, mut rx) = mpsc::channel::<Outgoing>(settings.channels.session_capacity);
+        let mut session = SocketSession::new(guest_addr(), tx, protocol, settings.channels.slow_consumer);
+        session.name = Some(name);
+
+        let cluster = self.clone();
+        tasks.spawn(async move {
+            while let Some(outgoing) = rx.recv().await {
+                let envelope = match outgoing {
+                    Outgoing::Message(message) => Envelope::Outgoing { addr, seq: None, message },
+                    Outgoing::Event { seq, message } => Envelope::Outgoing { addr, seq: Some(seq), message },
+                    Outgoing::Close { code, reason } => Envelope::Close {
+                        addr,
+                        code: code.into(),
+                        reason: reason.to_string(),
+                    },
+                    // Only the home node talks to the socket
+                    Outgoing::Ping | Outgoing::Route(_) => continue,
+                };
+
+                cluster.send(&home, envelope).await;
+            }
+        });
+
+        session
+    }
+
+    // Published right away, the node is about to stop
+    pub async fn leave(&self) {
+        if self.outbound.is_none() {
+            return;
+        }
+
+        let envelope = Envelope::Bye { node: self.node.clone() };
+
+        if let Err(e) = self.backend.publish(&self.lobby_channel(), serde_json::to_vec(&envelope).unwrap()).await {
+            log::error!("failed to leave the cluster {e}");
+        }
+    }
+}
+
+async fn publish(backend: Arc<dyn Backend>, mut rx: mpsc::Receiver<(String, Envelope)>) {
+    while let Some((channel, envelope)) = rx.recv().await {
+        if let Err(e) = backend.publish(&channel, serde_json::to_vec(&envelope).unwrap()).await {
+            log::error!("failed to publish to {channel} {e}");
+        }
+    }
+}
+
+async fn ingress(node: String, mut rx: mpsc::Receiver<Vec<u8>>, lobby: mpsc::UnboundedSender<Command>) {
+    while let Some(payload) = rx.recv().await {
+        let envelope: Envelope = match serde_json::from_slice(&payload) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                log::error!("invalid message from the cluster {e}");
+                continue;
+            }
+        };
+
+        // Own broadcasts
+        if let Envelope::Hello { node: from } | Envelope::Room { node: from, .. } | Envelope::Bye { node: from } = &envelope {
+            if *from == node {
+                continue;
+            }
+        }
+
+        send_lobby(&lobby, Command::Cluster(envelope));
+    }
+}
+
+// Unique on this node, apart from the addresses given to Unix peers
+fn guest_addr() -> SocketAddr {
+    static GUESTS: AtomicU64 = AtomicU64::new(0);
+    let id = GUESTS.fetch_add(1, Ordering::Relaxed);
+
+    SocketAddr::new(IpAddr::V6(Ipv6Addr::from((0x0100 << 112) | (1 << 64) | id as u128)), 0)
+}
+
+// Close reasons are static, these are the ones rooms close guests with
+pub fn close_reason(reason: &str) -> &'static str {
+    ["turn timed out", "server shutting down"]
+        .into_iter()
+        .find(|known| *known == reason)
+        .unwrap_or("closed by the host")
+}
@@ -0,0 +1,396 @@
+// Backend on a Redis server, or anything else speaking its protocol (RESP2). Publishing and
+// counting share one connection, subscriptions are kept on another and renewed when it drops
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::task::JoinSet;
+
+use super::{Backend, BoxFuture};
+
+// Largest bulk string read, well over anything nodes send each other
+const MAX_BULK: usize = 16 * 1024 * 1024;
+// Payloads a subscriber may fall behind by
+const CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug)]
+struct Config {
+    addr: String,
+    user: Option<String>,
+    password: Option<String>,
+    db: Option<u32>,
+}
+
+// Channel to subscribe to, who to hand its payloads to and who to tell once subscribed
+type Subscription = (String, mpsc::Sender<Vec<u8>>, oneshot::Sender<()>);
+
+pub struct Redis {
+    config: Config,
+    commands: tokio::sync::Mutex<Option<tokio::io::BufReader<tokio::net::TcpStream>>>,
+    subscriptions: mpsc::Sender<Subscription>,
+    // Told to stop by `close`, it may be waiting to reconnect
+    stop: Arc<Notify>,
+    subscriber: std::sync::Mutex<Option<tokio::task::JoinHandle<()>>>,
+}
+
+#[derive(Debug)]
+enum Value {
+    Simple,
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Value>>),
+}
+
+impl Redis {
+    // Fails unless the server can be reached right away, later outages are retried
+    pub async fn connect(url: &str) -> std::io::Result<Self> {
+        let config = parse_url(url)?;
+        let commands = open(&config).await?;
+
+        let (subscriptions, rx) = mpsc::channel(16);
+        let stop = Arc::new(Notify::new());
+        let subscriber = tokio::spawn(subscriber(config.clone(), rx, stop.clone()));
+
+        Ok(Self {
+            config,
+            commands: tokio::sync::Mutex::new(Some(commands)),
+            subscriptions,
+            stop,
+            subscriber: std::sync::Mutex::new(Some(subscriber)),
+        })
+    }
+
+    async fn command(&self, args: &[&[u8]]) -> std::io::Result<Value> {
+        let mut connection = self.commands.lock().await;
+
+        // Once more on a new connection, the last one may have been dropped by the server
+        for retry in [false, true] {
+            let stream = match connection.as_mut() {
+                Some(stream) => stream,
+                None => connection.insert(open(&self.config).await?),
+            };
+
+            match request(stream, args).await {
+                Err(e) if e.kind() != ErrorKind::Other => {
+                    *connection = None;
+
+                    if retry {
+                        return Err(e);
+                    }
+                }
+                result => return result,
+            }
+        }
+
+        unreachable!()
+    }
+}
+
+impl Backend for Redis {
+    fn publish<'a>(&'a self, channel: &'a str, payload: Vec<u8>) -> BoxFuture<'a, std::io::Result<()>> {
+        Box::pin(async move {
+            self.command(&[b"PUBLISH", channel.as_bytes(), &payload]).await?;
+
+            Ok(())
+        })
+    }
+
+    fn subscribe<'a>(&'a self, channel: &'a str) -> BoxFuture<'a, std::io::Result<mpsc::Receiver<Vec<u8>>>> {
+        Box::pin(async move {
+            let (tx, rx) = mpsc::channel(CAPACITY);
+            let (done, subscribed) = oneshot::channel();
+
+            let gone = || Error::new(ErrorKind::BrokenPipe, "subscriber stopped");
+            self.subscriptions.send((channel.to_string(), tx, done)).await.map_err(|_| gone())?;
+            subscribed.await.map_err(|_| gone())?;
+
+            Ok(rx)
+        })
+    }
+
+    fn next_id<'a>(&'a self, key: &'a str) -> BoxFuture<'a, std::io::Result<u64>> {
+        Box::pin(async move {
+            match self.command(&[b"INCR", key.as_bytes()]).await? {
+                Value::Integer(id) if id > 0 => Ok(id as u64 - 1),
+                value => Err(Error::new(ErrorKind::InvalidData, format!("unexpected reply {value:?}"))),
+            }
+        })
+    }
+
+    fn close(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            let subscriber = self.subscriber.lock().unwrap().take();
+
+            if let Some(subscriber) = subscriber {
+                self.stop.notify_one();
+                let _ = subscriber.await;
+            }
+        })
+    }
+}
+
+// `redis://[[user]:password@]host:port[/db]`, or just `host:port`
+fn parse_url(url: &str) -> std::io::Result<Config> {
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("invalid redis url {url}"));
+
+    let rest = url.strip_prefix("redis://").unwrap_or(url);
+    let (auth, rest) = match rest.rsplit_once('@') {
+        Some((auth, rest)) => (Some(auth), rest),
+        None => (None, rest),
+    };
+    let (addr, db) = match rest.split_once('/') {
+        Some((addr, "")) => (addr, None),
+        Some((addr, db)) => (addr, Some(db.parse().map_err(|_| invalid())?)),
+        None => (rest, None),
+    };
+
+    if addr.is_empty() {
+        return Err(invalid());
+    }
+
+    let (user, password) = match auth.map(|auth| auth.split_once(':')) {
+        Some(Some((user, password))) => ((!user.is_empty()).then(|| user.to_string()), Some(password.to_string())),
+        Some(None) => (None, auth.map(String::from)),
+        None => (None, None),
+    };
+
+    Ok(Config {
+        addr: addr.to_string(),
+        user,
+        password,
+        db,
+    })
+}
+
+async fn open(config: &Config) -> std::io::Result<tokio::io::BufReader<tokio::net::TcpStream>> {
+    let stream = tokio::net::TcpStream::connect(&config.addr).await?;
+    stream.set_nodelay(true)?;
+
+    let mut stream = tokio::io::BufReader::new(stream);
+
+    match (&config.user, &config.password) {
+        (Some(user), Some(password)) => {
+            request(&mut stream, &[b"AUTH", user.as_bytes(), password.as_bytes()]).await?;
+        }
+        (None, Some(password)) => {
+            request(&mut stream, &[b"AUTH", password.as_bytes()]).await?;
+        }
+        _ => {}
+    }
+
+    if let Some(db) = config.db {
+        request(&mut stream, &[b"SELECT", db.to_string().as_bytes()]).await?;
+    }
+
+    Ok(stream)
+}
+
+// Errors the server replied with are of kind `Other`, others mean the connection is unusable
+async fn request(stream: &mut tokio::io::BufReader<tokio::net::TcpStream>, args: &[&[u8]]) -> std::io::Result<Value> {
+    stream.write_all(&encode(args)).await?;
+
+    read_value(stream).await?
+}
+
+fn encode(args: &[&[u8]]) -> Vec<u8> {
+    let mut data = format!("*{}\r\n", args.len()).into_bytes();
+
+    for arg in args {
+        data.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        data.extend_from_slice(arg);
+        data.extend_from_slice(b"\r\n");
+    }
+
+    data
+}
+
+// Replies and pushes, the outer result failing when the connection does
+fn read_value<R>(reader: &mut R) -> BoxFuture<'_, std::io::Result<std::io::Result<Value>>>
+where
+    R: AsyncBufRead + Unpin + Send,
+{
+    Box::pin(async move {
+        let invalid = |what: &str| Error::new(ErrorKind::InvalidData, format!("invalid reply {what}"));
+
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed"));
+        }
+
+        let line = line.trim_end_matches("\r\n");
+        let (kind, rest) = line.split_at_checked(1).ok_or_else(|| invalid(line))?;
+        let len = || rest.parse::<i64>().map_err(|_| invalid(line));
+
+        Ok(Ok(match kind {
+            "+" => Value::Simple,
+            "-" => return Ok(Err(Error::other(rest.to_string()))),
+            ":" => Value::Integer(len()?),
+            "$" => match len()? {
+                -1 => Value::Bulk(None),
+                len if len < 0 || len as usize > MAX_BULK => return Err(invalid(line)),
+                len => {
+                    let mut data = vec![0; len as usize + 2];
+                    reader.read_exact(&mut data).await?;
+                    data.truncate(len as usize);
+
+                    Value::Bulk(Some(data))
+                }
+            },
+            "*" => match len()? {
+                -1 => Value::Array(None),
+                len if len < 0 => return Err(invalid(line)),
+                len => {
+                    let mut items = Vec::new();
+                    let mut error = None;
+
+                    // Read to the end even past an error, or the next reply would start midway
+                    for _ in 0..len {
+                        match read_value(reader).await? {
+                            Ok(item) => items.push(item),
+                            Err(e) => error = error.or(Some(e)),
+                        }
+                    }
+
+                    if let Some(e) = error {
+                        return Ok(Err(e));
+                    }
+
+                    Value::Array(Some(items))
+                }
+            },
+            _ => return Err(invalid(line)),
+        }))
+    })
+}
+
+// Until told to stop, with the task reading its connection
+async fn subscriber(config: Config, requests: mpsc::Receiver<Subscription>, stop: Arc<Notify>) {
+    let mut reader = JoinSet::new();
+
+    tokio::select! {
+        _ = subscribe(config, requests, &mut reader) => {}
+        _ = stop.notified() => {}
+    }
+
+    reader.shutdown().await;
+}
+
+// Holds the subscribed connection, connecting again when it drops
+async fn subscribe(config: Config, mut requests: mpsc::Receiver<Subscription>, reader_task: &mut JoinSet<()>) {
+    let mut channels: HashMap<String, Vec<mpsc::Sender<Vec<u8>>>> = HashMap::new();
+    // Waiting for the server to confirm
+    let mut pending: HashMap<String, Vec<oneshot::Sender<()>>> = HashMap::new();
+
+    loop {
+        let stream = match open(&config).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::error!("failed to connect to redis at {} {e}", config.addr);
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+                continue;
+            }
+        };
+        let (reader, mut writer) = stream.into_inner().into_split();
+
+        // Read apart, so subscribing doesn't cut a reply short. The one of the last connection
+        // is done with
+        let (values_tx, mut values) = mpsc::channel(CAPACITY);
+        reader_task.shutdown().await;
+        reader_task.spawn(async move {
+            let mut reader = tokio::io::BufReader::new(reader);
+
+            while let Ok(Ok(value)) = read_value(&mut reader).await {
+                if values_tx.send(value).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        if !channels.is_empty() {
+            let mut args: Vec<&[u8]> = vec![b"SUBSCRIBE"];
+            args.extend(channels.keys().map(|channel| channel.as_bytes()));
+
+            if let Err(e) = writer.write_all(&encode(&args)).await {
+                log::error!("failed to subscribe on redis {e}");
+                continue;
+            }
+        }
+
+        loop {
+            tokio::select! {
+                request = requests.recv() => {
+                    // Backend dropped
+                    let Some((channel, tx, done)) = request else {
+                        return;
+                    };
+
+                    let subscribers = channels.entry(channel.clone()).or_default();
+                    subscribers.push(tx);
+
+                    if subscribers.len() > 1 {
+                        let _ = done.send(());
+                        continue;
+                    }
+
+                    pending.entry(channel.clone()).or_default().push(done);
+
+                    if let Err(e) = writer.write_all(&encode(&[b"SUBSCRIBE", channel.as_bytes()])).await {
+                        log::error!("failed to subscribe on redis {e}");
+                        break;
+                    }
+                }
+                value = values.recv() => {
+                    let Some(value) = value else {
+                        log::error!("lost the redis connection, reconnecting");
+                        break;
+                    };
+
+                    dispatch(value, &mut channels, &mut pending).await;
+                }
+            }
+        }
+
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+}
+
+// `[message, channel, payload]` pushes, and `[subscribe, channel, count]` confirmations
+async fn dispatch(
+    value: Value,
+    channels: &mut HashMap<String, Vec<mpsc::Sender<Vec<u8>>>>,
+    pending: &mut HashMap<String, Vec<oneshot::Sender<()>>>,
+) {
+    let Value::Array(Some(items)) = value else {
+        return;
+    };
+
+    let mut items = items.into_iter();
+    let (Some(Value::Bulk(Some(kind))), Some(Value::Bulk(Some(channel)))) = (items.next(), items.next()) else {
+        return;
+    };
+    let channel = String::from_utf8_lossy(&channel).into_owned();
+
+    match (kind.as_slice(), items.next()) {
+        (b"message", Some(Value::Bulk(Some(payload)))) => {
+            let Some(subscribers) = channels.get_mut(&channel) else {
+                return;
+            };
+
+            for subscriber in subscribers.iter() {
+                let _ = subscriber.send(payload.clone()).await;
+            }
+
+            subscribers.retain(|subscriber| !subscriber.is_closed());
+        }
+        (b"subscribe", _) => {
+            for done in pending.remove(&channel).unwrap_or_default() {
+                let _ = done.send(());
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,295 @@
+// permessage-deflate (RFC 7692)
+
+use std::io::ErrorKind;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use tokio::io::{AsyncRead, ReadBuf};
+
+use common::settings::deflate::DeflateSettings;
+
+pub const EXTENSION: &str = "permessage-deflate";
+// Marks the first frame of a compressed message
+pub const RSV1: u8 = 0x40;
+// Empty block ending every compressed message, left out of the frames
+const TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+// Parameters agreed on during the handshake
+#[derive(Clone, Copy, Debug)]
+pub struct Params {
+    pub server_no_context_takeover: bool,
+    pub client_no_context_takeover: bool,
+}
+
+impl Params {
+    // First offer of `Sec-WebSocket-Extensions` we can accept
+    pub fn negotiate<'a>(
+        offers: impl Iterator<Item = &'a str>,
+        settings: &DeflateSettings,
+    ) -> Option<Self> {
+        if !settings.enabled {
+            return None;
+        }
+
+        offers.filter_map(|offer| Self::accept(offer, settings)).next()
+    }
+
+    fn accept(offer: &str, settings: &DeflateSettings) -> Option<Self> {
+        let mut params = offer.split(';').map(str::trim);
+
+        if params.next()? != EXTENSION {
+            return None;
+        }
+
+        let mut accepted = Self {
+            server_no_context_takeover: settings.server_no_context_takeover,
+            client_no_context_takeover: settings.client_no_context_takeover,
+        };
+
+        for param in params {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param, None),
+            };
+
+            match (name, value) {
+                ("server_no_context_takeover", None) => accepted.server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => accepted.client_no_context_takeover = true,
+                // Our compressor always uses the whole window
+                ("server_max_window_bits", Some("15")) => {}
+                // Any window the client picks can be inflated
+                ("client_max_window_bits", None) => {}
+                ("client_max_window_bits", Some(bits))
+                    if bits.parse::<u8>().is_ok_and(|bits| (8..=15).contains(&bits)) => {}
+                _ => return None,
+            }
+        }
+
+        Some(accepted)
+    }
+
+    // Value of `Sec-WebSocket-Extensions` in the handshake response
+    pub fn response(&self) -> String {
+        let mut response = EXTENSION.to_string();
+
+        if self.server_no_context_takeover {
+            response.push_str("; server_no_context_takeover");
+        }
+
+        if self.client_no_context_takeover {
+            response.push_str("; client_no_context_takeover");
+        }
+
+        response
+    }
+}
+
+pub struct Deflate {
+    params: Params,
+    min_size: usize,
+    compress: Compress,
+    decompress: Decompress,
+}
+
+impl Deflate {
+    pub fn new(params: Params, settings: &DeflateSettings) -> Self {
+        Self {
+            params,
+            min_size: settings.min_size,
+            compress: Compress::new(Compression::new(settings.level.min(9)), false),
+            decompress: Decompress::new(false),
+        }
+    }
+
+    // `None` when the message is better sent as it is
+    pub fn compress(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        if data.len() < self.min_size {
+            return None;
+        }
+
+        let mut output = Vec::with_capacity(data.len() / 2 + TAIL.len());
+        let mut consumed = 0;
+
+        loop {
+            if output.len() == output.capacity() {
+                output.reserve(output.capacity().max(64));
+            }
+
+            let total_in = self.compress.total_in();
+            if let Err(e) = self.compress.compress_vec(&data[consumed..], &mut output, FlushCompress::Sync) {
+                log::error!("failed to compress message {e}");
+                self.compress.reset();
+
+                return None;
+            }
+
+            consumed += (self.compress.total_in() - total_in) as usize;
+
+            // The flush is done once there is room left in the output
+            if consumed == data.len() && output.len() < output.capacity() {
+                break;
+            }
+        }
+
+        if output.ends_with(&TAIL) {
+            output.truncate(output.len() - TAIL.len());
+        }
+
+        if self.params.server_no_context_takeover {
+            self.compress.reset();
+        }
+
+        Some(output)
+    }
+
+    // `None` once the output goes over `max_len`
+    pub fn decompress(&mut self, data: &[u8], max_len: usize) -> std::io::Result<Option<Vec<u8>>> {
+        let input = [data, &TAIL].concat();
+        let mut output = Vec::with_capacity(data.len() * 4);
+        let mut consumed = 0;
+
+        loop {
+            if output.len() == output.capacity() {
+                output.reserve(output.capacity().max(64));
+            }
+
+            let (total_in, total_out) = (self.decompress.total_in(), self.decompress.total_out());
+            self.decompress
+                .decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync)
+                .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+
+            consumed += (self.decompress.total_in() - total_in) as usize;
+
+            if output.len() > max_len {
+                return Ok(None);
+            }
+
+            let stalled = self.decompress.total_in() == total_in && self.decompress.total_out() == total_out;
+
+            if consumed == input.len() && output.len() < output.capacity() || stalled {
+                break;
+            }
+        }
+
+        if self.params.client_no_context_takeover {
+            self.decompress.reset(false);
+        }
+
+        Ok(Some(output))
+    }
+}
+
+// Clears RSV1 of incoming data frames, which `web_socket` rejects, and remembers it
+// for the frame being read
+pub struct Reader<R> {
+    inner: R,
+    enabled: bool,
+    header: [u8; 14],
+    header_len: usize,
+    header_needed: usize,
+    payload_left: u64,
+    // Whether the message of the last frame read is compressed
+    pub compressed: bool,
+}
+
+impl<R> Reader<R> {
+    pub fn new(inner: R, enabled: bool) -> Self {
+        Self {
+            inner,
+            enabled,
+            header: [0; 14],
+            header_len: 0,
+            header_needed: 2,
+            payload_left: 0,
+            compressed: false,
+        }
+    }
+
+    fn track(&mut self, data: &mut [u8]) {
+        let mut i = 0;
+
+        while i < data.len() {
+            if self.payload_left > 0 {
+                let skip = self.payload_left.min((data.len() - i) as u64);
+                self.payload_left -= skip;
+                i += skip as usize;
+
+                continue;
+            }
+
+            // Only the first frame of a text or binary message carries the bit
+            if self.header_len == 0 && matches!(data[i] & 0x0f, 1 | 2) {
+                self.compressed = data[i] & RSV1 != 0;
+                data[i] &= !RSV1;
+            }
+
+            self.header[self.header_len] = data[i];
+            self.header_len += 1;
+            i += 1;
+
+            if self.header_len == 2 {
+                let len = match self.header[1] & 0x7f {
+                    126 => 2,
+                    127 => 8,
+                    _ => 0,
+                };
+                let mask = if self.header[1] & 0x80 != 0 { 4 } else { 0 };
+
+                self.header_needed = 2 + len + mask;
+            }
+
+            if self.header_len >= 2 && self.header_len == self.header_needed {
+                self.payload_left = match self.header[1] & 0x7f {
+                    126 => u16::from_be_bytes([self.header[2], self.header[3]]) as u64,
+                    127 => u64::from_be_bytes(self.header[2..10].try_into().unwrap()),
+                    len => len as u64,
+                };
+                self.header_len = 0;
+                self.header_needed = 2;
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Reader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let start = buf.filled().len();
+        std::task::ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+
+        if self.enabled {
+            self.track(&mut buf.filled_mut()[start..]);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Inflates compressed data events, anything else is passed through
+pub fn inflate(
+    event: web_socket::Event,
+    deflate: Option<&mut Deflate>,
+    compressed: bool,
+    max_len: usize,
+) -> web_socket::Event {
+    match (event, deflate) {
+        (web_socket::Event::Data { ty, data }, Some(deflate)) if compressed => {
+            match deflate.decompress(&data, max_len) {
+                Ok(Some(data)) => web_socket::Event::Data {
+                    ty,
+                    data: data.into_boxed_slice(),
+                },
+                Ok(None) => web_socket::Event::Error(super::session::TOO_LARGE),
+                Err(e) => {
+                    log::error!("failed to inflate message {e}");
+                    web_socket::Event::Error("invalid compressed message")
+                }
+            }
+        }
+        (event, _) => event,
+    }
+}
@@ -3,9 +3,15 @@ use std::io::ErrorKind;
 // https://github.com/nurmohammed840/websocket.rs/blob/main/examples/utils/handshake.rs
 use sha1::Digest;
 
-use crate::server::request;
+use common::settings::{handshake::HandshakeSettings, AppSettings};
+
+use crate::json::Protocol;
+use crate::server::http::{HttpError, BAD_REQUEST, FORBIDDEN, METHOD_NOT_ALLOWED, UPGRADE_REQUIRED};
+use crate::server::{deflate, http, request, sse};
 
 pub const MAGIC_STRING: &[u8; 36] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// Only version of the protocol there is
+pub const VERSION: &str = "13";
 
 fn accept_key_from(sec_ws_key: impl AsRef<[u8]>) -> String {
     let mut sha1 = sha1::Sha1::new();
@@ -28,25 +34,171 @@ fn response(
     format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {key}\r\n{headers}\r\n")
 }
 
-async fn build<IO>(reader: &mut IO) -> std::io::Result<String>
-    where IO: Unpin + tokio::io::AsyncBufRead
-{
-    let req = request::HttpRequest::parse(reader).await?;
+fn build(
+    req: &request::HttpRequest,
+    settings: &AppSettings,
+) -> Result<(String, Protocol, Option<deflate::Params>), HttpError> {
+    validate(req, &settings.handshake)?;
+
+    let key = request::get_sec_key(req).ok_or(HttpError::new(BAD_REQUEST, "security key not found"))?;
+
+    let mut headers = vec![("x-agent".to_string(), "web-socket".to_string())];
+
+    // First subprotocol offered by the client that we speak
+    let protocol = match request::get_protocols(req)
+        .find_map(|name| Protocol::from_subprotocol(name).map(|protocol| (name, protocol)))
+    {
+        Some((name, protocol)) => {
+            headers.push(("Sec-WebSocket-Protocol".to_string(), name.to_string()));
+            protocol
+        }
+        None => Protocol::Legacy,
+    };
+
+    let deflate = deflate::Params::negotiate(request::get_extensions(req), &settings.deflate);
+
+    if let Some(params) = deflate {
+        headers.push(("Sec-WebSocket-Extensions".to_string(), params.response()));
+    }
+
+    Ok((response(key, headers), protocol, deflate))
+}
+
+// RFC 6455 section 4.2.1
+fn validate(req: &request::HttpRequest, settings: &HandshakeSettings) -> Result<(), HttpError> {
+    let mut prefix = req.prefix.split(' ');
+
+    match (prefix.next(), prefix.next(), prefix.next(), prefix.next()) {
+        (Some("GET"), Some(target), Some("HTTP/1.1"), None) if target.starts_with('/') => {}
+        (Some(method), _, _, _) if method != "GET" => {
+            return Err(HttpError::new(METHOD_NOT_ALLOWED, "upgrades must be GET requests").header("Allow", "GET"));
+        }
+        _ => return Err(HttpError::new(BAD_REQUEST, "invalid request line")),
+    }
+
+    if request::get_header(req, "host").is_none_or(str::is_empty) {
+        return Err(HttpError::new(BAD_REQUEST, "host not found"));
+    }
 
-    let key = request::get_sec_key(&req).ok_or(std::io::Error::new(
-        ErrorKind::InvalidData,
-        "security key not found",
-    ))?;
+    if request::get_header(req, "sec-websocket-version") != Some(VERSION) {
+        return Err(
+            HttpError::new(UPGRADE_REQUIRED, "unsupported WebSocket version")
+                .header("Sec-WebSocket-Version", VERSION),
+        );
+    }
 
-    Ok(response(key, [("x-agent", "web-socket")]))
+    // Nonce of 16 random bytes
+    let key = request::get_header(req, "sec-websocket-key").unwrap_or_default();
+    if base64::Engine::decode(&base64::prelude::BASE64_STANDARD, key).map(|key| key.len()) != Ok(16) {
+        return Err(HttpError::new(BAD_REQUEST, "invalid security key"));
+    }
+
+    check_origin(req, settings)
 }
 
-pub async fn send<Reader, Writer>(reader: &mut Reader, writer: &mut Writer) -> std::io::Result<()>
+// Only browsers send an origin, and only they need to be kept from other sites
+pub fn check_origin(req: &request::HttpRequest, settings: &HandshakeSettings) -> Result<(), HttpError> {
+    if let Some(origin) = request::get_header(req, "origin") {
+        if !settings.allowed_origins.is_empty()
+            && !settings.allowed_origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin))
+        {
+            return Err(HttpError::new(FORBIDDEN, "origin not allowed"));
+        }
+    }
+
+    Ok(())
+}
+
+// Connection ready to exchange frames
+pub struct Upgraded {
+    pub reader: tokio::io::BufReader<super::Reader>,
+    pub writer: super::Writer,
+    // Switching Protocols, still to be sent
+    pub response: String,
+    pub protocol: Protocol,
+    pub deflate: Option<deflate::Params>,
+}
+
+// What a connection asked for, besides probes answered right away
+pub enum Accepted {
+    Upgraded(Upgraded),
+    Fallback(sse::Fallback),
+}
+
+// Request handled after the handshake
+pub enum Handshake {
+    // Switching Protocols, still to be sent
+    Upgrade(String, Protocol, Option<deflate::Params>),
+    // Event stream or post of the transport without WebSockets
+    Fallback(request::HttpRequest),
+}
+
+// TLS, if configured, then the HTTP upgrade, answered with `refused` when the connection is
+// over a limit
+pub async fn accept(
+    stream: super::listener::Stream,
+    tls: Option<std::sync::Arc<tokio_rustls::rustls::ServerConfig>>,
+    settings: &AppSettings,
+    status: &http::Status,
+    refused: Option<HttpError>,
+) -> std::io::Result<Option<Accepted>> {
+    let (reader, mut writer) = stream.split(tls).await?;
+    let mut reader = tokio::io::BufReader::new(reader);
+
+    Ok(send(&mut reader, &mut writer, settings, status, refused).await?.map(|handshake| match handshake {
+        Handshake::Upgrade(response, protocol, deflate) => {
+            Accepted::Upgraded(Upgraded { reader, writer, response, protocol, deflate })
+        }
+        Handshake::Fallback(request) => Accepted::Fallback(sse::Fallback { reader, writer, request }),
+    }))
+}
+
+// Upgrade response for the caller to send, `None` when the request was not an upgrade and got
+// a plain HTTP response instead
+pub async fn send<Reader, Writer>(
+    reader: &mut Reader,
+    writer: &mut Writer,
+    settings: &AppSettings,
+    status: &http::Status,
+    refused: Option<HttpError>,
+) -> std::io::Result<Option<Handshake>>
     where Reader: Unpin + tokio::io::AsyncBufRead,
           Writer: Unpin + tokio::io::AsyncWrite,
 {
-    let response = build(reader).await?;
+    let result = match request::HttpRequest::parse(reader, &settings.handshake).await {
+        // Streams are refused like upgrades
+        Ok(req) if sse::is_fallback(&req) => match (refused, sse::validate(&req, &settings.handshake)) {
+            (_, Err(e)) => Err(e),
+            (Some(e), _) if sse::is_stream(&req) => Err(e),
+            _ => return Ok(Some(Handshake::Fallback(req))),
+        },
+        Ok(req) if !request::is_upgrade(&req) => {
+            let response = http::respond(&req, status);
+            log::trace!("{} {}", req.prefix, response.lines().next().unwrap_or_default());
 
-    tokio::io::AsyncWriteExt::write_all(writer, response.as_bytes()).await?;
-    Ok(())
-}
\ No newline at end of file
+            tokio::io::AsyncWriteExt::write_all(writer, response.as_bytes()).await?;
+            tokio::io::AsyncWriteExt::shutdown(writer).await?;
+
+            return Ok(None);
+        }
+        // Only upgrades are refused, probes are still answered
+        Ok(req) => match refused {
+            Some(e) => Err(e),
+            None => build(&req, settings),
+        },
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok((response, protocol, deflate)) => Ok(Some(Handshake::Upgrade(response, protocol, deflate))),
+        Err(e) => {
+            // Tell the client why before hanging up
+            if let Some(response) = e.response() {
+                tokio::io::AsyncWriteExt::write_all(writer, response.as_bytes()).await?;
+                tokio::io::AsyncWriteExt::shutdown(writer).await?;
+            }
+
+            Err(std::io::Error::new(ErrorKind::InvalidData, e.to_string()))
+        }
+    }
+}
@@ -0,0 +1,152 @@
+// Plain HTTP requests answered on the WebSocket port, so probes don't need to upgrade
+
+use std::sync::atomic::Ordering;
+
+use serde_json::json;
+
+use crate::json::{MSGPACK_PROTOCOL, TAGGED_PROTOCOL};
+use crate::server::request::{Header, HttpRequest};
+use crate::server::stats;
+
+pub const ACCEPTED: &str = "202 Accepted";
+pub const NO_CONTENT: &str = "204 No Content";
+pub const BAD_REQUEST: &str = "400 Bad Request";
+pub const FORBIDDEN: &str = "403 Forbidden";
+pub const NOT_FOUND: &str = "404 Not Found";
+pub const METHOD_NOT_ALLOWED: &str = "405 Method Not Allowed";
+pub const REQUEST_TIMEOUT: &str = "408 Request Timeout";
+pub const LENGTH_REQUIRED: &str = "411 Length Required";
+pub const PAYLOAD_TOO_LARGE: &str = "413 Payload Too Large";
+pub const UPGRADE_REQUIRED: &str = "426 Upgrade Required";
+pub const TOO_MANY_REQUESTS: &str = "429 Too Many Requests";
+pub const HEADERS_TOO_LARGE: &str = "431 Request Header Fields Too Large";
+pub const SERVICE_UNAVAILABLE: &str = "503 Service Unavailable";
+
+// What the server is up to when a probe comes in
+#[derive(Clone, Default)]
+pub struct Status {
+    pub rooms: usize,
+    pub players: usize,
+}
+
+pub fn respond(req: &HttpRequest, status: &Status) -> String {
+    if req.method() != Some("GET") {
+        return response(METHOD_NOT_ALLOWED, "text/plain", "method not allowed");
+    }
+
+    match req.path() {
+        // Process is alive
+        Some("/healthz") => response("200 OK", "text/plain", "ok"),
+        // Rooms are being served
+        Some("/readyz") => response(
+            "200 OK",
+            "application/json",
+            &json!({
+                "status": "ready",
+                "rooms": status.rooms,
+                "players": status.players,
+            })
+            .to_string(),
+        ),
+        Some("/version") => response(
+            "200 OK",
+            "application/json",
+            &json!({
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+                "protocols": [TAGGED_PROTOCOL, MSGPACK_PROTOCOL],
+            })
+            .to_string(),
+        ),
+        // Prometheus text format
+        Some("/metrics") => response("200 OK", "text/plain; version=0.0.4", &metrics(status)),
+        _ => response(NOT_FOUND, "text/plain", "not found"),
+    }
+}
+
+fn metrics(status: &Status) -> String {
+    let metrics = [
+        ("rooms", "gauge", "Rooms with players in them", status.rooms as u64),
+        ("players", "gauge", "Connected players, in the lobby or in rooms", status.players as u64),
+        (
+            "dropped_messages_total",
+            "counter",
+            "Lobby updates and pings dropped for clients with a full queue",
+            stats::DROPPED_MESSAGES.load(Ordering::Relaxed),
+        ),
+        (
+            "slow_disconnects_total",
+            "counter",
+            "Clients disconnected for a full queue",
+            stats::SLOW_DISCONNECTS.load(Ordering::Relaxed),
+        ),
+    ];
+
+    metrics
+        .into_iter()
+        .map(|(name, kind, help, value)| {
+            format!("# HELP tictactoe_{name} {help}\n# TYPE tictactoe_{name} {kind}\ntictactoe_{name} {value}\n")
+        })
+        .collect()
+}
+
+pub fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+// Request refused before the upgrade
+#[derive(Debug)]
+pub struct HttpError {
+    // `None` when the connection is gone and there is nobody to answer
+    pub status: Option<&'static str>,
+    pub message: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpError {
+    pub fn new(status: &'static str, message: &str) -> Self {
+        Self {
+            status: Some(status),
+            message: message.to_string(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn response(&self) -> Option<String> {
+        let status = self.status?;
+        let headers: String = self.headers.iter().map(|header| Header::fmt(&header)).collect();
+
+        Some(format!(
+            "HTTP/1.1 {status}\r\n{headers}Content-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.message.len(),
+            self.message
+        ))
+    }
+}
+
+impl From<std::io::Error> for HttpError {
+    fn from(e: std::io::Error) -> Self {
+        Self {
+            status: None,
+            message: e.to_string(),
+            headers: Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "{status}: {}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
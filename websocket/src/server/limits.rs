@@ -0,0 +1,78 @@
+// Connections open at once, in total and per source address
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use common::settings::limit::LimitSettings;
+
+use crate::server::http::{self, HttpError};
+
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+pub struct Limits {
+    max_connections: usize,
+    max_connections_per_ip: usize,
+    counts: Arc<Mutex<Counts>>,
+}
+
+// Counts a connection for as long as it's held
+pub struct Permit {
+    ip: IpAddr,
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl Limits {
+    pub fn new(settings: &LimitSettings) -> Self {
+        Self {
+            max_connections: settings.max_connections,
+            max_connections_per_ip: settings.max_connections_per_ip,
+            counts: Default::default(),
+        }
+    }
+
+    pub fn acquire(&self, ip: IpAddr) -> Result<Permit, HttpError> {
+        let mut counts = self.counts.lock().unwrap();
+        let counts = &mut *counts;
+
+        if counts.total >= self.max_connections {
+            return Err(HttpError::new(http::SERVICE_UNAVAILABLE, "too many connections").header("Retry-After", "5"));
+        }
+
+        let per_ip = counts.per_ip.entry(ip).or_default();
+
+        if *per_ip >= self.max_connections_per_ip {
+            return Err(
+                HttpError::new(http::TOO_MANY_REQUESTS, "too many connections from your address")
+                    .header("Retry-After", "5"),
+            );
+        }
+
+        *per_ip += 1;
+        counts.total += 1;
+
+        Ok(Permit {
+            ip,
+            counts: self.counts.clone(),
+        })
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.total -= 1;
+
+        if let Some(per_ip) = counts.per_ip.get_mut(&self.ip) {
+            *per_ip -= 1;
+
+            if *per_ip == 0 {
+                counts.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// Sockets connections are accepted on: TCP addresses, a Unix domain socket, or the ones systemd
+// passes when it starts the server on socket activation
+
+use std::net::SocketAddr;
+
+use tokio::sync::mpsc;
+
+use common::settings::{web::WebSocketSettings, Protocol};
+
+pub enum Listener {
+    Tcp(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+pub enum Stream {
+    Tcp(tokio::net::TcpStream),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+// Unix sockets have no peer address, so each connection gets one of its own in the discard-only
+// prefix (RFC 6666), only counting toward the total of connections
+#[cfg(unix)]
+fn unix_peer() -> SocketAddr {
+    use std::net::{IpAddr, Ipv6Addr};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static PEERS: AtomicU64 = AtomicU64::new(0);
+    let id = PEERS.fetch_add(1, Ordering::Relaxed);
+
+    SocketAddr::new(IpAddr::V6(Ipv6Addr::from((0x0100 << 112) | id as u128)), 0)
+}
+
+impl Listener {
+    pub async fn accept(&self) -> std::io::Result<(Stream, SocketAddr)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Stream::Tcp(stream), addr))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Stream::Unix(stream), unix_peer()))
+            }
+        }
+    }
+
+    // Only TCP listeners have one
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            Self::Unix(_) => None,
+        }
+    }
+
+    pub fn describe(&self, scheme: &str) -> String {
+        match self {
+            Self::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => format!("{scheme}://{addr}"),
+                Err(_) => format!("{scheme}://"),
+            },
+            #[cfg(unix)]
+            Self::Unix(listener) => {
+                let addr = listener.local_addr().ok();
+                let path = addr.as_ref().and_then(|addr| addr.as_pathname());
+
+                format!("{scheme}+unix://{}", path.unwrap_or(std::path::Path::new("")).display())
+            }
+        }
+    }
+}
+
+impl From<tokio::net::TcpListener> for Listener {
+    fn from(listener: tokio::net::TcpListener) -> Self {
+        Self::Tcp(listener)
+    }
+}
+
+#[cfg(unix)]
+impl From<tokio::net::UnixListener> for Listener {
+    fn from(listener: tokio::net::UnixListener) -> Self {
+        Self::Unix(listener)
+    }
+}
+
+impl Stream {
+    // Halves of the connection, over TLS when configured
+    pub async fn split(
+        self,
+        tls: Option<std::sync::Arc<tokio_rustls::rustls::ServerConfig>>,
+    ) -> std::io::Result<(super::Reader, super::Writer)> {
+        match (self, tls) {
+            (Self::Tcp(stream), tls) => {
+                // Moves are tiny and should go out right away
+                stream.set_nodelay(true)?;
+
+                match tls {
+                    Some(config) => secure(stream, config).await,
+                    None => {
+                        let (reader, writer) = stream.into_split();
+                        Ok((Box::new(reader), Box::new(writer)))
+                    }
+                }
+            }
+            #[cfg(unix)]
+            (Self::Unix(stream), Some(config)) => secure(stream, config).await,
+            #[cfg(unix)]
+            (Self::Unix(stream), None) => {
+                let (reader, writer) = stream.into_split();
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+        }
+    }
+}
+
+async fn secure<S>(
+    stream: S,
+    config: std::sync::Arc<tokio_rustls::rustls::ServerConfig>,
+) -> std::io::Result<(super::Reader, super::Writer)>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin + 'static,
+{
+    let (reader, writer) = tokio::io::split(tokio_rustls::TlsAcceptor::from(config).accept(stream).await?);
+
+    Ok((Box::new(reader), Box::new(writer)))
+}
+
+// The sockets systemd passed, or else every one configured
+pub async fn bind(settings: &WebSocketSettings) -> std::io::Result<Vec<Listener>> {
+    #[cfg(unix)]
+    if let Some(listeners) = systemd()? {
+        return Ok(listeners);
+    }
+
+    let mut listeners = Vec::new();
+
+    for addr in std::iter::once(settings.socket()).chain(settings.listen.iter().cloned()) {
+        let listener = tokio::net::TcpListener::bind(&addr)
+            .await
+            .map_err(|e| std::io::Error::new(e.kind(), format!("failed to listen on {addr} {e}")))?;
+
+        listeners.push(Listener::Tcp(listener));
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = &settings.unix {
+        use std::os::unix::fs::FileTypeExt;
+
+        // Left over by an earlier run
+        if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+
+        let listener = tokio::net::UnixListener::bind(path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("failed to listen on {path} {e}")))?;
+
+        listeners.push(Listener::Unix(listener));
+    }
+
+    Ok(listeners)
+}
+
+// sd_listen_fds(3), descriptors from 3 on, when they were meant for this process
+#[cfg(unix)]
+fn systemd() -> std::io::Result<Option<Vec<Listener>>> {
+    use std::os::fd::{FromRawFd, IntoRawFd};
+
+    const FIRST: i32 = 3;
+
+    let var = |name| std::env::var(name).ok().and_then(|value| value.parse::<u32>().ok());
+
+    if var("LISTEN_PID") != Some(std::process::id()) {
+        return Ok(None);
+    }
+
+    let count = match var("LISTEN_FDS") {
+        Some(count) if count > 0 => count as i32,
+        _ => return Ok(None),
+    };
+
+    (FIRST..FIRST + count)
+        .map(|fd| {
+            // SAFETY: systemd handed the descriptor over to this process, nothing else owns it
+            let inherited = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+            // Copies are closed on exec, so engine processes don't keep the socket open
+            let socket = inherited.try_clone()?;
+            drop(inherited);
+
+            socket.set_nonblocking(true)?;
+
+            // Only IP sockets have an address std understands
+            match socket.local_addr() {
+                Ok(_) => Ok(Listener::Tcp(tokio::net::TcpListener::from_std(socket)?)),
+                Err(_) => {
+                    // SAFETY: the descriptor is moved out of `socket`, which no longer owns it
+                    let socket = unsafe { std::os::unix::net::UnixListener::from_raw_fd(socket.into_raw_fd()) };
+
+                    Ok(Listener::Unix(tokio::net::UnixListener::from_std(socket)?))
+                }
+            }
+        })
+        .collect::<std::io::Result<_>>()
+        .map(Some)
+}
+
+// Hands connections over until the server stops accepting
+pub async fn run(listener: Listener, tx: mpsc::Sender<(Stream, SocketAddr)>) {
+    loop {
+        match listener.accept().await {
+            Ok(connection) => {
+                if tx.send(connection).await.is_err() {
+                    return;
+                }
+            }
+            // Out of descriptors and the like, the socket itself is still fine
+            Err(e) => {
+                log::error!("failed to accept connection {e}");
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
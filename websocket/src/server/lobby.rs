@@ -0,0 +1,163 @@
+// Task holding the sessions outside rooms and the directory of rooms, each room plays in a
+// task of its own, on this node or another
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+
+use tokio::sync::{mpsc, watch, Notify};
+use tokio::task::JoinSet;
+
+use common::settings::AppSettings;
+
+use crate::json::{ClientMessage, Command, RoomEntry, RoomId};
+use crate::server::room::{self, Room};
+use crate::server::{cluster::Cluster, http, session::SocketSession};
+
+// Where a room plays
+pub enum Host {
+    Local(mpsc::Sender<Command>),
+    // Node reached through the cluster
+    Remote(String),
+}
+
+// What the lobby knows about a room without asking it
+pub struct RoomHandle {
+    pub host: Host,
+    // Last entry the room sent
+    pub entry: RoomEntry,
+    pub code: Option<String>,
+}
+
+pub struct Lobby {
+    pub settings: Arc<AppSettings>,
+    pub book: Arc<RwLock<crate::book::Book>>,
+    pub services: Arc<crate::server::Services>,
+    pub queue: HashMap<SocketAddr, SocketSession>,
+    // Room of each client that left the lobby
+    pub members: HashMap<SocketAddr, usize>,
+    pub rooms: BTreeMap<usize, RoomHandle>,
+    // For the rooms and the cluster, unbounded so they never wait on the lobby while the lobby
+    // waits on them
+    pub mailbox: mpsc::UnboundedSender<Command>,
+    pub cluster: Cluster,
+    // Sessions of this node seated in rooms of other nodes
+    pub away: HashMap<SocketAddr, SocketSession>,
+    // Stand-ins for players of other nodes seated in rooms here, by their node and their address
+    // there
+    pub guests: HashMap<(String, SocketAddr), SocketSession>,
+    // Rooms and what forwards to guests, stopped with the lobby
+    pub tasks: JoinSet<()>,
+    status: watch::Sender<http::Status>,
+}
+
+impl Lobby {
+    pub fn new(
+        settings: Arc<AppSettings>,
+        book: Arc<RwLock<crate::book::Book>>,
+        services: Arc<crate::server::Services>,
+        mailbox: mpsc::UnboundedSender<Command>,
+        cluster: Cluster,
+        status: watch::Sender<http::Status>,
+    ) -> Self {
+        Self {
+            settings,
+            book,
+            services,
+            queue: HashMap::new(),
+            members: HashMap::new(),
+            rooms: BTreeMap::new(),
+            mailbox,
+            cluster,
+            away: HashMap::new(),
+            guests: HashMap::new(),
+            tasks: JoinSet::new(),
+            status,
+        }
+    }
+
+    // Commands of the connections in `rx`, of the rooms and the cluster in `mailbox_rx`, until
+    // told to stop
+    pub async fn run(
+        mut self,
+        mut rx: mpsc::Receiver<Command>,
+        mut mailbox_rx: mpsc::UnboundedReceiver<Command>,
+        stop: Arc<Notify>,
+    ) {
+        loop {
+            let command = tokio::select! {
+                _ = stop.notified() => break,
+                Some(_) = self.tasks.join_next() => continue,
+                Some(command) = mailbox_rx.recv() => command,
+                Some(command) = rx.recv() => command,
+                else => break,
+            };
+
+            crate::commands::lobby(command, &mut self).await;
+
+            self.status.send_replace(self.status());
+        }
+
+        self.tasks.shutdown().await;
+    }
+
+    // Starts the task of a new room, returning its mailbox
+    pub fn spawn(&mut self, id: usize, room: Room) -> mpsc::Sender<Command> {
+        let (tx, rx) = mpsc::channel::<Command>(self.settings.channels.command_capacity);
+
+        self.rooms.insert(id, RoomHandle {
+            host: Host::Local(tx.clone()),
+            entry: room.entry(id),
+            code: room.code.clone(),
+        });
+
+        let context = room::Context {
+            id,
+            settings: self.settings.clone(),
+            book: self.book.clone(),
+            services: self.services.clone(),
+            lobby: self.mailbox.clone(),
+            mailbox: tx.clone(),
+        };
+        self.tasks.spawn(room::run(room, context, rx));
+
+        tx
+    }
+
+    // Room that should handle the command instead, for clients that sent it before
+    // learning they are in one
+    pub fn seated(&self, command: &Command) -> Option<usize> {
+        let (addr, message) = match command {
+            Command::Message { session, message, .. } => (session.addr, Some(message)),
+            Command::RemoveUser { addr } | Command::Forfeit { addr } => (*addr, None),
+            _ => return None,
+        };
+
+        // Unless gone with its node
+        let room = *self.members.get(&addr).filter(|room| self.rooms.contains_key(room))?;
+
+        match message {
+            // Only the owner can delete a room, from inside of it
+            Some(ClientMessage::DeleteRoom(RoomId { id })) if *id != room => None,
+            _ => Some(room),
+        }
+    }
+
+    // Mailboxes of the rooms played on this node
+    pub fn local_rooms(&self) -> impl Iterator<Item = (&usize, &RoomHandle, &mpsc::Sender<Command>)> {
+        self.rooms.iter().filter_map(|(id, room)| match &room.host {
+            Host::Local(mailbox) => Some((id, room, mailbox)),
+            Host::Remote(_) => None,
+        })
+    }
+
+    // Of this node only, other nodes count their own
+    fn status(&self) -> http::Status {
+        http::Status {
+            rooms: self.local_rooms().filter(|(_, room, _)| room.entry.players_amount > 0).count(),
+            players: self.queue.len()
+                + self.away.len()
+                + self.local_rooms().map(|(_, room, _)| room.entry.players_amount as usize).sum::<usize>(),
+        }
+    }
+}
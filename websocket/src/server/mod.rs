@@ -1,139 +1,484 @@
 use web_socket::WebSocket;
 
-use common::settings::{AppSettings, Protocol};
+use common::settings::AppSettings;
 
 use crate::{
-    json::{Command, SocketRequest},
-    server::room::Room,
+    json::{Command, Outgoing},
     server::session::SocketSession,
 };
 
+pub mod builder;
+pub mod cluster;
+pub mod deflate;
 pub mod handshake;
+pub mod http;
+pub mod limits;
+pub mod listener;
+pub mod lobby;
+pub mod rate;
 pub mod request;
 pub mod room;
 pub mod session;
+pub mod sse;
+pub mod stats;
+
+// Halves of a plain or TLS connection
+pub type Reader = Box<dyn tokio::io::AsyncRead + Send + Unpin>;
+pub type Writer = Box<dyn tokio::io::AsyncWrite + Send + Unpin>;
 
 pub struct App {
-    pub settings: AppSettings,
-    pub rooms: Vec<Room>,
-    pub queue: Vec<SocketSession>,
-    pub cmd_tx: tokio::sync::mpsc::UnboundedSender<Command>,
-    pub cmd_rx: tokio::sync::mpsc::UnboundedReceiver<Command>,
+    pub settings: std::sync::Arc<AppSettings>,
+    pub book: std::sync::Arc<std::sync::RwLock<crate::book::Book>>,
+    pub services: std::sync::Arc<Services>,
+    // Bound by the embedding service, or else from the settings
+    listeners: Vec<listener::Listener>,
+    backend: Option<std::sync::Arc<dyn cluster::Backend>>,
+    // Stops on Ctrl-C and SIGTERM
+    signals: bool,
+}
+
+// What embedding services can swap out, shared by the lobby and the rooms
+pub struct Services {
+    pub rules: crate::rules::Registry,
+    pub storage: std::sync::Arc<dyn crate::storage::Storage>,
+    pub hooks: crate::hooks::Hooks,
+}
+
+// Server running in the background
+pub struct Handle {
+    // TCP addresses listened on
+    pub addrs: Vec<std::net::SocketAddr>,
+    status: tokio::sync::watch::Receiver<http::Status>,
+    stop: std::sync::Arc<tokio::sync::Notify>,
+    task: tokio::task::JoinHandle<std::io::Result<()>>,
+}
+
+impl Handle {
+    // As on SIGTERM, games in progress get until the shutdown deadline
+    pub fn shutdown(&self) {
+        self.stop.notify_one();
+    }
+
+    pub fn status(&self) -> http::Status {
+        self.status.borrow().clone()
+    }
+
+    // Until the server and every task it started stopped
+    pub async fn wait(self) -> std::io::Result<()> {
+        self.task.await.map_err(std::io::Error::other)?
+    }
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl App {
+    // Standalone server, configured from `application.toml` or the environment
     pub fn new() -> Self {
-        let (cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel::<Command>();
-
-        Self {
-            settings: AppSettings::new("application.toml"),
-            rooms: Vec::new(),
-            queue: Vec::new(),
-            cmd_tx,
-            cmd_rx,
-        }
+        Self::builder()
+            .settings(AppSettings::new("application.toml"))
+            .signals(true)
+            .build()
     }
 
-    pub async fn run(&mut self) -> std::io::Result<()> {
-        let listener = tokio::net::TcpListener::bind(&self.settings.websocket.socket()).await?;
-        log::info!(
-            "WebSocket server is listening on ws://{}",
-            self.settings.websocket.socket()
+    pub fn builder() -> builder::Builder {
+        builder::Builder::default()
+    }
+
+    // Starts accepting connections, failing if the server can't
+    pub async fn run(self) -> std::io::Result<Handle> {
+        let Self { settings, book, services, listeners, backend, signals } = self;
+        session::start_clock();
+
+        let listeners = if listeners.is_empty() {
+            listener::bind(&settings.websocket).await?
+        } else {
+            listeners
+        };
+
+        let mut tls = if settings.tls.enabled() {
+            Some(app_core::cert::Certificates::load(&settings.tls)?)
+        } else {
+            None
+        };
+        let mut tls_reload = tokio::time::interval(std::time::Duration::from_secs(settings.tls.reload_interval.max(1)));
+
+        let scheme = if tls.is_some() { "wss" } else { "ws" };
+        for listener in &listeners {
+            log::info!("WebSocket server is listening on {}", listener.describe(scheme));
+        }
+        let addrs = listeners.iter().filter_map(listener::Listener::local_addr).collect();
+
+        // Connections from every listener
+        let (incoming_tx, mut incoming) = tokio::sync::mpsc::channel(listeners.len());
+        let mut accepting: Vec<_> = listeners
+            .into_iter()
+            .map(|listener| tokio::spawn(listener::run(listener, incoming_tx.clone())))
+            .collect();
+        drop(incoming_tx);
+
+        // Sessions outside rooms, rooms run in tasks the lobby starts
+        let (lobby, lobby_rx) = tokio::sync::mpsc::channel::<Command>(settings.channels.command_capacity);
+        let (mailbox, mailbox_rx) = tokio::sync::mpsc::unbounded_channel::<Command>();
+        let (status_tx, status) = tokio::sync::watch::channel(http::Status::default());
+        // Other nodes sharing the lobby, if any. Closed with the server unless given by the
+        // embedding service
+        let (backend, owned) = match backend {
+            Some(backend) => (Some(backend), None),
+            None => {
+                let backend = cluster::backend(&settings.cluster).await?;
+                (backend.clone(), backend)
+            }
+        };
+        // Stopped along with the server, so nothing outlives it
+        let mut tasks = tokio::task::JoinSet::new();
+        let cluster = cluster::Cluster::join(&settings, backend, mailbox.clone(), &mut tasks).await?;
+        let lobby_stop = std::sync::Arc::new(tokio::sync::Notify::new());
+        let lobby_task = tokio::spawn(
+            lobby::Lobby::new(settings.clone(), book.clone(), services.clone(), mailbox, cluster.clone(), status_tx)
+                .run(lobby_rx, mailbox_rx, lobby_stop.clone()),
         );
 
-        loop {
-            // Channel to modify Vecs without parallelism
-            let cmd_tx = self.cmd_tx.clone();
-            let mut room_turn = tokio::time::interval(std::time::Duration::from_secs(15));
+        let limits = limits::Limits::new(&settings.limits);
+        let shared = Shared {
+            settings: settings.clone(),
+            lobby: lobby.clone(),
+            streams: sse::Streams::default(),
+        };
 
-            tokio::select! {
-                Ok((stream, addr)) = listener.accept() => {
-                    let (reader, mut writer) = stream.into_split();
-                    let mut reader = tokio::io::BufReader::new(reader);
+        let stop = std::sync::Arc::new(tokio::sync::Notify::new());
+        let shutdown = {
+            let stop = stop.clone();
 
-                    if let Err(e) = handshake::send(&mut reader, &mut writer).await {
-                        log::error!("[{addr}] failed to handshake {e}");
-                        continue;
+            async move {
+                if signals {
+                    tokio::select! {
+                        _ = shutdown_signal() => {}
+                        _ = stop.notified() => {}
                     }
+                } else {
+                    stop.notified().await
+                }
+            }
+        };
+        let handle_status = status.clone();
+
+        let task = tokio::spawn(async move {
+            tokio::pin!(shutdown);
+            // Set once asked to stop, games in progress may go on until then
+            let mut deadline: Option<tokio::time::Instant> = None;
+            let mut drain = tokio::time::interval(std::time::Duration::from_millis(250));
+            let mut connections = tokio::task::JoinSet::new();
+
+            loop {
+                tokio::select! {
+                    Some(_) = connections.join_next() => {}
+                    Some((stream, addr)) = incoming.recv() => {
+                        let (permit, refused) = match limits.acquire(addr.ip()) {
+                            Ok(permit) => (Some(permit), None),
+                            Err(e) => (None, Some(e)),
+                        };
 
-                    log::trace!("[{addr}] successfully connected");
-
-                    // Channel to send events between sockets
-                    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<SocketRequest>();
-                    let mut session: SocketSession = SocketSession::new(addr, tx);
-                    self.queue.push(session.clone());
-
-                    tokio::spawn(async move {
-                        let mut ws_writer = WebSocket::server(writer);
-                        let mut ws_reader = WebSocket::server(reader);
-
-                        let mut interval = tokio::time::interval(std::time::Duration::from_secs(20));
-
-                        loop {
-                            tokio::select! {
-                                Ok(event) = ws_reader.recv() => {
-                                    if let Err(_) = session::handle_client(
-                                        &mut session,
-                                        event,
-                                        &cmd_tx,
-                                        &mut ws_writer
-                                    ).await {
-                                        break;
-                                    }
-                                },
-                                Some(event) = rx.recv() => {
-                                    match event.opcode {
-                                        8 => {
-                                            send_message(
-                                                &cmd_tx,
-                                                Command::RemoveUser { addr }
-                                            );
-
-                                            break;
-                                        }
-                                        9 => { ws_writer.send_ping("p").await; }
-                                        _ => {
-                                            ws_writer.send(serde_json::to_string(&event).unwrap().as_str()).await;
-                                        }
-                                    }
-                                },
-                                _ = interval.tick() => {
-                                    if let Err(_) = session.heartbeat() {
-                                        send_message(
-                                            &cmd_tx,
-                                            Command::RemoveUser { addr }
-                                        );
-
-                                        break;
-                                    };
-                                }
-                            }
+                        // Handshakes in its own task, so a slow client only holds up itself
+                        connections.spawn(connect(
+                            stream,
+                            addr,
+                            permit,
+                            refused,
+                            tls.as_ref().map(|certs| certs.config()),
+                            status.borrow().clone(),
+                            shared.clone(),
+                        ));
+                    }
+                    _ = &mut shutdown, if deadline.is_none() => {
+                        let grace = std::time::Duration::from_secs(settings.shutdown.deadline);
+                        log::info!("Shutting down, games in progress have {grace:?} to finish");
+
+                        // Closes the sockets, once the tasks are gone
+                        for task in accepting.drain(..) {
+                            task.abort();
+                            let _ = task.await;
                         }
-                    });
-                }
-                Some(cmd) = self.cmd_rx.recv() =>
-                    crate::commands::handle(cmd, &mut self.rooms, &mut self.queue),
-                _ = room_turn.tick() => {
-                    for (id, room) in self.rooms.iter().enumerate() {
-                        if room.player1.is_some() && room.player2.is_some() {
-                            room.timer()
+                        deadline = Some(tokio::time::Instant::now() + grace);
+
+                        send_command(&lobby, Command::Shutdown { deadline: grace.as_secs() }).await;
+                    }
+                    _ = drain.tick(), if deadline.is_some() => {
+                        let deadline = deadline.unwrap();
+                        // Closes the sessions without a game to finish, all of them past the deadline
+                        send_command(&lobby, Command::Drain { expired: tokio::time::Instant::now() >= deadline }).await;
+                        let left = status.borrow().players;
+
+                        if left == 0 {
+                            log::info!("All connections closed");
+
+                            break;
+                        }
+
+                        // Sockets that won't even close
+                        if tokio::time::Instant::now() > deadline + std::time::Duration::from_secs(5) {
+                            log::warn!("Stopping with {left} connections left");
+
+                            break;
                         }
+                    }
+                    _ = tls_reload.tick(), if tls.is_some() => {
+                        let certs = tls.as_mut().unwrap();
 
-                        if room.player1.is_none() && room.player2.is_none() {
-                            crate::commands::notify_connections(
-                                SocketRequest::new(19, Some(crate::json::EventData::RoomDeleted { id })),
-                                &mut self.queue,
-                            );
+                        match certs.reload() {
+                            Ok(true) => log::info!("TLS certificates reloaded"),
+                            Ok(false) => {}
+                            Err(e) => log::error!("failed to reload TLS certificates {e}"),
                         }
                     }
                 }
             }
+
+            connections.shutdown().await;
+            cluster.leave().await;
+            // Rooms stop with the lobby
+            lobby_stop.notify_one();
+            let _ = lobby_task.await;
+            tasks.shutdown().await;
+            if let Some(backend) = owned {
+                backend.close().await;
+            }
+
+            Ok(())
+        });
+
+        Ok(Handle {
+            addrs,
+            status: handle_status,
+            stop,
+            task,
+        })
+    }
+}
+
+// What every connection task needs from the server
+#[derive(Clone)]
+struct Shared {
+    settings: std::sync::Arc<AppSettings>,
+    lobby: tokio::sync::mpsc::Sender<Command>,
+    streams: sse::Streams,
+}
+
+// Handshake then messages of one accepted connection, until it closes
+async fn connect(
+    stream: listener::Stream,
+    addr: std::net::SocketAddr,
+    permit: Option<limits::Permit>,
+    refused: Option<http::HttpError>,
+    tls: Option<std::sync::Arc<tokio_rustls::rustls::ServerConfig>>,
+    status: http::Status,
+    shared: Shared,
+) {
+    let Shared { settings, lobby, streams } = shared;
+
+    let upgraded = tokio::time::timeout(
+        std::time::Duration::from_secs(settings.handshake.timeout),
+        handshake::accept(stream, tls, &settings, &status, refused),
+    ).await;
+
+    let handshake::Upgraded { reader, mut writer, response, protocol, deflate: params } = match upgraded {
+        Ok(Ok(Some(handshake::Accepted::Upgraded(value)))) => value,
+        Ok(Ok(Some(handshake::Accepted::Fallback(fallback)))) => {
+            sse::serve(fallback, addr, permit, settings, lobby, streams).await;
+            return;
         }
+        // Plain HTTP request, already answered
+        Ok(Ok(None)) => return,
+        Ok(Err(e)) => {
+            log::error!("[{addr}] failed to handshake {e}");
+            return;
+        }
+        Err(_) => {
+            log::error!("[{addr}] handshake timed out");
+            return;
+        }
+    };
+
+    log::trace!("[{addr}] successfully connected");
+
+    // Channel to send events between sockets
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Outgoing>(settings.channels.session_capacity);
+    let mut session: SocketSession =
+        SocketSession::new(addr, tx, protocol, settings.channels.slow_consumer);
+    let kick = session.kick.clone();
+    send_command(&lobby, Command::Enter { session: session.clone() }).await;
+
+    // Upgraded once in the lobby, so no lobby update is missed after it
+    if let Err(e) = tokio::io::AsyncWriteExt::write_all(&mut writer, response.as_bytes()).await {
+        log::error!("[{addr}] failed to handshake {e}");
+        send_command(&lobby, Command::RemoveUser { addr }).await;
+        return;
+    }
+
+    let mut limiter = rate::Limiter::new(&settings.rates);
+    let max_message_size = settings.messages.max_size;
+    let heartbeat_interval = std::time::Duration::from_secs(settings.heartbeat.interval.max(1));
+    let heartbeat_timeout = std::time::Duration::from_secs(settings.heartbeat.timeout);
+    let mut deflate = params.map(|params| deflate::Deflate::new(params, &settings.deflate));
+
+    // Counted until the connection closes
+    let _permit = permit;
+    let mut ws_writer = WebSocket::server(writer);
+    let mut ws_reader = WebSocket::server(deflate::Reader::new(reader, deflate.is_some()));
+    ws_reader.max_payload_len = max_message_size;
+
+    let mut interval = tokio::time::interval(heartbeat_interval);
+    // Room the session is in, requests go to the lobby otherwise
+    let mut route: Option<tokio::sync::mpsc::Sender<Command>> = None;
+
+    loop {
+        tokio::select! {
+            event = ws_reader.recv() => {
+                let cmd_tx = route.as_ref().unwrap_or(&lobby);
+
+                let event = match event {
+                    // Checked by `web_socket` against `max_payload_len`, before
+                    // reading the payload
+                    Ok(web_socket::Event::Error("payload too large")) => {
+                        web_socket::Event::Error(session::TOO_LARGE)
+                    }
+                    Ok(event) => event,
+                    // Connection dropped without a close frame
+                    Err(_) => {
+                        send_command(cmd_tx, Command::RemoveUser { addr }).await;
+
+                        break;
+                    }
+                };
+
+                let event = deflate::inflate(
+                    event,
+                    deflate.as_mut(),
+                    ws_reader.stream.compressed,
+                    ws_reader.max_payload_len,
+                );
+
+                if let web_socket::Event::Error(session::TOO_LARGE) = event {
+                    let _ = ws_writer.close((web_socket::CloseCode::MessageTooBig, session::TOO_LARGE)).await;
+                    // Hanging up with the payload unread resets the connection,
+                    // and the client may never see the close frame
+                    let _ = tokio::time::timeout(
+                        std::time::Duration::from_secs(1),
+                        tokio::io::copy(&mut ws_reader.stream, &mut tokio::io::sink()),
+                    ).await;
+                    send_command(cmd_tx, Command::RemoveUser { addr }).await;
+
+                    break;
+                }
+
+                if session::handle_client(
+                    &mut session,
+                    event,
+                    cmd_tx,
+                    &mut ws_writer,
+                    &mut limiter,
+                ).await.is_err() {
+                    break;
+                }
+            },
+            Some(event) = rx.recv() => {
+                let (message, seq) = match event {
+                    Outgoing::Close { code, reason } => {
+                        let _ = ws_writer.close((code, reason)).await;
+                        send_command(route.as_ref().unwrap_or(&lobby), Command::RemoveUser { addr }).await;
+
+                        break;
+                    }
+                    Outgoing::Route(mailbox) => {
+                        route = mailbox;
+
+                        continue;
+                    }
+                    Outgoing::Ping => {
+                        if ws_writer.send_ping(session::ping_payload()).await.is_err() {
+                            break;
+                        }
+
+                        continue;
+                    }
+                    Outgoing::Message(message) => (message, None),
+                    Outgoing::Event { seq, message } => (message, Some(seq)),
+                };
+
+                let data = session.protocol.encode(message, seq);
+                // Binary formats go in binary frames
+                let mut opcode = if session.protocol.is_binary() { 2 } else { 1 };
+
+                let compressed = deflate.as_mut().and_then(|deflate| deflate.compress(&data));
+                if compressed.is_some() {
+                    opcode |= deflate::RSV1;
+                }
+
+                let frame = web_socket::Frame {
+                    fin: true,
+                    opcode,
+                    data: compressed.as_deref().unwrap_or(&data),
+                };
+
+                if ws_writer.send(frame).await.is_err() {
+                    break;
+                }
+            },
+            // Couldn't keep up with its messages
+            _ = kick.notified() => {
+                let _ = ws_writer.close((web_socket::CloseCode::PolicyViolation, "too slow")).await;
+                send_command(route.as_ref().unwrap_or(&lobby), Command::RemoveUser { addr }).await;
+
+                break;
+            }
+            _ = interval.tick() => {
+                if let Err(Outgoing::Close { code, reason }) = session.heartbeat(heartbeat_timeout) {
+                    let _ = ws_writer.close((code, reason)).await;
+                    send_command(route.as_ref().unwrap_or(&lobby), Command::RemoveUser { addr }).await;
+
+                    break;
+                };
+            }
+        }
+    }
+}
+
+// Ctrl-C, or SIGTERM from container runtimes
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                log::error!("failed to listen for SIGTERM {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        Ok(()) = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+// From the rooms and the cluster, which can't wait on the lobby as it waits on them
+pub fn send_lobby(lobby: &tokio::sync::mpsc::UnboundedSender<Command>, command: Command) {
+    if lobby.send(command).is_err() {
+        log::error!("failed to send command");
     }
 }
 
-pub fn send_message<T : std::fmt::Debug>(frame: &tokio::sync::mpsc::UnboundedSender<T>, data: T) {
-    if let Err(_) = frame.send(data) {
-        log::error!("failed to send frame message");
+// Waits for room in the queue, so busy sockets slow down instead of piling up requests
+pub async fn send_command(cmd_tx: &tokio::sync::mpsc::Sender<Command>, command: Command) {
+    if cmd_tx.send(command).await.is_err() {
+        log::error!("failed to send command");
     }
-}
\ No newline at end of file
+}
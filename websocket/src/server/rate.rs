@@ -0,0 +1,99 @@
+// Token buckets limiting what a client sends, so one can't keep the lobby and its rooms busy
+
+use std::time::{Duration, Instant};
+
+use common::settings::rate::RateSettings;
+
+use crate::json::ClientMessage;
+
+// Holds up to `capacity` tokens and earns them back steadily, each request spends one
+pub struct Bucket {
+    capacity: f64,
+    // Tokens earned per second
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    pub fn new(capacity: u32, period: Duration) -> Self {
+        Self {
+            capacity: capacity as f64,
+            rate: capacity as f64 / period.as_secs_f64(),
+            tokens: capacity as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    pub fn take(&mut self) -> bool {
+        self.refill();
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+
+    pub fn is_full(&mut self) -> bool {
+        self.refill();
+
+        self.tokens >= self.capacity
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+
+        self.tokens = (self.tokens + now.duration_since(self.updated).as_secs_f64() * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+}
+
+// Budgets of a session, kept by its socket
+pub struct Limiter {
+    messages: Bucket,
+    // Answered with the whole room list
+    list_rooms: Bucket,
+    // Announced to everyone in the lobby
+    create_room: Bucket,
+    strikes: u32,
+    max_strikes: u32,
+}
+
+impl Limiter {
+    pub fn new(settings: &RateSettings) -> Self {
+        Self {
+            messages: Bucket::new(settings.messages, Duration::from_secs(1)),
+            list_rooms: Bucket::new(settings.list_rooms, Duration::from_secs(60)),
+            create_room: Bucket::new(settings.create_room, Duration::from_secs(60)),
+            strikes: 0,
+            max_strikes: settings.strikes,
+        }
+    }
+
+    // Any frame with data, before it's read
+    pub fn frame(&mut self) -> bool {
+        // Slowed down long enough to be forgiven
+        if self.messages.is_full() {
+            self.strikes = 0;
+        }
+
+        self.messages.take()
+    }
+
+    pub fn request(&mut self, message: &ClientMessage) -> bool {
+        match message {
+            ClientMessage::ListRooms => self.list_rooms.take(),
+            ClientMessage::CreateRoom(_) => self.create_room.take(),
+            _ => true,
+        }
+    }
+
+    // Counts a refused request, true once the client should be disconnected
+    pub fn strike(&mut self) -> bool {
+        self.strikes += 1;
+
+        self.strikes > self.max_strikes
+    }
+}
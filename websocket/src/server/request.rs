@@ -2,7 +2,11 @@
 
 use std::io::ErrorKind;
 
-use tokio::io::AsyncBufReadExt;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+use common::settings::handshake::HandshakeSettings;
+
+use crate::server::http::{HttpError, BAD_REQUEST, HEADERS_TOO_LARGE};
 
 pub struct HttpRequest {
     pub prefix: String,
@@ -15,50 +19,134 @@ pub trait Header {
 }
 
 pub fn get_sec_key(req: &HttpRequest) -> Option<&String> {
-    if !req
-        .headers
-        .get("connection")?
-        .eq_ignore_ascii_case("upgrade")
-        || !req
-            .headers
-            .get("upgrade")?
-            .eq_ignore_ascii_case("websocket")
-    {
+    if !has_token(req, "connection", "upgrade") || !has_token(req, "upgrade", "websocket") {
         return None;
     }
 
     req.headers.get("sec-websocket-key")
 }
 
+// Header lists like `Connection: keep-alive, Upgrade` from Firefox
+fn has_token(req: &HttpRequest, header: &str, token: &str) -> bool {
+    req.headers
+        .get(header)
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+pub fn get_header<'a>(req: &'a HttpRequest, header: &str) -> Option<&'a str> {
+    req.headers.get(header).map(String::as_str)
+}
+
+// Whether the client asks for an upgrade at all, other requests are plain HTTP
+pub fn is_upgrade(req: &HttpRequest) -> bool {
+    req.headers.contains_key("upgrade")
+}
+
+pub fn get_protocols(req: &HttpRequest) -> impl Iterator<Item = &str> {
+    req.headers
+        .get("sec-websocket-protocol")
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+}
+
+pub fn get_extensions(req: &HttpRequest) -> impl Iterator<Item = &str> {
+    req.headers
+        .get("sec-websocket-extensions")
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+}
+
 impl HttpRequest {
-    pub async fn parse<IO>(reader: &mut IO) -> std::io::Result<Self>
+    pub fn method(&self) -> Option<&str> {
+        self.prefix.split_whitespace().next()
+    }
+
+    // Path of the request target, without the query
+    pub fn path(&self) -> Option<&str> {
+        let target = self.prefix.split_whitespace().nth(1)?;
+
+        target.split('?').next()
+    }
+
+    // Value of a parameter of the query, left encoded
+    pub fn query(&self, key: &str) -> Option<&str> {
+        let target = self.prefix.split_whitespace().nth(1)?;
+
+        target
+            .split_once('?')?
+            .1
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+    }
+
+    pub async fn parse<IO>(reader: &mut IO, settings: &HandshakeSettings) -> Result<Self, HttpError>
     where
         IO: Unpin + tokio::io::AsyncBufRead,
     {
-        let mut lines = reader.lines();
+        // Bytes left for the request line and headers
+        let mut budget = settings.max_header_size;
+
+        let prefix = read_line(reader, &mut budget).await?;
+        let mut headers = std::collections::HashMap::<String, String>::new();
 
-        let prefix = lines.next_line().await?.ok_or(std::io::Error::new(
-            ErrorKind::InvalidData,
-            "fail to get request type",
-        ))?;
-        let mut headers = std::collections::HashMap::new();
+        for count in 0.. {
+            let line = read_line(reader, &mut budget).await?;
 
-        while let Some(line) = lines.next_line().await? {
             if line.is_empty() {
                 break;
             }
 
-            let (key, value) = line.split_once(":").ok_or(std::io::Error::new(
-                ErrorKind::InvalidData,
-                "fail to split lines",
-            ))?;
-            headers.insert(key.to_ascii_lowercase(), value.trim_start().into());
+            if count >= settings.max_headers {
+                return Err(HttpError::new(HEADERS_TOO_LARGE, "too many headers"));
+            }
+
+            let (key, value) = line
+                .split_once(':')
+                .ok_or(HttpError::new(BAD_REQUEST, "malformed header"))?;
+
+            // Repeated headers are the same as a single comma separated one
+            headers
+                .entry(key.trim().to_ascii_lowercase())
+                .and_modify(|values| {
+                    values.push_str(", ");
+                    values.push_str(value.trim());
+                })
+                .or_insert_with(|| value.trim().into());
         }
 
         Ok(Self { prefix, headers })
     }
 }
 
+async fn read_line<IO>(reader: &mut IO, budget: &mut usize) -> Result<String, HttpError>
+where
+    IO: Unpin + tokio::io::AsyncBufRead,
+{
+    let mut line = Vec::new();
+    let read = (&mut *reader).take(*budget as u64).read_until(b'\n', &mut line).await?;
+
+    // Cut short by the budget, a line ending right past it included
+    if !line.ends_with(b"\n") {
+        return Err(if read == *budget {
+            HttpError::new(HEADERS_TOO_LARGE, "request header too large")
+        } else {
+            std::io::Error::new(ErrorKind::UnexpectedEof, "connection closed during handshake").into()
+        });
+    }
+
+    *budget -= read;
+    line.pop();
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+
+    String::from_utf8(line).map_err(|_| HttpError::new(BAD_REQUEST, "header is not valid UTF-8"))
+}
+
 impl<T: Header> Header for &T {
     fn fmt(this: &Self) -> String {
         T::fmt(this)
@@ -1,7 +1,78 @@
-use crate::json::{EventData, SocketRequest};
-use crate::server::send_message;
+use std::sync::{Arc, RwLock};
+
+use tokio::sync::mpsc;
+
+use common::settings::AppSettings;
+
+use crate::json::{
+    BotOffer, Command, Difficulty, ErrorCode, ErrorMessage, Latency, Outgoing, RoomEntry, RoomId,
+    RoomState, ServerMessage,
+};
+use crate::server::send_lobby;
 use crate::server::session::SocketSession;
 
+// What a room task needs besides the room itself
+pub struct Context {
+    // Index in the lobby directory
+    pub id: usize,
+    pub settings: Arc<AppSettings>,
+    pub book: Arc<RwLock<crate::book::Book>>,
+    pub services: Arc<crate::server::Services>,
+    pub lobby: mpsc::UnboundedSender<Command>,
+    // Own mailbox, for the seats joining the room
+    pub mailbox: mpsc::Sender<Command>,
+}
+
+impl Context {
+    // Keeps the lobby directory in sync and tells the lobby what changed
+    pub fn update(&self, entry: RoomEntry, message: ServerMessage) {
+        send_lobby(&self.lobby, Command::Update { entry, message });
+    }
+}
+
+pub async fn run(mut room: Room, context: Context, mut rx: mpsc::Receiver<Command>) {
+    let turn_timeout = std::time::Duration::from_secs(context.settings.rooms.turn_timeout);
+    // Often enough to notice a turn running out soon after it does
+    let mut room_turn = tokio::time::interval(
+        (turn_timeout / 2).clamp(std::time::Duration::from_millis(100), std::time::Duration::from_secs(15)),
+    );
+    let offer_bot_after = std::time::Duration::from_secs(context.settings.bots.offer_after);
+    // Once the players had a chance to answer a ping
+    let heartbeat = std::time::Duration::from_secs(context.settings.heartbeat.interval.max(1));
+    let mut latency = tokio::time::interval_at(tokio::time::Instant::now() + heartbeat, heartbeat);
+
+    loop {
+        tokio::select! {
+            Some(command) = rx.recv() => crate::commands::room(command, &mut room, &context),
+            _ = room_turn.tick() => {
+                if room.player1.is_some() && room.player2.is_some() {
+                    room.timer(turn_timeout)
+                }
+
+                room.offer_bot(offer_bot_after);
+            }
+            _ = latency.tick() => {
+                let message = ServerMessage::Latency(room.latency());
+
+                for session in room.sessions() {
+                    session.send(message.clone());
+                }
+            }
+        }
+
+        // Nobody left to play, the lobby drops the room from its directory
+        if room.players_amount() == 0 {
+            context.update(room.entry(context.id), ServerMessage::RoomDeleted(RoomId { id: context.id }));
+            break;
+        }
+    }
+
+    rx.close();
+    while let Some(command) = rx.recv().await {
+        crate::commands::closed(command, &context);
+    }
+}
+
 #[derive(Debug)]
 pub struct Room {
     pub tray: [char; 9],
@@ -10,7 +81,14 @@ pub struct Room {
     pub player1_turn: bool,
     pub duration_turn: Option<std::time::Instant>,
     pub code: Option<String>,
-    pub name: String
+    pub name: String,
+    // Positions played this match, in order
+    pub moves: Vec<usize>,
+    pub created: std::time::Instant,
+    pub bot_offered: bool,
+    // Seat taken by a backfill bot
+    pub bot: Option<std::net::SocketAddr>,
+    pub rules: crate::rules::RuleSet,
 }
 
 impl Room {
@@ -18,21 +96,36 @@ impl Room {
         player1: Option<SocketSession>,
         player2: Option<SocketSession>,
         code: Option<String>,
-        name: String
+        name: String,
+        rules: crate::rules::RuleSet,
     ) -> Self {
         Self {
             tray: [' '; 9],
-            player1: player1,
+            player1,
             player2,
             player1_turn: true,
             duration_turn: None,
             code,
-            name
+            name,
+            moves: Vec::new(),
+            created: std::time::Instant::now(),
+            bot_offered: false,
+            bot: None,
+            rules,
         }
     }
 
-    pub fn reset(self) -> Self {
-        Self::new(self.player1, self.player2, self.code, self.name)
+    // Clears the match, players stay
+    pub fn reset(&mut self) {
+        self.tray = [' '; 9];
+        self.player1_turn = true;
+        self.duration_turn = None;
+        self.moves.clear();
+        self.created = std::time::Instant::now();
+    }
+
+    pub fn players_amount(&self) -> u8 {
+        self.player1.is_some() as u8 + self.player2.is_some() as u8
     }
 
     pub fn is_available(&self) -> bool {
@@ -43,66 +136,123 @@ impl Room {
         is_player(&self.player1, addr) || is_player(&self.player2, addr)
     }
 
+    pub fn get_player(&self, addr: std::net::SocketAddr) -> Option<&SocketSession> {
+        [&self.player1, &self.player2]
+            .into_iter()
+            .flatten()
+            .find(|session| session.addr == addr)
+    }
+
     pub fn mark_position(
         &mut self,
         is_player1: bool,
         position: usize,
-    ) -> Result<(), SocketRequest> {
-        if position < 0 || position > 8 {
-            return Err(SocketRequest::new(
-                1007,
-                Some(EventData::Message("invalid position".to_string())),
-            ));
+    ) -> Result<(), ErrorMessage> {
+        if position > 8 {
+            return Err(ErrorMessage::new(ErrorCode::InvalidPosition, "invalid position"));
         }
 
         if self.player1_turn && !is_player1 || !self.player1_turn && is_player1 {
-            return Err(SocketRequest::new(
-                1007,
-                Some(EventData::Message("not your turn".to_string())),
-            ));
+            return Err(ErrorMessage::new(ErrorCode::NotYourTurn, "not your turn"));
         }
 
         if self.tray[position] != ' ' {
-            return Err(SocketRequest::new(
-                1007,
-                Some(EventData::Message("position already taken".to_string())),
-            ));
+            return Err(ErrorMessage::new(ErrorCode::PositionTaken, "position already taken"));
         }
 
         self.tray[position] = if is_player1 { 'X' } else { 'O' };
+        self.moves.push(position);
 
         Ok(())
     }
 
-    pub fn is_full(&self) -> bool {
-        !self.tray.iter().any(|square| square == &' ')
+    // Once `mover` marked a square
+    pub fn outcome(&self, mover: u8) -> Option<crate::rules::Outcome> {
+        self.rules.rules.outcome(&self.tray, mover)
     }
 
-    pub fn is_win(&self) -> bool {
-        for i in 0..=2 {
-            // Check horizontal and vertical lines
-            if is_line_equal(self.tray[i], self.tray[i + 3], self.tray[i + 6])
-                || is_line_equal(self.tray[i], self.tray[i + 1], self.tray[i + 2])
-            {
-                return true;
-            }
+    pub fn reply_event(&self, event: ServerMessage) {
+        if let (Some(player1), Some(player2)) = (&self.player1, &self.player2) {
+            player1.send_event(event.clone());
+            player2.send_event(event);
         }
+    }
 
-        // Check diagonals
-        if is_line_equal(self.tray[0], self.tray[4], self.tray[8])
-            || is_line_equal(self.tray[2], self.tray[4], self.tray[6])
-        {
-            return true;
+    // Lobby entry of this room
+    pub fn entry(&self, id: usize) -> RoomEntry {
+        RoomEntry {
+            id,
+            player_name: self.name.clone(),
+            players_amount: self.players_amount(),
+            public: self.code.is_none(),
+            bot: self.bot.is_some(),
+            rules: self.rules.name.clone(),
         }
+    }
+
+    // Everything a player needs to rebuild the match
+    pub fn state(
+        &self,
+        id: usize,
+        player: &SocketSession,
+        turn_timeout: std::time::Duration,
+    ) -> RoomState {
+        let name = |player: &Option<SocketSession>| player.as_ref().and_then(|session| session.name.clone());
 
-        false
+        RoomState {
+            id,
+            board: self
+                .tray
+                .iter()
+                .map(|&square| (square != ' ').then_some(square))
+                .collect(),
+            turn: if self.player1_turn { 1 } else { 2 },
+            you: if is_player(&self.player1, player.addr) { 1 } else { 2 },
+            player1: name(&self.player1),
+            player2: name(&self.player2),
+            turn_time_left: self
+                .duration_turn
+                .map(|duration_turn| turn_timeout.saturating_sub(duration_turn.elapsed()).as_millis() as u64),
+            moves: self.moves.clone(),
+            seq: player.seq(),
+            latency: self.latency(),
+        }
     }
 
-    pub fn reply_event(&self, event: SocketRequest) {
-        if let (Some(player1), Some(player2)) = (&self.player1, &self.player2) {
-            send_message(&player1.frame, event.clone());
-            send_message(&player2.frame, event);
+    // Players of other nodes are measured there, so they show as unknown
+    pub fn latency(&self) -> Latency {
+        let rtt = |player: &Option<SocketSession>| player.as_ref().and_then(SocketSession::rtt);
+
+        Latency {
+            player1: rtt(&self.player1),
+            player2: rtt(&self.player2),
+        }
+    }
+
+    pub fn sessions(&self) -> impl Iterator<Item = &SocketSession> {
+        self.player1.iter().chain(self.player2.iter())
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.tray.iter().any(|square| square != &' ')
+    }
+
+    pub fn offer_bot(&mut self, after: std::time::Duration) {
+        if self.bot_offered
+            || self.code.is_some()
+            || self.player1.is_some() == self.player2.is_some()
+            || self.created.elapsed() < after
+        {
+            return;
         }
+
+        let player = self.player1.as_ref().or(self.player2.as_ref()).unwrap();
+        log::trace!("[{}] offering a bot after waiting", player.addr);
+
+        player.send_event(ServerMessage::BotOffer(BotOffer {
+            levels: vec![Difficulty::Easy, Difficulty::Medium, Difficulty::Hard],
+        }));
+        self.bot_offered = true;
     }
 
     pub fn refresh_turn(&mut self) {
@@ -110,11 +260,9 @@ impl Room {
         self.player1_turn = !self.player1_turn;
     }
 
-    pub fn timer(&self) {
+    pub fn timer(&self, turn_timeout: std::time::Duration) {
         if let Some(duration_turn) = self.duration_turn {
-            if std::time::Instant::now().duration_since(duration_turn)
-                > std::time::Duration::new(30, 0)
-            {
+            if std::time::Instant::now().duration_since(duration_turn) > turn_timeout {
                 let player = if self.player1_turn {
                     &self.player1
                 } else {
@@ -126,15 +274,31 @@ impl Room {
                     player.as_ref().unwrap().addr
                 );
 
-                send_message(
-                    &player.as_ref().unwrap().frame,
-                    SocketRequest { opcode: 8, d: None },
+                player.as_ref().unwrap().send(
+                    Outgoing::Close {
+                        code: web_socket::CloseCode::PolicyViolation,
+                        reason: "turn timed out",
+                    },
                 );
             }
         }
     }
 }
 
+pub fn has_line(tray: &[char; 9]) -> bool {
+    for i in 0..=2 {
+        // Check vertical and horizontal lines
+        if is_line_equal(tray[i], tray[i + 3], tray[i + 6])
+            || is_line_equal(tray[3 * i], tray[3 * i + 1], tray[3 * i + 2])
+        {
+            return true;
+        }
+    }
+
+    // Check diagonals
+    is_line_equal(tray[0], tray[4], tray[8]) || is_line_equal(tray[2], tray[4], tray[6])
+}
+
 fn is_line_equal(a: char, b: char, c: char) -> bool {
     a == b && b == c && a != ' '
 }
@@ -142,5 +306,5 @@ fn is_line_equal(a: char, b: char, c: char) -> bool {
 pub fn is_player(player: &Option<SocketSession>, addr: std::net::SocketAddr) -> bool {
     player
         .as_ref()
-        .map_or(false, |session| session.addr == addr)
+        .is_some_and(|session| session.addr == addr)
 }
@@ -1,38 +1,112 @@
-use crate::json::{Command, SocketRequest};
-use crate::server::send_message;
+use std::sync::atomic::Ordering;
+
+use tokio::sync::mpsc::error::TrySendError;
+
+use common::settings::channel::SlowConsumer;
+
+use crate::json::{Command, Outgoing, Protocol, ServerMessage};
+use crate::server::{send_command, stats};
+
+// Reason of the close frame sent for frames over the size limit
+pub const TOO_LARGE: &str = "message too large";
+
+// Round trip not measured yet
+const NO_RTT: u64 = u64::MAX;
 
 #[derive(Clone, Debug)]
 pub struct SocketSession {
     pub addr: std::net::SocketAddr,
-    pub frame: tokio::sync::mpsc::UnboundedSender<SocketRequest>,
+    pub frame: tokio::sync::mpsc::Sender<Outgoing>,
+    // What to do when the socket can't keep up with `frame`
+    policy: SlowConsumer,
+    // Tells the socket to hang up, even with `frame` full
+    pub kick: std::sync::Arc<tokio::sync::Notify>,
+    kicked: std::sync::Arc<std::sync::atomic::AtomicBool>,
     // Last heartbeat received
     hb: std::time::Instant,
+    // Milliseconds between the last ping and its pong, seen by the rooms too
+    rtt: std::sync::Arc<std::sync::atomic::AtomicU64>,
     pub name: Option<String>,
+    pub protocol: Protocol,
+    // Room events sent since joining the current room, shared by every copy of the session
+    seq: std::sync::Arc<std::sync::atomic::AtomicU64>,
 }
 
 impl SocketSession {
     pub fn new(
         addr: std::net::SocketAddr,
-        frame: tokio::sync::mpsc::UnboundedSender<SocketRequest>,
+        frame: tokio::sync::mpsc::Sender<Outgoing>,
+        protocol: Protocol,
+        policy: SlowConsumer,
     ) -> Self {
         Self {
             addr,
             frame,
+            policy,
+            kick: Default::default(),
+            kicked: Default::default(),
             hb: std::time::Instant::now(),
+            rtt: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(NO_RTT)),
             name: None,
+            protocol,
+            seq: Default::default(),
+        }
+    }
+
+    pub fn send(&self, data: impl Into<Outgoing>) {
+        let data = match self.frame.try_send(data.into()) {
+            Ok(()) => return,
+            Err(TrySendError::Closed(_)) => {
+                log::error!("failed to send frame message");
+                return;
+            }
+            Err(TrySendError::Full(data)) => data,
+        };
+
+        let stale = match &data {
+            Outgoing::Message(message) => message.is_lobby_update() || matches!(message, ServerMessage::Latency(_)),
+            Outgoing::Ping => true,
+            _ => false,
+        };
+
+        if stale && self.policy == SlowConsumer::DropLobby {
+            stats::DROPPED_MESSAGES.fetch_add(1, Ordering::Relaxed);
+            log::trace!("[{}] queue full, dropped {data:?}", self.addr);
+        } else if !self.kicked.swap(true, Ordering::Relaxed) {
+            stats::SLOW_DISCONNECTS.fetch_add(1, Ordering::Relaxed);
+            log::warn!("[{}] queue full, disconnecting slow client", self.addr);
+
+            self.kick.notify_one();
         }
     }
 
-    pub fn heartbeat(&self) -> Result<(), SocketRequest> {
-        if std::time::Instant::now().duration_since(self.hb) > std::time::Duration::new(45, 0) {
+    pub fn send_event(&self, message: ServerMessage) {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed) + 1;
+
+        self.send(Outgoing::Event { seq, message });
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq.load(Ordering::Relaxed)
+    }
+
+    pub fn reset_seq(&self) {
+        self.seq.store(0, Ordering::Relaxed);
+    }
+
+    pub fn heartbeat(&self, timeout: std::time::Duration) -> Result<(), Outgoing> {
+        if std::time::Instant::now().duration_since(self.hb) > timeout {
             log::trace!("[{}] client heartbeat failed, disconnecting!", self.addr);
 
             // Send close event
-            return Err(SocketRequest { opcode: 8, d: None });
+            return Err(Outgoing::Close {
+                code: web_socket::CloseCode::Away,
+                reason: "heartbeat timed out",
+            });
         }
 
         // Send ping event
-        send_message(&self.frame, SocketRequest { opcode: 9, d: None });
+        self.send(Outgoing::Ping);
 
         Ok(())
     }
@@ -40,24 +114,59 @@ impl SocketSession {
     pub fn refresh_hb(&mut self) {
         self.hb = std::time::Instant::now();
     }
+
+    // Pong to one of the pings, which carry the time they were sent
+    pub fn pong(&mut self, payload: &[u8]) {
+        self.refresh_hb();
+
+        let sent = std::str::from_utf8(payload).ok().and_then(|sent| sent.parse::<u64>().ok());
+
+        // Clients may answer with anything, only pings from the past count
+        if let Some(rtt) = sent.and_then(|sent| now_millis().checked_sub(sent)) {
+            self.rtt.store(rtt, Ordering::Relaxed);
+        }
+    }
+
+    // Latest round trip in milliseconds, `None` until a ping was answered
+    pub fn rtt(&self) -> Option<u64> {
+        Some(self.rtt.load(Ordering::Relaxed)).filter(|rtt| *rtt != NO_RTT)
+    }
+}
+
+// Monotonic clock of the pings, so wall clock steps don't skew round trips
+static STARTED: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+
+// Called as the server starts, pings then carry the milliseconds since
+pub fn start_clock() {
+    STARTED.get_or_init(std::time::Instant::now);
+}
+
+// Payload of the next ping
+pub fn ping_payload() -> String {
+    now_millis().to_string()
+}
+
+fn now_millis() -> u64 {
+    STARTED.get_or_init(std::time::Instant::now).elapsed().as_millis() as u64
 }
 
 pub async fn handle_client(
     session: &mut SocketSession,
     event: web_socket::Event,
-    cmd_tx: &tokio::sync::mpsc::UnboundedSender<Command>,
-    ws_writer: &mut web_socket::WebSocket<tokio::net::tcp::OwnedWriteHalf>,
+    cmd_tx: &tokio::sync::mpsc::Sender<Command>,
+    ws_writer: &mut web_socket::WebSocket<super::Writer>,
+    limiter: &mut super::rate::Limiter,
 ) -> Result<(), ()> {
     match event {
         web_socket::Event::Data { data, .. } => {
-            crate::events::handle(session, data, cmd_tx).await;
+            crate::events::handle(session, data, cmd_tx, limiter).await?;
         }
-        web_socket::Event::Ping(_) => {
-            ws_writer.send_pong("p").await;
+        web_socket::Event::Ping(data) => {
+            let _ = ws_writer.send_pong(data).await;
         }
-        web_socket::Event::Pong(_) => session.refresh_hb(),
+        web_socket::Event::Pong(data) => session.pong(&data),
         web_socket::Event::Error(_) | web_socket::Event::Close { .. } => {
-            send_message(&cmd_tx, Command::RemoveUser { addr: session.addr });
+            send_command(cmd_tx, Command::RemoveUser { addr: session.addr }).await;
 
             return Err(());
         }
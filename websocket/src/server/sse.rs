@@ -0,0 +1,308 @@
+// Transport for clients behind proxies that break WebSockets: events come down a Server-Sent
+// Events stream, and requests go up as POSTs naming the stream. Both carry the same JSON as
+// WebSocket frames and go through the same commands
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use common::settings::{handshake::HandshakeSettings, AppSettings};
+
+use crate::json::{Command, Outgoing, Protocol};
+use crate::server::http::{self, HttpError, BAD_REQUEST};
+use crate::server::request::{self, HttpRequest};
+use crate::server::session::{self, SocketSession};
+use crate::server::{handshake, limits, rate, send_command};
+
+// `GET` opens a stream, `POST /events/<token>` sends a request on it. Streams speak the legacy
+// format unless `?protocol=` names a subprotocol, as long as it's text
+pub const PATH: &str = "/events";
+
+// Bodies posted to a stream, read as if they came in frames
+type Inbox = mpsc::Sender<Box<[u8]>>;
+
+// Inbox of each open stream, by token
+#[derive(Clone, Default)]
+pub struct Streams(Arc<Mutex<HashMap<String, Inbox>>>);
+
+impl Streams {
+    fn get(&self, token: &str) -> Option<Inbox> {
+        self.0.lock().unwrap().get(token).cloned()
+    }
+}
+
+// Connection that asked for this transport instead of an upgrade
+pub struct Fallback {
+    pub reader: tokio::io::BufReader<super::Reader>,
+    pub writer: super::Writer,
+    pub request: HttpRequest,
+}
+
+pub fn is_fallback(req: &HttpRequest) -> bool {
+    match (req.method(), req.path()) {
+        (Some("GET" | "OPTIONS"), Some(PATH)) => true,
+        (Some("POST" | "OPTIONS"), Some(path)) => token(path).is_some(),
+        _ => false,
+    }
+}
+
+// Kept from other sites as upgrades are
+pub fn validate(req: &HttpRequest, settings: &HandshakeSettings) -> Result<(), HttpError> {
+    handshake::check_origin(req, settings)?;
+    protocol(req).map(drop)
+}
+
+fn protocol(req: &HttpRequest) -> Result<Protocol, HttpError> {
+    let Some(name) = req.query("protocol") else {
+        return Ok(Protocol::Legacy);
+    };
+
+    match Protocol::from_subprotocol(name) {
+        Some(protocol) if !protocol.is_binary() => Ok(protocol),
+        Some(_) => Err(HttpError::new(BAD_REQUEST, "only text protocols fit in events")),
+        None => Err(HttpError::new(BAD_REQUEST, "unknown protocol")),
+    }
+}
+
+// Lets pages of the allowed origins read the responses, the origin is already checked
+fn cors(req: &HttpRequest) -> Vec<(&'static str, &str)> {
+    match request::get_header(req, "origin") {
+        Some(origin) => vec![("Access-Control-Allow-Origin", origin), ("Vary", "Origin")],
+        None => Vec::new(),
+    }
+}
+
+fn head(req: &HttpRequest, status: &str, headers: &[(&str, &str)]) -> String {
+    let headers: String = cors(req)
+        .iter()
+        .chain(headers)
+        .map(|(key, value)| format!("{key}: {value}\r\n"))
+        .collect();
+
+    format!("HTTP/1.1 {status}\r\n{headers}")
+}
+
+// Held open like an upgraded connection, posts are answered right away
+pub fn is_stream(req: &HttpRequest) -> bool {
+    req.method() == Some("GET")
+}
+
+fn token(path: &str) -> Option<&str> {
+    path.strip_prefix(PATH)?.strip_prefix('/').filter(|token| !token.is_empty())
+}
+
+pub async fn serve(
+    fallback: Fallback,
+    addr: SocketAddr,
+    // Counted until the stream closes
+    _permit: Option<limits::Permit>,
+    settings: Arc<AppSettings>,
+    lobby: mpsc::Sender<Command>,
+    streams: Streams,
+) {
+    // Preflight of posts, which browsers send as JSON
+    if fallback.request.method() == Some("OPTIONS") {
+        let Fallback { mut writer, request, .. } = fallback;
+        let response = head(
+            &request,
+            http::NO_CONTENT,
+            &[
+                ("Access-Control-Allow-Methods", "GET, POST"),
+                ("Access-Control-Allow-Headers", "Content-Type"),
+                ("Access-Control-Max-Age", "86400"),
+                ("Connection", "close"),
+            ],
+        );
+
+        let _ = writer.write_all(format!("{response}\r\n").as_bytes()).await;
+        let _ = writer.shutdown().await;
+
+        return;
+    }
+
+    if is_stream(&fallback.request) {
+        let token = format!("{:032x}", rand::random::<u128>());
+        let (tx, inbox) = mpsc::channel(settings.channels.command_capacity);
+        streams.0.lock().unwrap().insert(token.clone(), tx);
+
+        stream(fallback, addr, &token, inbox, &settings, lobby).await;
+
+        streams.0.lock().unwrap().remove(&token);
+        log::trace!("[{addr}] event stream closed");
+
+        return;
+    }
+
+    let Fallback { mut reader, mut writer, request } = fallback;
+    let timeout = std::time::Duration::from_secs(settings.handshake.timeout);
+    let result = match tokio::time::timeout(timeout, post(&request, &mut reader, &settings, &streams)).await {
+        Ok(result) => result,
+        Err(_) => Err(HttpError::new(http::REQUEST_TIMEOUT, "request timed out")),
+    };
+
+    let response = match result {
+        Ok(()) => {
+            let head = head(&request, http::ACCEPTED, &[("Content-Type", "text/plain"), ("Connection", "close")]);
+            format!("{head}Content-Length: 8\r\n\r\naccepted")
+        }
+        Err(e) => {
+            let e = cors(&request).into_iter().fold(e, |e, (key, value)| e.header(key, value));
+
+            match e.response() {
+                Some(response) => response,
+                None => return,
+            }
+        }
+    };
+    log::trace!("[{addr}] {} {}", request.prefix, response.lines().next().unwrap_or_default());
+
+    let _ = writer.write_all(response.as_bytes()).await;
+    let _ = writer.shutdown().await;
+}
+
+// Hands the body to the stream, answers and rejections come down the stream as well
+async fn post(
+    req: &HttpRequest,
+    reader: &mut tokio::io::BufReader<super::Reader>,
+    settings: &AppSettings,
+    streams: &Streams,
+) -> Result<(), HttpError> {
+    let not_found = || HttpError::new(http::NOT_FOUND, "stream not found");
+
+    let inbox = req.path().and_then(token).and_then(|token| streams.get(token)).ok_or_else(not_found)?;
+
+    let len: usize = request::get_header(req, "content-length")
+        .and_then(|len| len.parse().ok())
+        .ok_or(HttpError::new(http::LENGTH_REQUIRED, "content length required"))?;
+
+    if len > settings.messages.max_size {
+        return Err(HttpError::new(http::PAYLOAD_TOO_LARGE, session::TOO_LARGE));
+    }
+
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data).await?;
+
+    inbox.send(data.into_boxed_slice()).await.map_err(|_| not_found())
+}
+
+async fn stream(
+    fallback: Fallback,
+    addr: SocketAddr,
+    token: &str,
+    mut inbox: mpsc::Receiver<Box<[u8]>>,
+    settings: &AppSettings,
+    lobby: mpsc::Sender<Command>,
+) {
+    let Fallback { mut reader, mut writer, request } = fallback;
+    // Checked with the handshake
+    let protocol = protocol(&request).unwrap_or(Protocol::Legacy);
+
+    let (tx, mut rx) = mpsc::channel::<Outgoing>(settings.channels.session_capacity);
+    let mut session = SocketSession::new(addr, tx, protocol, settings.channels.slow_consumer);
+    let kick = session.kick.clone();
+    send_command(&lobby, Command::Enter { session: session.clone() }).await;
+
+    // Token first, the client can't post without it
+    let head = head(
+        &request,
+        "200 OK",
+        &[
+            ("Content-Type", "text/event-stream"),
+            ("Cache-Control", "no-cache"),
+            ("X-Accel-Buffering", "no"),
+            ("Connection", "close"),
+        ],
+    );
+    let head = format!("{head}\r\n{}", event(Some("session"), &serde_json::json!({ "token": token }).to_string()));
+    if writer.write_all(head.as_bytes()).await.is_err() {
+        send_command(&lobby, Command::RemoveUser { addr }).await;
+        return;
+    }
+
+    log::trace!("[{addr}] event stream opened");
+
+    let mut limiter = rate::Limiter::new(&settings.rates);
+    // Keeps proxies from timing out an idle stream
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(settings.heartbeat.interval.max(1)));
+    // Room the session is in, requests go to the lobby otherwise
+    let mut route: Option<mpsc::Sender<Command>> = None;
+    // Nothing more is sent after the request, only the end of the connection
+    let mut closed = [0; 1];
+
+    loop {
+        tokio::select! {
+            Some(data) = inbox.recv() => {
+                let cmd_tx = route.as_ref().unwrap_or(&lobby);
+                let _ = crate::events::handle(&mut session, data, cmd_tx, &mut limiter).await;
+            }
+            Some(outgoing) = rx.recv() => {
+                let cmd_tx = route.as_ref().unwrap_or(&lobby);
+                let (message, seq) = match outgoing {
+                    Outgoing::Close { code, reason } => {
+                        close(&mut writer, code, reason).await;
+                        send_command(cmd_tx, Command::RemoveUser { addr }).await;
+
+                        break;
+                    }
+                    Outgoing::Route(mailbox) => {
+                        route = mailbox;
+
+                        continue;
+                    }
+                    // Answered by the stream staying open
+                    Outgoing::Ping => continue,
+                    Outgoing::Message(message) => (message, None),
+                    Outgoing::Event { seq, message } => (message, Some(seq)),
+                };
+
+                let data = String::from_utf8(session.protocol.encode(message, seq)).unwrap();
+
+                if writer.write_all(event(None, &data).as_bytes()).await.is_err() {
+                    send_command(cmd_tx, Command::RemoveUser { addr }).await;
+
+                    break;
+                }
+            }
+            // Couldn't keep up with its messages
+            _ = kick.notified() => {
+                close(&mut writer, web_socket::CloseCode::PolicyViolation, "too slow").await;
+                send_command(route.as_ref().unwrap_or(&lobby), Command::RemoveUser { addr }).await;
+
+                break;
+            }
+            _ = interval.tick() => {
+                if writer.write_all(b": ping\n\n").await.is_err() {
+                    send_command(route.as_ref().unwrap_or(&lobby), Command::RemoveUser { addr }).await;
+
+                    break;
+                }
+            }
+            read = reader.read(&mut closed) => {
+                if let Ok(0) | Err(_) = read {
+                    send_command(route.as_ref().unwrap_or(&lobby), Command::RemoveUser { addr }).await;
+
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Payloads are single lines of JSON
+fn event(name: Option<&str>, data: &str) -> String {
+    match name {
+        Some(name) => format!("event: {name}\ndata: {data}\n\n"),
+        None => format!("data: {data}\n\n"),
+    }
+}
+
+// Same code and reason a WebSocket would get in its close frame
+async fn close(writer: &mut super::Writer, code: web_socket::CloseCode, reason: &str) {
+    let data = serde_json::json!({ "code": u16::from(code), "reason": reason }).to_string();
+
+    let _ = writer.write_all(event(Some("close"), &data).as_bytes()).await;
+    let _ = writer.shutdown().await;
+}
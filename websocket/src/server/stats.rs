@@ -0,0 +1,8 @@
+// Counters of the slow consumer policies, since the server started
+
+use std::sync::atomic::AtomicU64;
+
+// Lobby updates and pings not sent to clients with a full queue
+pub static DROPPED_MESSAGES: AtomicU64 = AtomicU64::new(0);
+// Clients disconnected for a full queue
+pub static SLOW_DISCONNECTS: AtomicU64 = AtomicU64::new(0);
@@ -0,0 +1,63 @@
+// Where finished games are kept, to grow the opening book across restarts
+
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+use common::settings::book::BookSettings;
+
+pub trait Storage: Send + Sync {
+    // Games stored so far, each the positions played in order, X first
+    fn load_games(&self) -> std::io::Result<Vec<Vec<usize>>>;
+
+    // Called from the room task as the game ends, so it should be quick
+    fn store_game(&self, moves: &[usize]) -> std::io::Result<()>;
+}
+
+// File with one game per line, or nothing without a path
+pub struct Files {
+    path: Option<PathBuf>,
+}
+
+impl Files {
+    pub fn new(settings: &BookSettings) -> Self {
+        Self {
+            path: settings.games.as_ref().map(PathBuf::from),
+        }
+    }
+}
+
+impl Storage for Files {
+    fn load_games(&self) -> std::io::Result<Vec<Vec<usize>>> {
+        let Some(path) = self.path.as_ref().filter(|path| path.exists()) else {
+            return Ok(Vec::new());
+        };
+
+        std::fs::read_to_string(path)?
+            .lines()
+            .map(|line| {
+                line.split_whitespace()
+                    .map(|position| {
+                        position
+                            .parse()
+                            .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "invalid stored game"))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn store_game(&self, moves: &[usize]) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let line: Vec<String> = moves.iter().map(usize::to_string).collect();
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+
+        writeln!(file, "{}", line.join(" "))
+    }
+}
@@ -0,0 +1,83 @@
+// Clients that stop reading don't make the server queue messages without end
+
+mod common;
+
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use common::{Client, Server};
+
+async fn metric(server: &Server, name: &str) -> u64 {
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    response
+        .lines()
+        .find_map(|line| line.strip_prefix(&format!("tictactoe_{name} ")))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn slow_lobby_drops_updates() {
+    let server = Server::with_env(&[
+        ("CHANNEL_SESSION_CAPACITY", "4"),
+        ("RATE_MESSAGES", "1000000"),
+        ("RATE_CREATE_ROOM", "1000000"),
+    ]);
+    let _slow = Client::connect_slow(server.addr).await;
+    let mut alice = server.connect().await;
+
+    // Every round trip broadcasts three updates to the lobby, until the socket buffers of
+    // the slow client are full
+    let mut rounds = 0;
+    while metric(&server, "dropped_messages_total").await == 0 {
+        for _ in 0..500 {
+            alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+            alice.send(14, Value::Null).await;
+            alice.expect(20).await;
+            alice.expect(19).await;
+        }
+
+        rounds += 500;
+        assert!(rounds < 50000, "nothing dropped");
+    }
+
+    assert_eq!(metric(&server, "slow_disconnects_total").await, 0);
+    assert_eq!(metric(&server, "players").await, 2);
+}
+
+#[tokio::test]
+async fn slow_replies_disconnect() {
+    let server = Server::with_env(&[
+        ("CHANNEL_SESSION_CAPACITY", "4"),
+        ("RATE_MESSAGES", "1000000"),
+        ("RATE_LIST_ROOMS", "1000000"),
+    ]);
+    let mut slow = Client::connect_slow(server.addr).await;
+
+    // Replies can't be dropped
+    let sent = tokio::time::timeout(Duration::from_secs(20), async {
+        while slow.try_send(17, Value::Null).await.is_ok() {}
+    });
+    assert!(sent.await.is_ok(), "still connected");
+
+    assert_eq!(metric(&server, "slow_disconnects_total").await, 1);
+
+    // Once its removal is handled
+    for _ in 0..20 {
+        if metric(&server, "players").await == 0 {
+            return;
+        }
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    panic!("slow client still listed");
+}
@@ -0,0 +1,133 @@
+// Opening book files, stored games and the replies served from them
+
+mod common;
+
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+
+use common::Server;
+use websocket::book::{hash, Book};
+
+fn temp_file(contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("tictactoe-book-{}", rand::random::<u64>()));
+    std::fs::write(&path, contents).unwrap();
+
+    path
+}
+
+// Replies as (position, weight)
+fn replies(book: &Book, tray: &[char; 9]) -> Vec<(usize, u32)> {
+    book.moves(tray).iter().map(|reply| (reply.position, reply.weight)).collect()
+}
+
+#[test]
+fn hashes() {
+    let mut tray = [' '; 9];
+    assert_eq!(hash(&tray), 0);
+
+    tray[0] = 'X';
+    assert_eq!(hash(&tray), 1);
+
+    tray[4] = 'O';
+    assert_eq!(hash(&tray), 1 + 2 * 81);
+}
+
+#[test]
+fn load() {
+    let path = temp_file("# empty board\n\n0 4:10 0:30 8:30\n1 0:5 4:3\n");
+    let book = Book::load(&path).unwrap();
+    let _ = std::fs::remove_file(path);
+
+    // Most played first, ties by position
+    assert_eq!(replies(&book, &[' '; 9]), vec![(0, 30), (8, 30), (4, 10)]);
+
+    // Taken squares are left out
+    let mut tray = [' '; 9];
+    tray[0] = 'X';
+    assert_eq!(replies(&book, &tray), vec![(4, 3)]);
+
+    tray[4] = 'O';
+    assert_eq!(replies(&book, &tray), vec![]);
+    assert_eq!(book.pick(&tray), None);
+
+    for contents in ["0 9:1\n", "0 4\n", "x 4:1\n", "0 4:x\n"] {
+        let path = temp_file(contents);
+        let error = Book::load(&path).unwrap_err();
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData, "{contents}");
+    }
+}
+
+#[test]
+fn games() {
+    let mut book = Book::default();
+    book.add_game(&[4, 0, 8]);
+    book.add_game(&[4, 2]);
+
+    let mut tray = [' '; 9];
+    assert_eq!(replies(&book, &tray), vec![(4, 2)]);
+    assert_eq!(book.pick(&tray), Some(4));
+
+    tray[4] = 'X';
+    assert_eq!(replies(&book, &tray), vec![(0, 1), (2, 1)]);
+
+    tray[0] = 'O';
+    assert_eq!(replies(&book, &tray), vec![(8, 1)]);
+
+    // Invalid games leave nothing behind, not even their valid start
+    let mut book = Book::default();
+    book.add_game(&[4, 0, 4]);
+    book.add_game(&[9]);
+    assert_eq!(replies(&book, &[' '; 9]), vec![]);
+}
+
+async fn book_moves(server: &Server) -> Value {
+    let mut client = server.connect().await;
+    client.send(15, json!({ "player_name": "alice", "public": true })).await;
+    client.send(27, Value::Null).await;
+
+    client.expect(27).await
+}
+
+#[tokio::test]
+async fn served_from_files() {
+    let book = temp_file("0 2:1\n");
+    let games = temp_file("4 0 8\n4 0\n");
+    let env = [("BOOK_PATH", book.to_str().unwrap()), ("BOOK_GAMES", games.to_str().unwrap())];
+
+    let server = Server::with_env(&env);
+    assert_eq!(
+        book_moves(&server).await,
+        json!({ "moves": [{ "position": 4, "weight": 2 }, { "position": 2, "weight": 1 }] })
+    );
+
+    // Finished games are stored, for the next start
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    bob.expect(18).await;
+    bob.send(12, json!({ "player_name": "bob", "room_id": 1, "room_code": null })).await;
+    alice.expect(13).await;
+    bob.expect(13).await;
+
+    for (ply, position) in [0, 3, 1, 4, 2].into_iter().enumerate() {
+        let (player, opponent) = if ply % 2 == 0 { (&mut alice, &mut bob) } else { (&mut bob, &mut alice) };
+        player.send(10, json!({ "position": position })).await;
+        opponent.expect(10).await;
+    }
+    alice.expect(11).await;
+
+    assert_eq!(std::fs::read_to_string(&games).unwrap(), "4 0 8\n4 0\n0 3 1 4 2\n");
+    drop(server);
+
+    let server = Server::with_env(&env);
+    assert_eq!(
+        book_moves(&server).await,
+        json!({ "moves": [{ "position": 4, "weight": 2 }, { "position": 0, "weight": 1 }, { "position": 2, "weight": 1 }] })
+    );
+
+    let _ = std::fs::remove_file(book);
+    let _ = std::fs::remove_file(games);
+}
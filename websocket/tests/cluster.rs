@@ -0,0 +1,246 @@
+// Nodes sharing one lobby through a broker speaking the Redis protocol
+
+mod common;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use ::common::settings::{cluster::ClusterSettings, AppSettings};
+use common::{Client, Server};
+use websocket::server::App;
+
+// Connection payloads are written to
+type Connection = mpsc::UnboundedSender<Vec<u8>>;
+
+// Just enough of Redis for the nodes: SUBSCRIBE, PUBLISH and INCR
+#[derive(Default)]
+struct Broker {
+    subscribers: Mutex<HashMap<Vec<u8>, Vec<Connection>>>,
+    counters: Mutex<HashMap<Vec<u8>, i64>>,
+}
+
+fn bulk(data: &[u8]) -> Vec<u8> {
+    let mut reply = format!("${}\r\n", data.len()).into_bytes();
+    reply.extend_from_slice(data);
+    reply.extend_from_slice(b"\r\n");
+
+    reply
+}
+
+async fn read_command(reader: &mut tokio::io::BufReader<tokio::net::tcp::OwnedReadHalf>) -> Option<Vec<Vec<u8>>> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok().filter(|read| *read > 0)?;
+    let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        line.clear();
+        reader.read_line(&mut line).await.ok()?;
+        let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg).await.ok()?;
+        arg.truncate(len);
+        args.push(arg);
+    }
+
+    Some(args)
+}
+
+async fn serve(broker: Arc<Broker>, stream: tokio::net::TcpStream) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = tokio::io::BufReader::new(reader);
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    tokio::spawn(async move {
+        while let Some(data) = rx.recv().await {
+            if writer.write_all(&data).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    while let Some(args) = read_command(&mut reader).await {
+        match args[0].to_ascii_uppercase().as_slice() {
+            b"SUBSCRIBE" => {
+                for (count, channel) in args[1..].iter().enumerate() {
+                    let mut subscribers = broker.subscribers.lock().unwrap();
+                    subscribers.entry(channel.clone()).or_default().push(tx.clone());
+
+                    let mut reply = b"*3\r\n".to_vec();
+                    reply.extend(bulk(b"subscribe"));
+                    reply.extend(bulk(channel));
+                    reply.extend(format!(":{}\r\n", count + 1).as_bytes());
+                    let _ = tx.send(reply);
+                }
+            }
+            b"PUBLISH" => {
+                let mut message = b"*3\r\n".to_vec();
+                message.extend(bulk(b"message"));
+                message.extend(bulk(&args[1]));
+                message.extend(bulk(&args[2]));
+
+                let subscribers = broker.subscribers.lock().unwrap();
+                let subscribers = subscribers.get(&args[1]).map(Vec::as_slice).unwrap_or_default();
+                for subscriber in subscribers {
+                    let _ = subscriber.send(message.clone());
+                }
+
+                let _ = tx.send(format!(":{}\r\n", subscribers.len()).into_bytes());
+            }
+            b"INCR" => {
+                let mut counters = broker.counters.lock().unwrap();
+                let counter = counters.entry(args[1].clone()).or_default();
+                *counter += 1;
+
+                let _ = tx.send(format!(":{counter}\r\n").into_bytes());
+            }
+            _ => {
+                let _ = tx.send(b"-ERR unknown command\r\n".to_vec());
+            }
+        }
+    }
+}
+
+async fn broker() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let broker = Arc::new(Broker::default());
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve(broker.clone(), stream));
+        }
+    });
+
+    format!("redis://{addr}")
+}
+
+// On a runtime of its own, so it doesn't count among the tasks of the test
+fn broker_apart() -> String {
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            tx.send(broker().await).unwrap();
+            std::future::pending::<()>().await
+        })
+    });
+
+    rx.recv().unwrap()
+}
+
+fn node(url: &str, name: &str) -> Server {
+    Server::with_env(&[("CLUSTER_REDIS_URL", url), ("CLUSTER_NODE", name)])
+}
+
+#[tokio::test]
+async fn play_across_nodes() {
+    let url = broker().await;
+    let first = node(&url, "first");
+    let second = node(&url, "second");
+
+    let mut alice = first.connect().await;
+    let mut bob = second.connect().await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    assert_eq!(
+        bob.expect(18).await,
+        json!({ "id": 0, "player_name": "alice", "players_amount": 1, "public": true, "bot": false })
+    );
+
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    assert_eq!(alice.expect(13).await, json!({ "id": 0, "name": "bob" }));
+    assert_eq!(bob.expect(13).await, json!({ "id": 1, "name": "alice" }));
+
+    alice.send(10, json!({ "position": 4 })).await;
+    assert_eq!(bob.expect(10).await, json!({ "position": 4 }));
+    bob.send(10, json!({ "position": 0 })).await;
+    assert_eq!(alice.expect(10).await, json!({ "position": 0 }));
+
+    // Ids come from the broker, so rooms of either node never clash
+    let mut lobby = first.connect().await;
+    let mut carol = second.connect().await;
+    carol.send(15, json!({ "player_name": "carol", "public": true })).await;
+    assert_eq!(lobby.expect(18).await["id"], 1);
+
+    // Nodes started later learn about the rooms already there
+    let third = node(&url, "third");
+    let mut dave = third.connect().await;
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    dave.send(17, serde_json::Value::Null).await;
+    let parties = dave.expect(17).await["parties"].clone();
+    let mut ids: Vec<_> = parties.as_array().unwrap().iter().map(|room| room["id"].as_u64().unwrap()).collect();
+    ids.sort();
+    assert_eq!(ids, [0, 1]);
+
+    drop(bob);
+    assert_eq!(alice.expect(14).await, serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn guests_sharing_an_address() {
+    let url = broker().await;
+    let sockets = [0, 1].map(|_| std::env::temp_dir().join(format!("tictactoe-{}.sock", rand::random::<u64>())));
+    let _first = Server::with_env(&[
+        ("CLUSTER_REDIS_URL", &url),
+        ("CLUSTER_NODE", "first"),
+        ("WEBSOCKET_UNIX", sockets[0].to_str().unwrap()),
+    ]);
+    let _second = Server::with_env(&[
+        ("CLUSTER_REDIS_URL", &url),
+        ("CLUSTER_NODE", "second"),
+        ("WEBSOCKET_UNIX", sockets[1].to_str().unwrap()),
+    ]);
+
+    // First peers of their node's socket, both given the same address
+    let mut alice = Client::connect_unix(&sockets[0]).await;
+    let mut bob = Client::connect_unix(&sockets[1]).await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    bob.expect(18).await;
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    assert_eq!(alice.expect(13).await, json!({ "id": 0, "name": "bob" }));
+    bob.expect(13).await;
+
+    // Each move played by its own player
+    alice.send(10, json!({ "position": 4 })).await;
+    assert_eq!(bob.expect(10).await, json!({ "position": 4 }));
+    bob.send(10, json!({ "position": 0 })).await;
+    assert_eq!(alice.expect(10).await, json!({ "position": 0 }));
+    alice.send(10, json!({ "position": 8 })).await;
+    assert_eq!(bob.expect(10).await, json!({ "position": 8 }));
+
+    for socket in sockets {
+        let _ = std::fs::remove_file(socket);
+    }
+}
+
+#[tokio::test]
+async fn stopped_node_leaves_no_task() {
+    let url = broker_apart();
+    let tasks = || tokio::runtime::Handle::current().metrics().num_alive_tasks();
+    let before = tasks();
+
+    let settings = AppSettings {
+        cluster: ClusterSettings { redis_url: Some(url), ..Default::default() },
+        ..Default::default()
+    };
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = App::builder().settings(settings).listener(listener).build().run().await.unwrap();
+
+    let mut alice = Client::connect(server.addrs[0], &[]).await;
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    alice.send(29, serde_json::Value::Null).await;
+    alice.expect(29).await;
+
+    server.shutdown();
+    tokio::time::timeout(std::time::Duration::from_secs(5), server.wait()).await.unwrap().unwrap();
+
+    assert_eq!(tasks(), before);
+}
@@ -0,0 +1,396 @@
+// Runs the server binary on an ephemeral port and talks to it through real WebSockets
+
+// Every test crate uses only part of it
+#![allow(dead_code)]
+
+use std::io::BufRead;
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use web_socket::{Event, WebSocket};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// TCP or Unix domain socket
+pub trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+pub struct Server {
+    child: std::process::Child,
+    pub addr: std::net::SocketAddr,
+}
+
+impl Server {
+    pub fn start() -> Self {
+        Self::with_env(&[])
+    }
+
+    pub fn with_env(env: &[(&str, &str)]) -> Self {
+        // Nothing to read settings from but the environment
+        let dir = std::env::temp_dir().join(format!("tictactoe-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_websocket"))
+            .current_dir(&dir)
+            .env_clear()
+            .env("RUST_LOG", "info")
+            .env("WEBSOCKET_HOST", "127.0.0.1")
+            .env("WEBSOCKET_PORT", "0")
+            .env("BOT_OFFER_AFTER", "3600")
+            .envs(env.iter().copied())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut lines = std::io::BufReader::new(child.stderr.take().unwrap()).lines();
+        let addr = loop {
+            let line = lines.next().expect("server exited").unwrap();
+
+            if let Some((_, addr)) = line.split_once("listening on ws://") {
+                break addr.trim().parse().unwrap();
+            }
+        };
+
+        // Keep the pipe drained
+        std::thread::spawn(move || lines.for_each(drop));
+
+        Self { child, addr }
+    }
+
+    pub async fn connect(&self) -> Client {
+        Client::connect(self.addr, &[]).await
+    }
+
+    // Asks the server to shut down, as container runtimes do
+    pub fn terminate(&self) {
+        let status = std::process::Command::new("kill")
+            .args(["-TERM", &self.child.id().to_string()])
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+    }
+
+    pub async fn exited(&mut self, timeout: Duration) -> std::process::ExitStatus {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return status;
+            }
+
+            assert!(tokio::time::Instant::now() < deadline, "server still running");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Sends the upgrade request, the raw response is returned
+async fn upgrade(stream: &mut Box<dyn Io>, host: &str, headers: &[(&str, &str)]) -> String {
+    let mut request = format!(
+        "GET / HTTP/1.1\r\nHost: {host}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {KEY}\r\nSec-WebSocket-Version: 13\r\n"
+    );
+    for (key, value) in headers {
+        request.push_str(&format!("{key}: {value}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    // Byte by byte, so no frame is read along with the response
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        let byte = tokio::time::timeout(TIMEOUT, stream.read_u8())
+            .await
+            .expect("handshake timed out")
+            .expect("connection closed during handshake");
+        response.push(byte);
+    }
+
+    String::from_utf8(response).unwrap()
+}
+
+fn header<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    response.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.eq_ignore_ascii_case(name).then_some(value.trim())
+    })
+}
+
+pub struct Client {
+    ws: WebSocket<Box<dyn Io>>,
+    // Raw handshake response
+    pub response: String,
+    // Code of the close frame the server sent
+    pub close_code: Option<u16>,
+}
+
+impl Client {
+    pub async fn connect(addr: std::net::SocketAddr, headers: &[(&str, &str)]) -> Self {
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.set_nodelay(true).unwrap();
+
+        Self::handshake(Box::new(stream), &addr.to_string(), headers).await
+    }
+
+    pub async fn connect_unix(path: &std::path::Path) -> Self {
+        let stream = tokio::net::UnixStream::connect(path).await.unwrap();
+
+        Self::handshake(Box::new(stream), "localhost", &[]).await
+    }
+
+    // From another loopback address, as a different client would
+    pub async fn connect_from(addr: std::net::SocketAddr, ip: std::net::Ipv4Addr) -> Self {
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind((ip, 0).into()).unwrap();
+        let stream = socket.connect(addr).await.unwrap();
+        stream.set_nodelay(true).unwrap();
+
+        Self::handshake(Box::new(stream), &addr.to_string(), &[]).await
+    }
+
+    // With a tiny receive window, so the server soon has to queue what it sends
+    pub async fn connect_slow(addr: std::net::SocketAddr) -> Self {
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.set_recv_buffer_size(1024).unwrap();
+        let stream = socket.connect(addr).await.unwrap();
+        stream.set_nodelay(true).unwrap();
+
+        Self::handshake(Box::new(stream), &addr.to_string(), &[]).await
+    }
+
+    async fn handshake(mut stream: Box<dyn Io>, host: &str, headers: &[(&str, &str)]) -> Self {
+        let response = upgrade(&mut stream, host, headers).await;
+
+        Self {
+            ws: WebSocket::client(stream),
+            response,
+            close_code: None,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.response, name)
+    }
+
+    pub async fn send(&mut self, opcode: u32, d: Value) {
+        self.send_text(&serde_json::json!({ "opcode": opcode, "d": d }).to_string())
+            .await
+    }
+
+    // Fails once the server hung up
+    pub async fn try_send(&mut self, opcode: u32, d: Value) -> std::io::Result<()> {
+        self.ws
+            .send(serde_json::json!({ "opcode": opcode, "d": d }).to_string().as_str())
+            .await
+    }
+
+    pub async fn send_text(&mut self, text: &str) {
+        self.ws.send(text).await.unwrap()
+    }
+
+    pub async fn send_binary(&mut self, data: &[u8]) {
+        self.ws.send(data).await.unwrap()
+    }
+
+    // Text frame compressed with permessage-deflate
+    pub async fn send_compressed(&mut self, text: &str) {
+        let mut compress = flate2::Compress::new(flate2::Compression::default(), false);
+        let mut data = Vec::with_capacity(text.len() + 64);
+        compress
+            .compress_vec(text.as_bytes(), &mut data, flate2::FlushCompress::Sync)
+            .unwrap();
+        // Without the empty block the extension leaves out
+        data.truncate(data.len() - 4);
+
+        let frame = web_socket::Frame { fin: true, opcode: 1 | 0x40, data: &data };
+        self.ws.send(frame).await.unwrap()
+    }
+
+    // Next data frame, `None` once the server closed the connection
+    pub async fn next(&mut self) -> Option<Vec<u8>> {
+        self.next_within(TIMEOUT).await.expect("no message from the server")
+    }
+
+    async fn next_within(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>, ()> {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        loop {
+            let event = match tokio::time::timeout_at(deadline, self.ws.recv()).await {
+                Ok(Ok(event)) => event,
+                Ok(Err(_)) => return Ok(None),
+                Err(_) => return Err(()),
+            };
+
+            match event {
+                Event::Data { data, .. } => return Ok(Some(data.into())),
+                Event::Ping(data) => self.ws.send_pong(data).await.unwrap(),
+                Event::Pong(_) => {}
+                Event::Close { code, .. } => {
+                    self.close_code = Some(code);
+                    return Ok(None);
+                }
+                Event::Error(_) => return Ok(None),
+            }
+        }
+    }
+
+    // Payload of the next ping, left unanswered
+    pub async fn ping(&mut self) -> Vec<u8> {
+        loop {
+            match tokio::time::timeout(TIMEOUT, self.ws.recv()).await {
+                Ok(Ok(Event::Ping(data))) => return data.into(),
+                Ok(Ok(Event::Data { .. } | Event::Pong(_))) => {}
+                _ => panic!("no ping from the server"),
+            }
+        }
+    }
+
+    pub async fn recv(&mut self) -> Value {
+        let data = self.next().await.expect("connection closed");
+
+        serde_json::from_slice(&data).unwrap()
+    }
+
+    // Payload of the next legacy message, which must have this opcode
+    pub async fn expect(&mut self, opcode: u32) -> Value {
+        let message = self.recv().await;
+
+        assert_eq!(message["opcode"], opcode, "unexpected message {message}");
+
+        message["d"].clone()
+    }
+
+    // Error code of the next message, which must be an error
+    pub async fn expect_error(&mut self) -> Value {
+        self.expect(1007).await
+    }
+
+    // Nothing for a while
+    pub async fn expect_silence(&mut self) {
+        if let Ok(Some(data)) = self.next_within(Duration::from_millis(300)).await {
+            panic!("unexpected message {}", String::from_utf8_lossy(&data));
+        }
+    }
+
+    pub async fn expect_closed(&mut self, timeout: Duration) {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        loop {
+            match self.next_within(deadline - tokio::time::Instant::now()).await {
+                Ok(None) => return,
+                Ok(Some(_)) => {}
+                Err(()) => panic!("connection still open"),
+            }
+        }
+    }
+}
+
+pub const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+// Empty block ending every compressed message, left out of the frames
+const TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+// Legacy client speaking permessage-deflate, frames are read and written by hand as
+// `web_socket` refuses RSV1
+pub struct Deflated {
+    stream: Box<dyn Io>,
+    compress: flate2::Compress,
+    decompress: flate2::Decompress,
+    pub response: String,
+}
+
+impl Deflated {
+    pub async fn connect(addr: std::net::SocketAddr, offer: &str) -> Self {
+        let mut stream: Box<dyn Io> = Box::new(tokio::net::TcpStream::connect(addr).await.unwrap());
+        let response = upgrade(&mut stream, &addr.to_string(), &[("Sec-WebSocket-Extensions", offer)]).await;
+
+        Self {
+            stream,
+            compress: flate2::Compress::new(flate2::Compression::default(), false),
+            decompress: flate2::Decompress::new(false),
+            response,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.response, name)
+    }
+
+    // Compressed with the window of the previous requests
+    pub async fn send(&mut self, opcode: u32, d: Value) {
+        let text = serde_json::json!({ "opcode": opcode, "d": d }).to_string();
+
+        let mut data = Vec::with_capacity(text.len() + 64);
+        self.compress
+            .compress_vec(text.as_bytes(), &mut data, flate2::FlushCompress::Sync)
+            .unwrap();
+        data.truncate(data.len() - TAIL.len());
+
+        // Final text frame with RSV1, masked as clients must
+        let mut frame = vec![0x80 | 0x40 | 0x01];
+        match data.len() {
+            len @ 0..126 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend((len as u16).to_be_bytes());
+            }
+        }
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        frame.extend(mask);
+        frame.extend(data.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+
+        self.stream.write_all(&frame).await.unwrap();
+    }
+
+    // Next legacy message, with its size on the wire when it came compressed
+    pub async fn recv(&mut self) -> (Value, Option<usize>) {
+        tokio::time::timeout(TIMEOUT, self.read()).await.expect("no message from the server")
+    }
+
+    async fn read(&mut self) -> (Value, Option<usize>) {
+        loop {
+            let mut head = [0; 2];
+            self.stream.read_exact(&mut head).await.unwrap();
+            let len = match head[1] & 0x7f {
+                126 => self.stream.read_u16().await.unwrap() as usize,
+                127 => self.stream.read_u64().await.unwrap() as usize,
+                len => len as usize,
+            };
+            let mut data = vec![0; len];
+            self.stream.read_exact(&mut data).await.unwrap();
+
+            match head[0] & 0x0f {
+                // Pings go unanswered, the tests are shorter than the heartbeat
+                0x9 | 0xa => continue,
+                0x1 => {}
+                opcode => panic!("unexpected frame {opcode}"),
+            }
+
+            if head[0] & 0x40 == 0 {
+                return (serde_json::from_slice(&data).unwrap(), None);
+            }
+
+            let input = [&data[..], &TAIL].concat();
+            let mut output = Vec::with_capacity(64 * 1024);
+            self.decompress
+                .decompress_vec(&input, &mut output, flate2::FlushDecompress::Sync)
+                .unwrap();
+
+            // Each message on its own window when the server agreed to it
+            if self.header("Sec-WebSocket-Extensions").unwrap().contains("server_no_context_takeover") {
+                self.decompress.reset(false);
+            }
+
+            return (serde_json::from_slice(&output).unwrap(), Some(len));
+        }
+    }
+}
@@ -0,0 +1,133 @@
+// Servers hosted inside the test process through the library
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::json;
+
+use common::Client;
+use websocket::hooks::GameEvent;
+use websocket::rules::{Outcome, Rules};
+use websocket::server::{cluster::memory::Memory, App, Handle};
+use websocket::storage::Storage;
+
+// Filling the middle row wins, nothing else does
+struct MiddleRow;
+
+impl Rules for MiddleRow {
+    fn outcome(&self, tray: &[char; 9], mover: u8) -> Option<Outcome> {
+        if tray[3] != ' ' && tray[3] == tray[4] && tray[4] == tray[5] {
+            Some(Outcome::Win(mover))
+        } else if tray.iter().all(|square| *square != ' ') {
+            Some(Outcome::Draw)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Games(Arc<Mutex<Vec<Vec<usize>>>>);
+
+impl Storage for Games {
+    fn load_games(&self) -> std::io::Result<Vec<Vec<usize>>> {
+        Ok(self.0.lock().unwrap().clone())
+    }
+
+    fn store_game(&self, moves: &[usize]) -> std::io::Result<()> {
+        self.0.lock().unwrap().push(moves.to_vec());
+        Ok(())
+    }
+}
+
+async fn start(backend: Arc<Memory>, games: Games, events: Arc<Mutex<Vec<GameEvent>>>) -> Handle {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    App::builder()
+        .listener(listener)
+        .rules("middle", MiddleRow)
+        .storage(games)
+        .hook(move |event| events.lock().unwrap().push(event.clone()))
+        .backend(backend)
+        .build()
+        .run()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn nodes_in_one_process() {
+    let backend = Arc::new(Memory::default());
+    let games = Games::default();
+    let events = Arc::new(Mutex::new(Vec::new()));
+
+    let first = start(backend.clone(), games.clone(), events.clone()).await;
+    let second = start(backend, games.clone(), events.clone()).await;
+
+    let mut alice = Client::connect(first.addrs[0], &[]).await;
+    let mut bob = Client::connect(second.addrs[0], &[]).await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true, "rules": "pool" })).await;
+    assert_eq!(alice.expect_error().await["code"], "unknown_rules");
+
+    alice.send(15, json!({ "player_name": "alice", "public": true, "rules": "middle" })).await;
+    assert_eq!(bob.expect(18).await["rules"], "middle");
+
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    alice.expect(13).await;
+    bob.expect(13).await;
+
+    // Alice's diagonal doesn't count, her middle row does
+    for (player, position) in [(0, 0), (1, 1), (0, 4), (1, 2), (0, 8), (1, 6), (0, 3), (1, 7), (0, 5)] {
+        let (player, opponent) = if player == 0 { (&mut alice, &mut bob) } else { (&mut bob, &mut alice) };
+
+        player.send(10, json!({ "position": position })).await;
+        assert_eq!(opponent.expect(10).await, json!({ "position": position }));
+    }
+    assert_eq!(alice.expect(11).await, json!({ "status": 1 }));
+    assert_eq!(bob.expect(11).await, json!({ "status": 1 }));
+
+    assert_eq!(*games.0.lock().unwrap(), [vec![0, 1, 4, 2, 8, 6, 3, 7, 5]]);
+    assert_eq!(first.status().rooms, 1);
+    assert_eq!(first.status().players, 2);
+
+    drop(bob);
+    alice.expect(14).await;
+
+    second.shutdown();
+    tokio::time::timeout(Duration::from_secs(5), second.wait()).await.unwrap().unwrap();
+    assert_eq!(first.status().players, 1);
+
+    let events = events.lock().unwrap();
+    assert!(matches!(&events[0], GameEvent::RoomCreated { room: 0, owner, public: true } if owner == "alice"));
+    assert!(matches!(&events[1], GameEvent::PlayerJoined { room: 0, name } if name == "bob"));
+    assert!(matches!(&events[2], GameEvent::GameEnded { room: 0, status: 1, .. }));
+}
+
+#[tokio::test]
+async fn stops_every_task() {
+    let tasks = || tokio::runtime::Handle::current().metrics().num_alive_tasks();
+    let before = tasks();
+
+    let backend = Arc::new(Memory::default());
+    let first = start(backend.clone(), Games::default(), Arc::default()).await;
+    let second = start(backend, Games::default(), Arc::default()).await;
+
+    // A room on the first node with a guest of the second
+    let mut alice = Client::connect(first.addrs[0], &[]).await;
+    let mut bob = Client::connect(second.addrs[0], &[]).await;
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    bob.expect(18).await;
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    alice.expect(13).await;
+    bob.expect(13).await;
+
+    for node in [first, second] {
+        node.shutdown();
+        tokio::time::timeout(Duration::from_secs(5), node.wait()).await.unwrap().unwrap();
+    }
+
+    assert_eq!(tasks(), before);
+}
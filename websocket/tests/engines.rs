@@ -0,0 +1,150 @@
+// External engines taking a seat, played by shell scripts
+
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde_json::json;
+
+use ::common::settings::{engine::EngineSettings, AppSettings};
+use common::Client;
+use websocket::server::{App, Handle};
+
+// Plays the first free square, and writes `quit` to the file of its first argument when told to
+const FIRST_FREE: &str = r#"
+while read -r command args; do
+    case "$command" in
+        tictactoe) echo tictactoeok ;;
+        position) board=${args%% *} ;;
+        go) taken=${board%%-*}; echo "bestmove ${#taken}" ;;
+        quit) echo quit > "$1"; exit 0 ;;
+    esac
+done
+"#;
+
+fn engine(name: &str, script: &str, quit: &Path) -> EngineSettings {
+    EngineSettings {
+        name: name.to_string(),
+        command: "sh".to_string(),
+        args: vec!["-c".to_string(), script.to_string(), name.to_string(), quit.display().to_string()],
+        move_time: 100,
+        timeout: 1000,
+    }
+}
+
+fn temp_path() -> PathBuf {
+    std::env::temp_dir().join(format!("tictactoe-engine-{}", rand::random::<u64>()))
+}
+
+async fn start(engines: Vec<EngineSettings>) -> Handle {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    App::builder()
+        .settings(AppSettings { engines, ..Default::default() })
+        .listener(listener)
+        .build()
+        .run()
+        .await
+        .unwrap()
+}
+
+// Checked every 50ms, for up to 5s
+async fn eventually(what: &str, mut check: impl FnMut() -> bool) {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+
+    while !check() {
+        assert!(tokio::time::Instant::now() < deadline, "{what}");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn released_with_the_room() {
+    let quit = temp_path();
+    let server = start(vec![engine("first", FIRST_FREE, &quit)]).await;
+    let mut alice = Client::connect(server.addrs[0], &[]).await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    alice.send(23, json!({ "engine": "first" })).await;
+    assert_eq!(alice.expect(13).await["name"], "first");
+    eventually("engine not seated", || server.status().players == 2).await;
+
+    alice.send(16, json!({ "id": 0 })).await;
+    assert_eq!(alice.expect(19).await, json!({ "id": 0 }));
+
+    // Told to quit, and not back in the lobby
+    eventually("engine not released", || quit.exists()).await;
+    assert_eq!(server.status().players, 1);
+
+    let _ = std::fs::remove_file(quit);
+}
+
+// Answers the handshake, then does whatever `go` is given
+fn playing(go: &str) -> String {
+    format!(
+        r#"
+while read -r command args; do
+    case "$command" in
+        tictactoe) echo tictactoeok ;;
+        go) {go} ;;
+        quit) exit 0 ;;
+    esac
+done
+"#
+    )
+}
+
+// Alice opens against the engine, which loses its seat on its answer
+async fn forfeited(engine: EngineSettings) {
+    let server = start(vec![engine]).await;
+    let mut alice = Client::connect(server.addrs[0], &[]).await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    alice.send(23, json!({ "engine": "faulty" })).await;
+    assert_eq!(alice.expect(13).await["name"], "faulty");
+
+    alice.send(10, json!({ "position": 4 })).await;
+    assert_eq!(alice.expect(11).await, json!({ "status": 1 }));
+    eventually("engine still seated", || server.status().players == 1).await;
+}
+
+#[tokio::test]
+async fn crash_forfeits() {
+    forfeited(engine("faulty", &playing("exit 1"), &temp_path())).await;
+}
+
+#[tokio::test]
+async fn illegal_move_forfeits() {
+    // Alice's square
+    forfeited(engine("faulty", &playing("echo 'bestmove 4'"), &temp_path())).await;
+}
+
+#[tokio::test]
+async fn timeout_forfeits() {
+    // Chatty, but never moving
+    forfeited(engine("faulty", &playing("while true; do echo info; sleep 0.2; done"), &temp_path())).await;
+}
+
+#[tokio::test]
+async fn long_line_forfeits() {
+    let script = playing("head -c 100000 /dev/zero | tr '\\0' a; echo; sleep 60");
+
+    // Well before it would time out
+    forfeited(EngineSettings { timeout: 60_000, ..engine("faulty", &script, &temp_path()) }).await;
+}
+
+#[tokio::test]
+async fn stalled_handshake() {
+    let quit = temp_path();
+    let script = "while true; do echo 'id name slow'; sleep 0.2; done";
+    let server = start(vec![engine("slow", script, &quit)]).await;
+    let mut alice = Client::connect(server.addrs[0], &[]).await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    alice.send(23, json!({ "engine": "slow" })).await;
+    assert_eq!(alice.expect(13).await["name"], "slow");
+
+    assert_eq!(alice.expect(11).await, json!({ "status": 1 }));
+    eventually("engine still seated", || server.status().players == 1).await;
+}
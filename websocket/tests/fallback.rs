@@ -0,0 +1,200 @@
+// Server-Sent Events with HTTP posts, for clients that can't open WebSockets
+
+mod common;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+use common::Server;
+
+struct EventStream {
+    reader: tokio::io::BufReader<tokio::net::TcpStream>,
+    addr: std::net::SocketAddr,
+    token: String,
+}
+
+async fn request(server: &Server, request: &str) -> String {
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    response
+}
+
+impl EventStream {
+    async fn open(server: &Server) -> Self {
+        Self::open_with(server, "/events", "").await.0
+    }
+
+    // With the head of the response
+    async fn open_with(server: &Server, target: &str, headers: &str) -> (Self, String) {
+        let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+        let request = format!("GET {target} HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\n{headers}\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut reader = tokio::io::BufReader::new(stream);
+        let mut head = String::new();
+        reader.read_line(&mut head).await.unwrap();
+        assert_eq!(head, "HTTP/1.1 200 OK\r\n");
+
+        // Rest of the head
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).await.unwrap();
+        }
+
+        let mut stream = Self {
+            reader,
+            addr: server.addr,
+            token: String::new(),
+        };
+
+        let (name, data) = stream.next().await;
+        assert_eq!(name.as_deref(), Some("session"));
+        stream.token = data["token"].as_str().unwrap().to_string();
+
+        (stream, head)
+    }
+
+    // Name and data of the next event, skipping comments
+    async fn next(&mut self) -> (Option<String>, Value) {
+        let mut name = None;
+        let mut data = None;
+
+        loop {
+            let mut line = String::new();
+            let read = tokio::time::timeout(std::time::Duration::from_secs(5), self.reader.read_line(&mut line))
+                .await
+                .expect("no event")
+                .unwrap();
+            assert!(read > 0, "stream closed");
+
+            match line.trim_end_matches('\n').split_once(": ") {
+                Some(("event", value)) => name = Some(value.to_string()),
+                Some(("data", value)) => data = Some(serde_json::from_str(value).unwrap()),
+                _ if line == "\n" && data.is_some() => return (name, data.unwrap()),
+                _ => {}
+            }
+        }
+    }
+
+    // Payload of the next legacy message, which must have this opcode
+    async fn expect(&mut self, opcode: u32) -> Value {
+        let (name, message) = self.next().await;
+        assert_eq!(name, None);
+        assert_eq!(message["opcode"], opcode, "unexpected message {message}");
+
+        message["d"].clone()
+    }
+
+    async fn post(&self, token: &str, body: &str) -> String {
+        let mut stream = tokio::net::TcpStream::connect(self.addr).await.unwrap();
+        let request = format!(
+            "POST /events/{token} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        response
+    }
+
+    async fn send(&self, opcode: u32, data: Value) {
+        let response = self.post(&self.token, &json!({ "opcode": opcode, "d": data }).to_string()).await;
+        assert!(response.starts_with("HTTP/1.1 202 Accepted\r\n"), "{response}");
+    }
+}
+
+#[tokio::test]
+async fn plays_with_websockets() {
+    let server = Server::start();
+    let mut alice = server.connect().await;
+    let mut bob = EventStream::open(&server).await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    assert_eq!(bob.expect(18).await["player_name"], "alice");
+
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    assert_eq!(alice.expect(13).await, json!({ "id": 0, "name": "bob" }));
+    assert_eq!(bob.expect(13).await, json!({ "id": 1, "name": "alice" }));
+
+    alice.send(10, json!({ "position": 4 })).await;
+    assert_eq!(bob.expect(10).await, json!({ "position": 4 }));
+
+    bob.send(10, json!({ "position": 0 })).await;
+    assert_eq!(alice.expect(10).await, json!({ "position": 0 }));
+
+    // Gone once the stream closes
+    drop(bob);
+    assert_eq!(alice.expect(14).await, Value::Null);
+}
+
+#[tokio::test]
+async fn rejected_posts() {
+    let server = Server::with_env(&[("MESSAGE_MAX_SIZE", "64")]);
+    let mut stream = EventStream::open(&server).await;
+
+    let response = stream.post("unknown", "{}").await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    let response = stream.post(&stream.token, &" ".repeat(65)).await;
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+    // Decoding errors come down the stream, as they would in a frame
+    stream.send(99, Value::Null).await;
+    assert_eq!(stream.expect(1007).await["code"], "invalid_message");
+}
+
+#[tokio::test]
+async fn origins() {
+    let server = Server::with_env(&[("HANDSHAKE_ALLOWED_ORIGINS", "http://localhost:5173")]);
+
+    let response = request(&server, "GET /events HTTP/1.1\r\nHost: localhost\r\nOrigin: https://evil.example\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{response}");
+
+    let (stream, head) = EventStream::open_with(&server, "/events", "Origin: http://localhost:5173\r\n").await;
+    assert!(head.contains("\r\nAccess-Control-Allow-Origin: http://localhost:5173\r\n"), "{head}");
+
+    let post = format!(
+        "POST /events/{} HTTP/1.1\r\nHost: localhost\r\nOrigin: https://evil.example\r\nContent-Length: 2\r\n\r\n{{}}",
+        stream.token
+    );
+    assert!(request(&server, &post).await.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+
+    // Preflight of a JSON post
+    let preflight = format!(
+        "OPTIONS /events/{} HTTP/1.1\r\nHost: localhost\r\nOrigin: http://localhost:5173\r\nAccess-Control-Request-Method: POST\r\nAccess-Control-Request-Headers: content-type\r\n\r\n",
+        stream.token
+    );
+    let response = request(&server, &preflight).await;
+    assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"), "{response}");
+    assert!(response.contains("\r\nAccess-Control-Allow-Origin: http://localhost:5173\r\n"));
+    assert!(response.contains("\r\nAccess-Control-Allow-Methods: GET, POST\r\n"));
+    assert!(response.contains("\r\nAccess-Control-Allow-Headers: Content-Type\r\n"));
+
+    let post = format!(
+        "POST /events/{} HTTP/1.1\r\nHost: localhost\r\nOrigin: http://localhost:5173\r\nContent-Length: 2\r\n\r\n{{}}",
+        stream.token
+    );
+    let response = request(&server, &post).await;
+    assert!(response.starts_with("HTTP/1.1 202 Accepted\r\n"), "{response}");
+    assert!(response.contains("\r\nAccess-Control-Allow-Origin: http://localhost:5173\r\n"));
+}
+
+#[tokio::test]
+async fn protocols() {
+    let server = Server::start();
+    let (mut stream, _) = EventStream::open_with(&server, "/events?protocol=tictactoe.json", "").await;
+
+    let body = r#"{"type": "create_room", "player_name": "alice", "public": false, "id": 1}"#;
+    assert!(stream.post(&stream.token, body).await.starts_with("HTTP/1.1 202 Accepted\r\n"));
+    assert_eq!(stream.next().await.1["type"], "owner_code");
+    assert_eq!(stream.next().await.1, json!({ "type": "ack", "id": 1, "opcode": 15, "request": "create_room" }));
+
+    // Binary frames don't fit in events
+    let response = request(&server, "GET /events?protocol=tictactoe.msgpack HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{response}");
+}
@@ -0,0 +1,208 @@
+// Upgrade requests refused with an HTTP error instead of a WebSocket
+
+mod common;
+
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use common::{Client, Server, KEY};
+
+async fn upgrade(server: &Server, headers: &str) -> String {
+    request(server, &format!("GET / HTTP/1.1\r\n{headers}\r\n")).await
+}
+
+async fn request(server: &Server, request: &str) -> String {
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    // The server may hang up before reading everything, resetting the connection
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response).await;
+
+    String::from_utf8(response).unwrap()
+}
+
+fn headers(except: &str) -> String {
+    [
+        "Host: localhost".to_string(),
+        "Upgrade: websocket".to_string(),
+        "Connection: Upgrade".to_string(),
+        format!("Sec-WebSocket-Key: {KEY}"),
+        "Sec-WebSocket-Version: 13".to_string(),
+    ]
+    .into_iter()
+    .filter(|header| !header.starts_with(except))
+    .map(|header| header + "\r\n")
+    .collect()
+}
+
+#[tokio::test]
+async fn connection_tokens() {
+    let server = Server::start();
+
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    // As sent by Firefox
+    let request = format!("GET / HTTP/1.1\r\n{}Connection: keep-alive, Upgrade\r\n\r\n", headers("Connection"));
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = [0; 16];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(&response, b"HTTP/1.1 101 Swi");
+}
+
+#[tokio::test]
+async fn bad_requests() {
+    let server = Server::start();
+
+    let response = upgrade(&server, &headers("Host")).await;
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{response}");
+    assert!(response.ends_with("host not found"));
+
+    let response = upgrade(&server, &headers("Sec-WebSocket-Key")).await;
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+    let response = upgrade(&server, &(headers("Sec-WebSocket-Key") + "Sec-WebSocket-Key: short\r\n")).await;
+    assert!(response.ends_with("invalid security key"));
+
+    let response = request(&server, &format!("GET / HTTP/1.0\r\n{}\r\n", headers("-"))).await;
+    assert!(response.ends_with("invalid request line"));
+
+    let response = request(&server, &format!("POST / HTTP/1.1\r\n{}\r\n", headers("-"))).await;
+    assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\n"));
+
+    let response = upgrade(&server, &(headers("-") + "no colon\r\n")).await;
+    assert!(response.ends_with("malformed header"));
+}
+
+#[tokio::test]
+async fn version() {
+    let server = Server::start();
+
+    let response = upgrade(&server, &(headers("Sec-WebSocket-Version") + "Sec-WebSocket-Version: 8\r\n")).await;
+    assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: 13\r\n"));
+}
+
+#[tokio::test]
+async fn origins() {
+    let server = Server::with_env(&[("HANDSHAKE_ALLOWED_ORIGINS", "https://tictactoe.example, http://localhost:5173")]);
+
+    let response = upgrade(&server, &(headers("-") + "Origin: https://evil.example\r\n")).await;
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+
+    let client = Client::connect(server.addr, &[("Origin", "http://localhost:5173")]).await;
+    assert!(client.response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+
+    // Not a browser
+    server.connect().await;
+}
+
+#[tokio::test]
+async fn limits() {
+    let server = Server::with_env(&[("HANDSHAKE_MAX_HEADER_SIZE", "512"), ("HANDSHAKE_MAX_HEADERS", "8")]);
+
+    let response = upgrade(&server, &(headers("-") + &format!("Cookie: {}\r\n", "a".repeat(512)))).await;
+    assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+
+    let response = upgrade(&server, &(headers("-") + &"X-Header: 1\r\n".repeat(4))).await;
+    assert!(response.ends_with("too many headers"));
+
+    server.connect().await;
+}
+
+#[tokio::test]
+async fn header_size_boundary() {
+    let server = Server::with_env(&[("HANDSHAKE_MAX_HEADER_SIZE", "512")]);
+
+    // The whole head, blank line included, padded to `size` bytes
+    let head = |size: usize| {
+        let head = format!("GET / HTTP/1.1\r\n{}X-Pad: \r\n\r\n", headers("-"));
+        head.replace("X-Pad: ", &format!("X-Pad: {}", "a".repeat(size - head.len())))
+    };
+
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(head(512).as_bytes()).await.unwrap();
+    let mut response = [0; 16];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(&response, b"HTTP/1.1 101 Swi");
+
+    let response = request(&server, &head(513)).await;
+    assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"), "{response}");
+
+    // A complete request line, one byte over
+    let line = format!("GET /{} HTTP/1.1\r\n", "a".repeat(513 - 16));
+    let response = request(&server, &line).await;
+    assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"), "{response}");
+
+    server.connect().await;
+}
+
+#[tokio::test]
+async fn timeout() {
+    let server = Server::with_env(&[("HANDSHAKE_TIMEOUT", "1")]);
+
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+
+    let mut response = Vec::new();
+    let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response)).await;
+    assert!(read.is_ok() && response.is_empty());
+
+    // Back to accepting connections
+    server.connect().await;
+}
+
+#[tokio::test]
+async fn idle_connections() {
+    let server = Server::with_env(&[("HANDSHAKE_TIMEOUT", "30")]);
+
+    // Connected but never sending its request
+    let _idle = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    let mut half = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    half.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+
+    // Others don't wait for them
+    let probe = tokio::time::timeout(Duration::from_secs(2), request(&server, "GET /healthz HTTP/1.1\r\n\r\n")).await;
+    assert!(probe.unwrap().starts_with("HTTP/1.1 200"));
+
+    tokio::time::timeout(Duration::from_secs(2), server.connect()).await.unwrap();
+}
+
+#[tokio::test]
+async fn connection_limits() {
+    let server = Server::with_env(&[("LIMIT_MAX_CONNECTIONS", "3"), ("LIMIT_MAX_CONNECTIONS_PER_IP", "2")]);
+
+    let first = server.connect().await;
+    let _second = server.connect().await;
+
+    let response = upgrade(&server, &headers("-")).await;
+    assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\nRetry-After: 5\r\n"), "{response}");
+
+    // Probes don't take a connection
+    let response = request(&server, "GET /healthz HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let _third = Client::connect_from(server.addr, [127, 0, 0, 2].into()).await;
+
+    let response = upgrade(&server, &headers("-")).await;
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\nRetry-After: 5\r\n"), "{response}");
+
+    // Given back once the connection closes
+    drop(first);
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+        stream.write_all(format!("GET / HTTP/1.1\r\n{}\r\n", headers("-")).as_bytes()).await.unwrap();
+
+        let mut response = [0; 12];
+        stream.read_exact(&mut response).await.unwrap();
+
+        if &response == b"HTTP/1.1 101" {
+            break;
+        }
+
+        assert!(tokio::time::Instant::now() < deadline, "connection still counted");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
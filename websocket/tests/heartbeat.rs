@@ -0,0 +1,60 @@
+// Pings on the configured interval, carrying the time they were sent to measure round trips
+
+mod common;
+
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use common::Server;
+
+#[tokio::test]
+async fn latency_in_room() {
+    let server = Server::with_env(&[("HEARTBEAT_INTERVAL", "1")]);
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    // Read past the ping sent as they connected, so it's answered before they take a seat
+    for player in [&mut alice, &mut bob] {
+        player.send(17, Value::Null).await;
+        player.expect(17).await;
+    }
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    bob.expect(18).await;
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    alice.expect(13).await;
+    bob.expect(13).await;
+
+    // Both answered the ping sent as they connected
+    for player in [&mut alice, &mut bob] {
+        let latency = player.expect(31).await;
+        assert!(latency["player1"].is_u64() && latency["player2"].is_u64(), "{latency}");
+    }
+
+    alice.send(29, Value::Null).await;
+    let state = alice.expect(29).await;
+    assert!(state["latency"]["player1"].as_u64().unwrap() < 1000);
+}
+
+#[tokio::test]
+async fn unanswered_pings() {
+    let server = Server::with_env(&[("HEARTBEAT_INTERVAL", "1"), ("HEARTBEAT_TIMEOUT", "1")]);
+    let mut client = server.connect().await;
+
+    // Not reading, so no pong goes back
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    client.expect_closed(Duration::from_secs(5)).await;
+    assert_eq!(client.close_code, Some(1001));
+}
+
+#[tokio::test]
+async fn pings_carry_the_server_clock() {
+    let server = Server::with_env(&[("HEARTBEAT_INTERVAL", "1")]);
+    let mut client = server.connect().await;
+
+    // Milliseconds since the server started, not the wall clock
+    let sent: u64 = String::from_utf8(client.ping().await).unwrap().parse().unwrap();
+    assert!(sent < 60_000, "{sent}");
+}
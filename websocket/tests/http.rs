@@ -0,0 +1,68 @@
+// Probes answered on the WebSocket port without upgrading
+
+mod common;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use common::{Client, Server};
+
+async fn get(server: &Server, request: &str) -> String {
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    response
+}
+
+#[tokio::test]
+async fn health() {
+    let server = Server::start();
+
+    let response = get(&server, "GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nok"));
+}
+
+#[tokio::test]
+async fn readiness() {
+    let server = Server::start();
+    let mut client = server.connect().await;
+
+    client.send(15, serde_json::json!({ "player_name": "alice", "public": true })).await;
+    client.send(17, serde_json::Value::Null).await;
+    client.expect_error().await;
+
+    let response = get(&server, "GET /readyz HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(r#"{"players":1,"rooms":1,"status":"ready"}"#));
+}
+
+#[tokio::test]
+async fn version() {
+    let server = Server::start();
+
+    let response = get(&server, "GET /version?format=json HTTP/1.1\r\n\r\n").await;
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let body: serde_json::Value = serde_json::from_str(body).unwrap();
+
+    assert_eq!(body["name"], "websocket");
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+}
+
+#[tokio::test]
+async fn unknown() {
+    let server = Server::start();
+
+    let response = get(&server, "GET /rooms HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    let response = get(&server, "POST /healthz HTTP/1.1\r\nContent-Length: 0\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+
+    // Upgrades still work on the same port
+    let mut client = Client::connect(server.addr, &[]).await;
+    client.send(17, serde_json::Value::Null).await;
+    client.expect(17).await;
+}
@@ -0,0 +1,43 @@
+// Sockets listened on besides the configured host and port
+
+mod common;
+
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use common::{Client, Server, KEY};
+
+#[tokio::test]
+async fn ipv6_and_unix() {
+    let path = std::env::temp_dir().join(format!("tictactoe-{}.sock", rand::random::<u64>()));
+    // Free for the server to take
+    let port = std::net::TcpListener::bind("[::1]:0").unwrap().local_addr().unwrap().port();
+
+    let server = Server::with_env(&[
+        ("WEBSOCKET_LISTEN", &format!("[::1]:{port}")),
+        ("WEBSOCKET_UNIX", path.to_str().unwrap()),
+    ]);
+
+    let mut client = Client::connect(format!("[::1]:{port}").parse().unwrap(), &[]).await;
+    client.send(17, Value::Null).await;
+    assert_eq!(client.expect(17).await["parties"], serde_json::json!([]));
+
+    let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    stream.write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.ends_with("\r\n\r\nok"));
+
+    // Upgrades as well
+    let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    let request = format!(
+        "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {KEY}\r\nSec-WebSocket-Version: 13\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = [0; 32];
+    stream.read_exact(&mut response).await.unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 101 Switching Protocols"));
+
+    drop(server);
+    let _ = std::fs::remove_file(path);
+}
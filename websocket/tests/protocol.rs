@@ -0,0 +1,748 @@
+// Scripted client conversations covering every opcode of the legacy protocol, plus the
+// subprotocols and extensions negotiated in the handshake
+
+mod common;
+
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use common::{Client, Deflated, Server};
+
+// Alice creates a public room and Bob joins it, `lobby` watches from the lobby
+async fn match_room(server: &Server) -> (Client, Client, Client) {
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    let mut lobby = server.connect().await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    assert_eq!(
+        bob.expect(18).await,
+        json!({ "id": 0, "player_name": "alice", "players_amount": 1, "public": true, "bot": false })
+    );
+    lobby.expect(18).await;
+
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    assert_eq!(alice.expect(13).await, json!({ "id": 0, "name": "bob" }));
+    assert_eq!(bob.expect(13).await, json!({ "id": 1, "name": "alice" }));
+    assert_eq!(lobby.expect(13).await, json!({ "id": 0, "name": null }));
+
+    (alice, bob, lobby)
+}
+
+// Plays the moves alternating, alice first, checking each reaches the opponent
+async fn play(alice: &mut Client, bob: &mut Client, moves: &[usize]) {
+    for (ply, &position) in moves.iter().enumerate() {
+        let (player, opponent) = if ply % 2 == 0 {
+            (&mut *alice, &mut *bob)
+        } else {
+            (&mut *bob, &mut *alice)
+        };
+
+        player.send(10, json!({ "position": position })).await;
+        assert_eq!(opponent.expect(10).await, json!({ "position": position }));
+    }
+}
+
+#[tokio::test]
+async fn handshake() {
+    let server = Server::start();
+
+    let client = server.connect().await;
+    assert!(client.response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert_eq!(client.header("Sec-WebSocket-Accept"), Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+    assert_eq!(client.header("Sec-WebSocket-Protocol"), None);
+    assert_eq!(client.header("Sec-WebSocket-Extensions"), None);
+
+    let client = Client::connect(
+        server.addr,
+        &[("Sec-WebSocket-Protocol", "chat, tictactoe.msgpack, tictactoe.json")],
+    )
+    .await;
+    assert_eq!(client.header("Sec-WebSocket-Protocol"), Some("tictactoe.msgpack"));
+
+    let client = Client::connect(
+        server.addr,
+        &[("Sec-WebSocket-Extensions", "permessage-deflate; client_max_window_bits")],
+    )
+    .await;
+    assert_eq!(client.header("Sec-WebSocket-Extensions"), Some("permessage-deflate"));
+
+    // Windows smaller than ours can't be honored
+    let client = Client::connect(
+        server.addr,
+        &[("Sec-WebSocket-Extensions", "permessage-deflate; server_max_window_bits=10")],
+    )
+    .await;
+    assert_eq!(client.header("Sec-WebSocket-Extensions"), None);
+}
+
+#[tokio::test]
+async fn create_and_list() {
+    let server = Server::start();
+    let mut alice = server.connect().await;
+    let mut lobby = server.connect().await;
+
+    lobby.send(17, Value::Null).await;
+    assert_eq!(lobby.expect(17).await, json!({ "parties": [] }));
+
+    alice.send(15, json!({ "player_name": "alice", "public": false })).await;
+    let code = alice.expect(21).await["code"].as_str().unwrap().to_string();
+    assert_eq!(code.len(), 5);
+
+    let room = json!({ "id": 0, "player_name": "alice", "players_amount": 1, "public": false, "bot": false });
+    assert_eq!(lobby.expect(18).await, room);
+
+    lobby.send(17, Value::Null).await;
+    assert_eq!(lobby.expect(17).await, json!({ "parties": [room] }));
+
+    // Private rooms need their code
+    lobby.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    assert_eq!(lobby.expect_error().await["code"], "wrong_room_code");
+
+    lobby.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": code })).await;
+    assert_eq!(alice.expect(13).await, json!({ "id": 0, "name": "bob" }));
+    assert_eq!(lobby.expect(13).await, json!({ "id": 1, "name": "alice" }));
+}
+
+#[tokio::test]
+async fn win() {
+    let server = Server::start();
+    let (mut alice, mut bob, _lobby) = match_room(&server).await;
+
+    play(&mut alice, &mut bob, &[0, 3, 1, 4, 2]).await;
+    assert_eq!(alice.expect(11).await, json!({ "status": 1 }));
+    assert_eq!(bob.expect(11).await, json!({ "status": 1 }));
+
+    // Both start over, alice first again
+    alice.send(22, Value::Null).await;
+    assert_eq!(alice.expect(13).await, json!({ "id": 0, "name": "bob" }));
+    assert_eq!(bob.expect(13).await, json!({ "id": 1, "name": "alice" }));
+
+    play(&mut alice, &mut bob, &[0, 3, 1, 4, 8, 5]).await;
+    assert_eq!(alice.expect(11).await, json!({ "status": 2 }));
+    assert_eq!(bob.expect(11).await, json!({ "status": 2 }));
+}
+
+#[tokio::test]
+async fn draw() {
+    let server = Server::start();
+    let (mut alice, mut bob, _lobby) = match_room(&server).await;
+
+    play(&mut alice, &mut bob, &[0, 1, 2, 4, 3, 5, 7, 6, 8]).await;
+    assert_eq!(alice.expect(11).await, json!({ "status": 3 }));
+    assert_eq!(bob.expect(11).await, json!({ "status": 3 }));
+}
+
+#[tokio::test]
+async fn rejected_moves() {
+    let server = Server::start();
+    let (mut alice, mut bob, _lobby) = match_room(&server).await;
+
+    bob.send(10, json!({ "position": 0 })).await;
+    assert_eq!(
+        bob.expect_error().await,
+        json!({
+            "code": "not_your_turn",
+            "message": "not your turn",
+            "id": null,
+            "opcode": 10,
+            "request": "mark_position",
+        })
+    );
+
+    alice.send(10, json!({ "position": 9 })).await;
+    assert_eq!(alice.expect_error().await["code"], "invalid_position");
+
+    play(&mut alice, &mut bob, &[4]).await;
+
+    bob.send(10, json!({ "position": 4 })).await;
+    assert_eq!(bob.expect_error().await["code"], "position_taken");
+
+    alice.expect_silence().await;
+}
+
+#[tokio::test]
+async fn rematches_start_with_player_one() {
+    let server = Server::start();
+    let (mut alice, mut bob, _lobby) = match_room(&server).await;
+
+    // Won by alice on an odd move, by bob on an even one, then drawn
+    for (moves, status) in [(&[0, 3, 1, 4, 2][..], 1), (&[0, 3, 1, 4, 8, 5], 2), (&[0, 1, 2, 4, 3, 5, 7, 6, 8], 3)] {
+        play(&mut alice, &mut bob, moves).await;
+        assert_eq!(alice.expect(11).await, json!({ "status": status }));
+        bob.expect(11).await;
+
+        bob.send(22, Value::Null).await;
+        assert_eq!(alice.expect(13).await, json!({ "id": 0, "name": "bob" }));
+        assert_eq!(bob.expect(13).await, json!({ "id": 1, "name": "alice" }));
+
+        // As the joined events tell them
+        bob.send(10, json!({ "position": 0 })).await;
+        assert_eq!(bob.expect_error().await["code"], "not_your_turn");
+    }
+}
+
+#[tokio::test]
+async fn moves_wait_for_an_opponent() {
+    let server = Server::start();
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    bob.expect(18).await;
+
+    alice.send(10, json!({ "position": 0 })).await;
+    assert_eq!(alice.expect_error().await["code"], "no_opponent");
+
+    // Nothing was marked
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    alice.expect(13).await;
+    bob.expect(13).await;
+    play(&mut alice, &mut bob, &[0]).await;
+}
+
+#[tokio::test]
+async fn invalid_requests() {
+    let server = Server::start();
+    let mut client = server.connect().await;
+
+    client.send_text("not json").await;
+    assert_eq!(client.expect_error().await["code"], "invalid_message");
+
+    client.send(99, Value::Null).await;
+    assert_eq!(client.expect_error().await["code"], "invalid_message");
+
+    client.send(12, json!({ "player_name": "bob" })).await;
+    assert_eq!(client.expect_error().await["code"], "invalid_message");
+
+    client.send(12, json!({ "player_name": "bob!", "room_id": 0, "room_code": null })).await;
+    assert_eq!(client.expect_error().await["code"], "invalid_name");
+
+    client.send(12, json!({ "player_name": "bob", "room_id": 3, "room_code": null })).await;
+    assert_eq!(client.expect_error().await["code"], "room_not_found");
+
+    for opcode in [10, 14, 22, 27, 29] {
+        client.send(opcode, json!({ "position": 0 })).await;
+
+        let error = client.expect_error().await;
+        assert_eq!(error["code"], "not_in_room");
+        assert_eq!(error["opcode"], opcode);
+    }
+}
+
+#[tokio::test]
+async fn message_size() {
+    let server = Server::with_env(&[("MESSAGE_MAX_SIZE", "256")]);
+    let mut client = server.connect().await;
+
+    // Names are checked when creating rooms as well
+    client.send(15, json!({ "player_name": "a".repeat(11), "public": true })).await;
+    assert_eq!(client.expect_error().await["code"], "invalid_name");
+
+    client.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": "a".repeat(256) })).await;
+    client.expect_closed(Duration::from_secs(2)).await;
+    assert_eq!(client.close_code, Some(1009));
+
+    // Once inflated
+    let mut client = Client::connect(
+        server.addr,
+        &[("Sec-WebSocket-Extensions", "permessage-deflate")],
+    )
+    .await;
+    client.send_compressed(&" ".repeat(1024)).await;
+    client.expect_closed(Duration::from_secs(2)).await;
+    assert_eq!(client.close_code, Some(1009));
+}
+
+#[tokio::test]
+async fn compression() {
+    // Sizes of two identical room lists, with the window kept and then reset per message
+    let mut sizes = Vec::new();
+
+    for offer in ["permessage-deflate", "permessage-deflate; server_no_context_takeover"] {
+        let server = Server::start();
+        let mut client = Deflated::connect(server.addr, offer).await;
+        assert_eq!(client.header("Sec-WebSocket-Extensions"), Some(offer));
+
+        // Under DEFLATE_MIN_SIZE
+        client.send(17, Value::Null).await;
+        assert_eq!(client.recv().await, (json!({ "opcode": 17, "d": { "parties": [] } }), None));
+
+        let mut players = Vec::new();
+        for name in ["alice", "bob", "carol"] {
+            let mut player = server.connect().await;
+            player.send(15, json!({ "player_name": name, "public": true })).await;
+
+            let (message, size) = client.recv().await;
+            assert_eq!(message["d"]["player_name"], name);
+            assert!(size.is_some());
+
+            players.push(player);
+        }
+
+        let mut list = Vec::new();
+        for _ in 0..2 {
+            client.send(17, Value::Null).await;
+            let (message, size) = client.recv().await;
+            assert_eq!(message["d"]["parties"].as_array().unwrap().len(), 3);
+
+            list.push(size.unwrap());
+        }
+        sizes.push(list);
+    }
+
+    // The second list is mostly a reference to the first, unless the window was reset
+    assert!(sizes[0][1] < sizes[0][0] / 2, "{sizes:?}");
+    assert_eq!(sizes[1][1], sizes[1][0]);
+}
+
+#[tokio::test]
+async fn request_ids() {
+    let server = Server::start();
+    let mut client = server.connect().await;
+
+    client.send_text(r#"{"opcode": 17, "d": null, "id": 7}"#).await;
+    client.expect(17).await;
+    assert_eq!(client.expect(28).await, json!({ "id": 7, "opcode": 17, "request": "list_rooms" }));
+
+    client.send_text(r#"{"opcode": 14, "d": null, "id": 8}"#).await;
+    let error = client.expect_error().await;
+    assert_eq!(error["id"], 8);
+    assert_eq!(error["request"], "leave_room");
+
+    // Still echoed when the request can't be decoded
+    client.send_text(r#"{"opcode": 10, "d": {}, "id": 9}"#).await;
+    assert_eq!(client.expect_error().await["id"], 9);
+}
+
+#[tokio::test]
+async fn ids_in_flight() {
+    let server = Server::start();
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    alice.send_text(r#"{"opcode": 15, "d": {"player_name": "alice", "public": true}, "id": 1}"#).await;
+    assert_eq!(alice.expect(28).await["id"], 1);
+    bob.expect(18).await;
+
+    // Sent together, told apart by their ids rather than the broadcasts they cause
+    bob.send_text(r#"{"opcode": 15, "d": {"player_name": "bob", "public": true}, "id": 2}"#).await;
+    bob.send_text(r#"{"opcode": 12, "d": {"player_name": "bob", "room_id": 0, "room_code": null}, "id": 3}"#).await;
+
+    let mut replies = Vec::new();
+    while replies.len() < 2 {
+        let message = bob.recv().await;
+
+        match message["opcode"].as_u64() {
+            Some(28) => replies.push((message["d"]["id"].clone(), "ack")),
+            Some(1007) => replies.push((message["d"]["id"].clone(), "error")),
+            _ => {}
+        }
+    }
+    assert_eq!(replies, vec![(json!(2), "ack"), (json!(3), "error")]);
+
+    // Acks follow what the request caused
+    alice.send_text(r#"{"opcode": 29, "d": null, "id": 4}"#).await;
+    assert_eq!(alice.expect(29).await["player1"], "alice");
+    assert_eq!(alice.expect(28).await, json!({ "id": 4, "opcode": 29, "request": "get_room_state" }));
+}
+
+#[tokio::test]
+async fn leave() {
+    let server = Server::start();
+    let (mut alice, mut bob, mut lobby) = match_room(&server).await;
+
+    bob.send(14, Value::Null).await;
+    assert_eq!(alice.expect(14).await, Value::Null);
+    assert_eq!(lobby.expect(20).await, json!({ "id": 0 }));
+    // Back in the lobby
+    assert_eq!(bob.expect(20).await, json!({ "id": 0 }));
+
+    bob.send(17, Value::Null).await;
+    assert_eq!(bob.expect(17).await["parties"][0]["players_amount"], 1);
+}
+
+#[tokio::test]
+async fn empty_rooms_close() {
+    // Rooms check their turns every 100ms
+    let server = Server::with_env(&[("ROOM_TURN_TIMEOUT", "0")]);
+    let mut alice = server.connect().await;
+    let mut lobby = server.connect().await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    lobby.expect(18).await;
+
+    alice.send(14, Value::Null).await;
+    assert_eq!(lobby.expect(20).await, json!({ "id": 0 }));
+    assert_eq!(lobby.expect(19).await, json!({ "id": 0 }));
+    // Announced once
+    lobby.expect_silence().await;
+
+    lobby.send(17, Value::Null).await;
+    assert_eq!(lobby.expect(17).await, json!({ "parties": [] }));
+}
+
+#[tokio::test]
+async fn delete() {
+    let server = Server::start();
+    let (mut alice, mut bob, mut lobby) = match_room(&server).await;
+
+    bob.send(16, json!({ "id": 0 })).await;
+    assert_eq!(bob.expect_error().await["code"], "not_room_owner");
+
+    alice.send(16, json!({ "id": 0 })).await;
+    assert_eq!(bob.expect(14).await, Value::Null);
+    assert_eq!(lobby.expect(19).await, json!({ "id": 0 }));
+    assert_eq!(alice.expect(19).await, json!({ "id": 0 }));
+    assert_eq!(bob.expect(19).await, json!({ "id": 0 }));
+}
+
+// Requests sent right after moving between the lobby and a room, before the server is done
+// moving the client, go where the client now is
+#[tokio::test]
+async fn pipelined_requests() {
+    let server = Server::start();
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    alice.send(29, Value::Null).await;
+    assert_eq!(alice.expect(29).await["player1"], "alice");
+    bob.expect(18).await;
+
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    bob.send(29, Value::Null).await;
+    assert_eq!(bob.expect(13).await, json!({ "id": 1, "name": "alice" }));
+    assert_eq!(bob.expect(29).await["player2"], "bob");
+
+    bob.send(14, Value::Null).await;
+    bob.send(29, Value::Null).await;
+
+    // Announced to the lobby bob is back in
+    let error = loop {
+        let message = bob.recv().await;
+
+        if message["opcode"] != 20 {
+            break message;
+        }
+    };
+    assert_eq!(error["opcode"], 1007);
+    assert_eq!(error["d"]["code"], "not_in_room");
+}
+
+#[tokio::test]
+async fn turn_timeout() {
+    let server = Server::with_env(&[("ROOM_TURN_TIMEOUT", "1")]);
+    let (mut alice, mut bob, mut lobby) = match_room(&server).await;
+
+    play(&mut alice, &mut bob, &[4]).await;
+
+    bob.expect_closed(Duration::from_secs(5)).await;
+    assert_eq!(alice.expect(14).await, Value::Null);
+    assert_eq!(lobby.expect(20).await, json!({ "id": 0 }));
+}
+
+#[tokio::test]
+async fn reconnect() {
+    let server = Server::start();
+    let (mut alice, mut bob, mut lobby) = match_room(&server).await;
+
+    play(&mut alice, &mut bob, &[4, 0]).await;
+
+    // Connection lost without a close frame
+    drop(bob);
+    assert_eq!(alice.expect(14).await, Value::Null);
+    assert_eq!(lobby.expect(20).await, json!({ "id": 0 }));
+
+    let mut bob = server.connect().await;
+    bob.send(17, Value::Null).await;
+    assert_eq!(bob.expect(17).await["parties"][0]["players_amount"], 1);
+
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    assert_eq!(alice.expect(13).await, json!({ "id": 0, "name": "bob" }));
+    assert_eq!(bob.expect(13).await, json!({ "id": 1, "name": "alice" }));
+
+    // Match starts over, alice first
+    bob.send(29, Value::Null).await;
+    let state = bob.expect(29).await;
+    assert_eq!(state["board"], json!([null, null, null, null, null, null, null, null, null]));
+    assert_eq!(state["turn"], 1);
+    assert_eq!(state["you"], 2);
+    assert_eq!(state["seq"], 1);
+
+    play(&mut alice, &mut bob, &[4]).await;
+}
+
+#[tokio::test]
+async fn room_state_and_sequence() {
+    let server = Server::start();
+    let (mut alice, mut bob, _lobby) = match_room(&server).await;
+
+    play(&mut alice, &mut bob, &[4, 0, 8]).await;
+
+    alice.send(29, Value::Null).await;
+    let state = alice.expect(29).await;
+    assert_eq!(state["id"], 0);
+    assert_eq!(state["board"], json!(["O", null, null, null, "X", null, null, null, "X"]));
+    assert_eq!(state["turn"], 2);
+    assert_eq!(state["you"], 1);
+    assert_eq!(state["player1"], "alice");
+    assert_eq!(state["player2"], "bob");
+    assert_eq!(state["moves"], json!([4, 0, 8]));
+    assert!(state["turn_time_left"].as_u64().unwrap() <= 30_000);
+    // Opponent joined and one move
+    assert_eq!(state["seq"], 2);
+
+    bob.send(10, json!({ "position": 1 })).await;
+    let message = alice.recv().await;
+    assert_eq!(message["opcode"], 10);
+    assert_eq!(message["seq"], 3);
+}
+
+#[tokio::test]
+async fn book_moves() {
+    let server = Server::start();
+    let (mut alice, mut bob, _lobby) = match_room(&server).await;
+
+    alice.send(27, Value::Null).await;
+    assert_eq!(alice.expect(27).await, json!({ "moves": [] }));
+
+    // Finished games feed the book
+    play(&mut alice, &mut bob, &[0, 3, 1, 4, 2]).await;
+    alice.expect(11).await;
+    bob.expect(11).await;
+
+    alice.send(27, Value::Null).await;
+    assert_eq!(alice.expect(27).await, json!({ "moves": [{ "position": 0, "weight": 1 }] }));
+}
+
+#[tokio::test]
+async fn bots() {
+    let server = Server::with_env(&[("BOT_OFFER_AFTER", "0"), ("ROOM_TURN_TIMEOUT", "2")]);
+    let mut alice = server.connect().await;
+    let mut lobby = server.connect().await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    lobby.expect(18).await;
+
+    assert_eq!(alice.expect(24).await, json!({ "levels": ["easy", "medium", "hard"] }));
+
+    alice.send(25, json!({ "difficulty": "hard" })).await;
+    assert_eq!(alice.expect(13).await, json!({ "id": 0, "name": "BotHard" }));
+    assert_eq!(lobby.expect(26).await["bot"], true);
+
+    alice.send(10, json!({ "position": 4 })).await;
+    let position = alice.expect(10).await["position"].as_u64().unwrap();
+    assert!([0, 2, 6, 8].contains(&position));
+
+    // Both seats are taken
+    alice.send(23, json!({ "engine": "stockfish" })).await;
+    assert_eq!(alice.expect_error().await["code"], "room_full");
+}
+
+#[tokio::test]
+async fn bot_backfill() {
+    let server = Server::with_env(&[("BOT_OFFER_AFTER", "1"), ("ROOM_TURN_TIMEOUT", "1")]);
+    let mut carol = server.connect().await;
+    let mut alice = server.connect().await;
+    let mut lobby = server.connect().await;
+
+    // Private rooms wait for whoever has the code
+    carol.send(15, json!({ "player_name": "carol", "public": false })).await;
+    carol.expect(21).await;
+    alice.expect(18).await;
+    lobby.expect(18).await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    lobby.expect(18).await;
+
+    // Not before BOT_OFFER_AFTER
+    alice.expect_silence().await;
+    alice.expect(24).await;
+    carol.expect_silence().await;
+
+    alice.send(25, json!({ "difficulty": "easy" })).await;
+    assert_eq!(alice.expect(13).await, json!({ "id": 0, "name": "BotEasy" }));
+    let entry = lobby.expect(26).await;
+    assert_eq!(entry["id"], 1);
+    assert_eq!(entry["bot"], true);
+    assert_eq!(entry["players_amount"], 2);
+
+    // The bot makes way for a human until the first move
+    let mut bob = server.connect().await;
+    bob.send(12, json!({ "player_name": "bob", "room_id": 1, "room_code": null })).await;
+    assert_eq!(alice.expect(13).await, json!({ "id": 0, "name": "bob" }));
+    assert_eq!(bob.expect(13).await, json!({ "id": 1, "name": "alice" }));
+    let entry = lobby.expect(26).await;
+    assert_eq!(entry["bot"], false);
+    assert_eq!(entry["players_amount"], 2);
+
+    play(&mut alice, &mut bob, &[4]).await;
+
+    // Once playing, the bot keeps its seat
+    let mut erin = server.connect().await;
+    erin.send(15, json!({ "player_name": "erin", "public": true })).await;
+    erin.expect(24).await;
+    erin.send(25, json!({ "difficulty": "easy" })).await;
+    erin.expect(13).await;
+    erin.send(10, json!({ "position": 4 })).await;
+    erin.expect(10).await;
+
+    lobby.send(12, json!({ "player_name": "dave", "room_id": 2, "room_code": null })).await;
+    let error = loop {
+        let message = lobby.recv().await;
+
+        if message["opcode"] == 1007 {
+            break message;
+        }
+    };
+    assert_eq!(error["d"]["code"], "room_full");
+}
+
+#[tokio::test]
+async fn hard_bot_ignores_the_book() {
+    // Answering the center with an edge, which loses
+    let book = std::env::temp_dir().join(format!("tictactoe-book-{}", rand::random::<u64>()));
+    std::fs::write(&book, "81 1:1000\n").unwrap();
+    let server = Server::with_env(&[("BOT_OFFER_AFTER", "0"), ("BOOK_PATH", book.to_str().unwrap())]);
+    let mut alice = server.connect().await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    alice.expect(24).await;
+    alice.send(25, json!({ "difficulty": "hard" })).await;
+    alice.expect(13).await;
+
+    alice.send(10, json!({ "position": 4 })).await;
+    let position = alice.expect(10).await["position"].as_u64().unwrap();
+    assert!([0, 2, 6, 8].contains(&position));
+
+    let _ = std::fs::remove_file(book);
+}
+
+#[tokio::test]
+async fn engines() {
+    let server = Server::start();
+    let mut alice = server.connect().await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    alice.send(23, json!({ "engine": "stockfish" })).await;
+    assert_eq!(alice.expect_error().await["code"], "engine_not_found");
+
+    // Bots are only offered after waiting
+    alice.send(25, json!({ "difficulty": "easy" })).await;
+    assert_eq!(alice.expect_error().await["code"], "bot_not_offered");
+}
+
+#[tokio::test]
+async fn tagged_messages() {
+    let server = Server::start();
+    let mut client = Client::connect(server.addr, &[("Sec-WebSocket-Protocol", "tictactoe.json")]).await;
+
+    client.send_text(r#"{"type": "create_room", "player_name": "alice", "public": false, "id": 1}"#).await;
+    assert_eq!(client.recv().await["type"], "owner_code");
+    assert_eq!(
+        client.recv().await,
+        json!({ "type": "ack", "id": 1, "opcode": 15, "request": "create_room" })
+    );
+
+    client.send_text(r#"{"type": "mark_position", "position": 0}"#).await;
+    assert_eq!(client.recv().await["code"], "no_opponent");
+}
+
+#[tokio::test]
+async fn mixed_formats() {
+    let server = Server::start();
+    let mut alice = Client::connect(server.addr, &[("Sec-WebSocket-Protocol", "tictactoe.json")]).await;
+    let mut bob = server.connect().await;
+
+    alice.send_text(r#"{"type": "create_room", "player_name": "alice", "public": true}"#).await;
+    assert_eq!(bob.expect(18).await["player_name"], "alice");
+
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    assert_eq!(alice.recv().await, json!({ "type": "opponent_joined", "id": 0, "name": "bob", "seq": 1 }));
+    assert_eq!(bob.expect(13).await, json!({ "id": 1, "name": "alice" }));
+
+    alice.send_text(r#"{"type": "mark_position", "position": 4}"#).await;
+    assert_eq!(bob.expect(10).await, json!({ "position": 4 }));
+    bob.send(10, json!({ "position": 0 })).await;
+    assert_eq!(alice.recv().await, json!({ "type": "mark_position", "position": 0, "seq": 2 }));
+
+    // Names and payloads are checked together
+    alice.send_text(r#"{"type": "mark_square", "position": 1}"#).await;
+    assert_eq!(alice.recv().await["code"], "invalid_message");
+    alice.send_text(r#"{"type": "mark_position", "id": 1}"#).await;
+    assert_eq!(alice.recv().await["code"], "invalid_message");
+    bob.send(10, json!({ "id": 0 })).await;
+    assert_eq!(bob.expect_error().await["code"], "invalid_message");
+}
+
+#[tokio::test]
+async fn room_joined() {
+    let server = Server::start();
+    let mut alice = server.connect().await;
+    let mut tagged = Client::connect(server.addr, &[("Sec-WebSocket-Protocol", "tictactoe.json")]).await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    assert_eq!(tagged.recv().await["type"], "room_created");
+
+    // Legacy frames of opcode 13 always have a name, tagged messages only what they mean
+    let mut bob = server.connect().await;
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    assert_eq!(tagged.recv().await, json!({ "type": "room_joined", "id": 0 }));
+}
+
+#[tokio::test]
+async fn messagepack() {
+    let server = Server::start();
+    let mut client = Client::connect(server.addr, &[("Sec-WebSocket-Protocol", "tictactoe.msgpack")]).await;
+
+    let request = rmp_serde::to_vec_named(&json!({ "type": "list_rooms", "id": 3 })).unwrap();
+    client.send_binary(&request).await;
+
+    let decode = |data: Vec<u8>| rmp_serde::from_slice::<Value>(&data).unwrap();
+    assert_eq!(decode(client.next().await.unwrap()), json!({ "type": "room_list", "parties": [] }));
+    assert_eq!(decode(client.next().await.unwrap())["type"], "ack");
+}
+
+#[tokio::test]
+async fn messagepack_game() {
+    let server = Server::start();
+    let mut alice = Client::connect(server.addr, &[("Sec-WebSocket-Protocol", "tictactoe.msgpack")]).await;
+    let mut bob = server.connect().await;
+
+    let encode = |message: Value| rmp_serde::to_vec_named(&message).unwrap();
+    let decode = |data: Vec<u8>| rmp_serde::from_slice::<Value>(&data).unwrap();
+
+    alice.send_binary(&encode(json!({ "type": "create_room", "player_name": "alice", "public": true, "id": 1 }))).await;
+    assert_eq!(
+        decode(alice.next().await.unwrap()),
+        json!({ "type": "ack", "id": 1, "opcode": 15, "request": "create_room" })
+    );
+    bob.expect(18).await;
+
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    assert_eq!(
+        decode(alice.next().await.unwrap()),
+        json!({ "type": "opponent_joined", "id": 0, "name": "bob", "seq": 1 })
+    );
+    bob.expect(13).await;
+
+    alice.send_binary(&encode(json!({ "type": "mark_position", "position": 4 }))).await;
+    assert_eq!(bob.expect(10).await, json!({ "position": 4 }));
+    bob.send(10, json!({ "position": 0 })).await;
+    assert_eq!(
+        decode(alice.next().await.unwrap()),
+        json!({ "type": "mark_position", "position": 0, "seq": 2 })
+    );
+
+    alice.send_binary(&encode(json!({ "type": "mark_position", "position": 0, "id": 2 }))).await;
+    let error = decode(alice.next().await.unwrap());
+    assert_eq!(error["type"], "error");
+    assert_eq!(error["code"], "position_taken");
+    assert_eq!(error["id"], 2);
+
+    // Not MessagePack
+    alice.send_binary(b"\xc1").await;
+    assert_eq!(decode(alice.next().await.unwrap())["code"], "invalid_message");
+}
@@ -0,0 +1,61 @@
+// Clients sending too much are refused, then disconnected
+
+mod common;
+
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use common::Server;
+
+#[tokio::test]
+async fn costly_requests() {
+    let server = Server::with_env(&[("RATE_LIST_ROOMS", "2"), ("RATE_CREATE_ROOM", "1")]);
+    let mut client = server.connect().await;
+
+    for _ in 0..2 {
+        client.send(17, Value::Null).await;
+        client.expect(17).await;
+    }
+
+    client.send(17, Value::Null).await;
+    let error = client.expect_error().await;
+    assert_eq!(error["code"], "rate_limited");
+    assert_eq!(error["opcode"], 17);
+
+    // Separate budget
+    client.send(15, json!({ "player_name": "alice", "public": true })).await;
+    client.send(14, Value::Null).await;
+    client.expect(20).await;
+    client.expect(19).await;
+
+    client.send(15, json!({ "player_name": "alice", "public": true })).await;
+    assert_eq!(client.expect_error().await["code"], "rate_limited");
+
+    // Moves aren't costly
+    client.send(10, json!({ "position": 0 })).await;
+    assert_eq!(client.expect_error().await["code"], "not_in_room");
+}
+
+#[tokio::test]
+async fn flood_disconnects() {
+    let server = Server::with_env(&[("RATE_MESSAGES", "5"), ("RATE_STRIKES", "3")]);
+    let mut client = server.connect().await;
+
+    for _ in 0..5 {
+        client.send(10, json!({ "position": 0 })).await;
+    }
+
+    for _ in 0..5 {
+        assert_eq!(client.expect_error().await["code"], "not_in_room");
+    }
+
+    for _ in 0..3 {
+        client.send(10, json!({ "position": 0 })).await;
+        assert_eq!(client.expect_error().await["code"], "rate_limited");
+    }
+
+    client.send(10, json!({ "position": 0 })).await;
+    client.expect_closed(Duration::from_secs(2)).await;
+    assert_eq!(client.close_code, Some(1008));
+}
@@ -0,0 +1,49 @@
+// Protocol definitions for clients, only built with the `schema` feature
+
+#![cfg(feature = "schema")]
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use websocket::json::{ClientMessage, ServerMessage, MESSAGE_OPCODES, REQUEST_OPCODES};
+
+#[test]
+fn checked_in_definitions_are_current() {
+    let dir = std::env::temp_dir().join(format!("tictactoe-schema-{}", rand::random::<u64>()));
+    websocket::schema::write(&dir).unwrap();
+
+    let web = Path::new(env!("CARGO_MANIFEST_DIR")).join("../web/src/lib");
+    for file in ["protocol.ts", "protocol.schema.json"] {
+        assert!(
+            std::fs::read_to_string(dir.join(file)).unwrap() == std::fs::read_to_string(web.join(file)).unwrap(),
+            "web/src/lib/{file} is out of date, run `cargo run -p websocket --features schema -- --protocol web/src/lib`"
+        );
+    }
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+// Names of the variants of a tagged enum
+fn kinds(schema: schemars::Schema) -> BTreeSet<String> {
+    schema.as_value()["oneOf"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|variant| variant["properties"]["type"]["const"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn every_message_has_an_opcode() {
+    for (schema, opcodes) in [
+        (schemars::schema_for!(ClientMessage), REQUEST_OPCODES),
+        (schemars::schema_for!(ServerMessage), MESSAGE_OPCODES),
+    ] {
+        let names: BTreeSet<String> = opcodes.iter().map(|(kind, _)| kind.to_string()).collect();
+        assert_eq!(kinds(schema), names);
+    }
+
+    // Only server messages may share one
+    let opcodes: BTreeSet<u32> = REQUEST_OPCODES.iter().map(|(_, opcode)| *opcode).collect();
+    assert_eq!(opcodes.len(), REQUEST_OPCODES.len());
+}
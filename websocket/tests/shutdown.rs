@@ -0,0 +1,81 @@
+// Stopping the server without cutting games short
+
+mod common;
+
+use std::time::Duration;
+
+use serde_json::json;
+
+use common::{Client, Server};
+
+async fn match_room(server: &Server) -> (Client, Client) {
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    alice.send(15, json!({ "player_name": "alice", "public": true })).await;
+    bob.expect(18).await;
+    bob.send(12, json!({ "player_name": "bob", "room_id": 0, "room_code": null })).await;
+    alice.expect(13).await;
+    bob.expect(13).await;
+
+    alice.send(10, json!({ "position": 0 })).await;
+    bob.expect(10).await;
+
+    (alice, bob)
+}
+
+#[tokio::test]
+async fn games_finish() {
+    let mut server = Server::with_env(&[("SHUTDOWN_DEADLINE", "10")]);
+    let (mut alice, mut bob) = match_room(&server).await;
+    let mut lobby = server.connect().await;
+
+    server.terminate();
+
+    assert_eq!(lobby.expect(30).await, json!({ "deadline": 10 }));
+    lobby.expect_closed(Duration::from_secs(2)).await;
+    assert_eq!(lobby.close_code, Some(1001));
+
+    // Nobody else gets in
+    assert!(tokio::net::TcpStream::connect(server.addr).await.is_err());
+
+    alice.expect(30).await;
+    bob.expect(30).await;
+
+    for (ply, position) in [3, 1, 4, 2].into_iter().enumerate() {
+        let (player, opponent) = if ply % 2 == 0 {
+            (&mut bob, &mut alice)
+        } else {
+            (&mut alice, &mut bob)
+        };
+
+        player.send(10, json!({ "position": position })).await;
+        assert_eq!(opponent.expect(10).await, json!({ "position": position }));
+    }
+
+    assert_eq!(alice.expect(11).await, json!({ "status": 1 }));
+    assert_eq!(bob.expect(11).await, json!({ "status": 1 }));
+
+    alice.expect_closed(Duration::from_secs(2)).await;
+    bob.expect_closed(Duration::from_secs(2)).await;
+    assert_eq!(alice.close_code, Some(1001));
+
+    assert!(server.exited(Duration::from_secs(2)).await.success());
+}
+
+#[tokio::test]
+async fn deadline() {
+    let mut server = Server::with_env(&[("SHUTDOWN_DEADLINE", "1")]);
+    let (mut alice, mut bob) = match_room(&server).await;
+
+    server.terminate();
+    assert_eq!(alice.expect(30).await, json!({ "deadline": 1 }));
+    bob.expect(30).await;
+
+    // Too late to finish
+    bob.expect_closed(Duration::from_secs(3)).await;
+    alice.expect_closed(Duration::from_secs(1)).await;
+    assert_eq!(bob.close_code, Some(1001));
+
+    assert!(server.exited(Duration::from_secs(2)).await.success());
+}
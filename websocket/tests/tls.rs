@@ -0,0 +1,237 @@
+// Connections over TLS, with certificates made up for each test
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rcgen::{BasicConstraints, CertificateParams, IsCa, Issuer, KeyPair};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+use ::common::settings::tls::{ClientAuth, TlsSettings};
+use ::common::settings::AppSettings;
+use websocket::server::{App, Handle};
+
+struct Authority {
+    issuer: Issuer<'static, KeyPair>,
+    pem: String,
+}
+
+impl Authority {
+    fn new() -> Self {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        let pem = params.self_signed(&key).unwrap().pem();
+
+        Self { issuer: Issuer::new(params, key), pem }
+    }
+
+    // Certificate and key in PEM
+    fn issue(&self, name: &str) -> (String, String) {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec![name.to_string()]).unwrap().signed_by(&key, &self.issuer).unwrap();
+
+        (cert.pem(), key.serialize_pem())
+    }
+
+    fn roots(&self) -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_slice(self.pem.as_bytes()).unwrap()).unwrap();
+
+        roots
+    }
+}
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tictactoe-tls-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+fn write(dir: &Path, name: &str, pem: &str) -> String {
+    let path = dir.join(name);
+    std::fs::write(&path, pem).unwrap();
+
+    path.display().to_string()
+}
+
+fn set_modified(path: &str, time: std::time::SystemTime) {
+    std::fs::File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+}
+
+async fn start(tls: TlsSettings) -> std::io::Result<Handle> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    App::builder()
+        .settings(AppSettings { tls, ..Default::default() })
+        .listener(listener)
+        .build()
+        .run()
+        .await
+}
+
+fn client(roots: RootCertStore, identity: Option<(String, String)>) -> ClientConfig {
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+
+    match identity {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(
+                vec![CertificateDer::from_pem_slice(cert.as_bytes()).unwrap()],
+                PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap(),
+            )
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    }
+}
+
+// Health probe over TLS, with the certificate the server presented
+async fn healthz(handle: &Handle, config: ClientConfig) -> std::io::Result<(String, CertificateDer<'static>)> {
+    let stream = tokio::net::TcpStream::connect(handle.addrs[0]).await?;
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+    let mut stream = connector.connect(ServerName::try_from("localhost").unwrap(), stream).await?;
+
+    let presented = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+
+    stream.write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n").await?;
+    let mut response = String::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response)).await??;
+
+    Ok((response, presented))
+}
+
+fn der(pem: &str) -> CertificateDer<'static> {
+    CertificateDer::from_pem_slice(pem.as_bytes()).unwrap()
+}
+
+#[tokio::test]
+async fn handshake_and_reload() {
+    let authority = Authority::new();
+    let dir = temp_dir();
+    let (cert, key) = authority.issue("localhost");
+
+    let server = start(TlsSettings {
+        cert: Some(write(&dir, "cert.pem", &cert)),
+        key: Some(write(&dir, "key.pem", &key)),
+        client_auth: ClientAuth::None,
+        client_ca: None,
+        reload_interval: 1,
+    })
+    .await
+    .unwrap();
+
+    let (response, presented) = healthz(&server, client(authority.roots(), None)).await.unwrap();
+    assert!(response.ends_with("\r\n\r\nok"), "{response}");
+    assert_eq!(presented, der(&cert));
+
+    // Renewed in place
+    let (renewed, key) = authority.issue("localhost");
+    write(&dir, "key.pem", &key);
+    write(&dir, "cert.pem", &renewed);
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        if let Ok((_, presented)) = healthz(&server, client(authority.roots(), None)).await {
+            if presented == der(&renewed) {
+                break;
+            }
+        }
+
+        assert!(tokio::time::Instant::now() < deadline, "certificate not reloaded");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn reload_older_files() {
+    let authority = Authority::new();
+    let clients = Authority::new();
+    let dir = temp_dir();
+    let (cert, key) = authority.issue("localhost");
+    let cert_path = write(&dir, "cert.pem", &cert);
+    let key_path = write(&dir, "key.pem", &key);
+
+    // The newest of the files, and it stays that way
+    let ca_path = write(&dir, "clients.pem", &clients.pem);
+    let now = std::time::SystemTime::now();
+    set_modified(&ca_path, now + Duration::from_secs(3600));
+
+    let server = start(TlsSettings {
+        cert: Some(cert_path.clone()),
+        key: Some(key_path.clone()),
+        client_auth: ClientAuth::Optional,
+        client_ca: Some(ca_path),
+        reload_interval: 1,
+    })
+    .await
+    .unwrap();
+
+    let (_, presented) = healthz(&server, client(authority.roots(), None)).await.unwrap();
+    assert_eq!(presented, der(&cert));
+
+    // Copied in with their timestamps kept, from before the ones they replace
+    let (renewed, key) = authority.issue("localhost");
+    write(&dir, "key.pem", &key);
+    write(&dir, "cert.pem", &renewed);
+    set_modified(&key_path, now - Duration::from_secs(3600));
+    set_modified(&cert_path, now - Duration::from_secs(3600));
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        if let Ok((_, presented)) = healthz(&server, client(authority.roots(), None)).await {
+            if presented == der(&renewed) {
+                break;
+            }
+        }
+
+        assert!(tokio::time::Instant::now() < deadline, "certificate not reloaded");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn client_auth() {
+    let authority = Authority::new();
+    let clients = Authority::new();
+    let dir = temp_dir();
+    let (cert, key) = authority.issue("localhost");
+    let cert = write(&dir, "cert.pem", &cert);
+    let key = write(&dir, "key.pem", &key);
+
+    // Only the given roots vouch for clients
+    let settings = TlsSettings {
+        cert: Some(cert),
+        key: Some(key),
+        client_auth: ClientAuth::Required,
+        client_ca: None,
+        reload_interval: 60,
+    };
+    assert!(start(settings.clone()).await.is_err());
+
+    let server = start(TlsSettings {
+        client_ca: Some(write(&dir, "clients.pem", &clients.pem)),
+        ..settings
+    })
+    .await
+    .unwrap();
+
+    let (response, _) = healthz(&server, client(authority.roots(), Some(clients.issue("alice")))).await.unwrap();
+    assert!(response.ends_with("\r\n\r\nok"), "{response}");
+
+    // Without a certificate, or with one the clients' authority didn't issue
+    assert!(healthz(&server, client(authority.roots(), None)).await.is_err());
+    assert!(healthz(&server, client(authority.roots(), Some(authority.issue("mallory")))).await.is_err());
+
+    let _ = std::fs::remove_dir_all(dir);
+}